@@ -0,0 +1,247 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const INDEX_FILE: &str = "index.json";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Serialises read-modify-write cycles on the index within one process.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+/// Names handed out by `new_backup_name` in this process, so two parallel
+/// deploys backing up same-named files in the same second get distinct ones.
+static RESERVED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// One file or directory moved aside by `apply --backup`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupEntry {
+    /// File name inside the backup directory.
+    pub id: String,
+    /// Where the backup came from. `None` for backups made before the index
+    /// existed, which only carry the original basename in their name.
+    pub original: Option<PathBuf>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// `index.json` in the backup directory, mapping each backup to the absolute
+/// path it was taken from so same-named files from different dirs stay apart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupIndex {
+    pub version: u32,
+    pub entries: Vec<BackupEntry>,
+}
+
+impl Default for BackupIndex {
+    fn default() -> Self {
+        Self {
+            version: 1,
+            entries: Vec::new(),
+        }
+    }
+}
+
+pub fn backup_dir(dotfiles_dir: &Path) -> PathBuf {
+    dotfiles_dir.join(".heimdal").join("backups")
+}
+
+impl BackupIndex {
+    /// Load the index for `dir`. Entries whose backup is gone (restored by
+    /// hand, or undone by a rolled-back apply) are dropped; files in `dir`
+    /// with no entry are listed as legacy backups of unknown origin.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(INDEX_FILE);
+        let mut index: Self = if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&content).map_err(|e| {
+                crate::error::HeimdallError::State(format!("{}: {}", path.display(), e))
+            })?
+        } else {
+            Self::default()
+        };
+        index
+            .entries
+            .retain(|e| std::fs::symlink_metadata(dir.join(&e.id)).is_ok());
+
+        if let Ok(read) = std::fs::read_dir(dir) {
+            for entry in read.filter_map(|e| e.ok()) {
+                let id = entry.file_name().to_string_lossy().to_string();
+                if id == INDEX_FILE || id.contains(".tmp.") || index.find(&id).is_some() {
+                    continue;
+                }
+                let created_at = legacy_timestamp(&id)
+                    .or_else(|| {
+                        let modified = entry.metadata().ok()?.modified().ok()?;
+                        Some(DateTime::<Utc>::from(modified))
+                    })
+                    .unwrap_or_else(Utc::now);
+                index.entries.push(BackupEntry {
+                    id,
+                    original: None,
+                    created_at,
+                });
+            }
+        }
+        index.entries.sort_by_key(|e| e.created_at);
+        Ok(index)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(INDEX_FILE);
+        let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+        let indexed = Self {
+            version: self.version,
+            entries: self
+                .entries
+                .iter()
+                .filter(|e| e.original.is_some())
+                .cloned()
+                .collect(),
+        };
+        std::fs::write(&tmp, serde_json::to_string_pretty(&indexed)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    pub fn find(&self, id: &str) -> Option<&BackupEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    /// Newest backup taken from `original`. Legacy backups match on basename.
+    pub fn latest_for(&self, original: &Path) -> Option<&BackupEntry> {
+        let base = original.file_name()?.to_string_lossy().to_string();
+        self.entries
+            .iter()
+            .filter(|e| match &e.original {
+                Some(o) => o == original,
+                None => legacy_basename(&e.id) == Some(base.as_str()),
+            })
+            .max_by_key(|e| e.created_at)
+    }
+}
+
+/// An unused backup file name for `original`: `<basename>.<timestamp>`, with
+/// a `.<n>` suffix when several backups land in the same second.
+pub fn new_backup_name(dir: &Path, original: &Path, now: DateTime<Utc>) -> String {
+    let base = original
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("backup");
+    let stem = format!("{}.{}", base, now.format(TIMESTAMP_FORMAT));
+    let mut reserved = RESERVED.lock().unwrap();
+    let mut name = stem.clone();
+    let mut n = 1;
+    while std::fs::symlink_metadata(dir.join(&name)).is_ok() || reserved.contains(&dir.join(&name))
+    {
+        name = format!("{}.{}", stem, n);
+        n += 1;
+    }
+    reserved.push(dir.join(&name));
+    name
+}
+
+/// Add an entry for a backup that was just written to `dir/id`.
+pub fn record(dir: &Path, id: &str, original: &Path) -> Result<()> {
+    let _guard = INDEX_LOCK.lock().unwrap();
+    let mut index = BackupIndex::load(dir)?;
+    index.entries.retain(|e| e.id != id);
+    index.entries.push(BackupEntry {
+        id: id.to_string(),
+        original: Some(original.to_owned()),
+        created_at: Utc::now(),
+    });
+    index.save(dir)
+}
+
+/// Drop the entry for `id` after its backup was restored or deleted.
+pub fn forget(dir: &Path, id: &str) -> Result<()> {
+    let _guard = INDEX_LOCK.lock().unwrap();
+    let mut index = BackupIndex::load(dir)?;
+    index.entries.retain(|e| e.id != id);
+    index.save(dir)
+}
+
+/// `<basename>.<timestamp>[.<n>]` → `<basename>`, for pre-index backups.
+pub fn legacy_basename(id: &str) -> Option<&str> {
+    let mut parts = id.rsplitn(3, '.');
+    let last = parts.next()?;
+    let (ts, rest) = if last.chars().all(|c| c.is_ascii_digit()) {
+        let ts = parts.next()?;
+        (ts, parts.next()?)
+    } else {
+        let rest = id.strip_suffix(last)?.strip_suffix('.')?;
+        (last, rest)
+    };
+    NaiveDateTime::parse_from_str(ts, TIMESTAMP_FORMAT).ok()?;
+    Some(rest)
+}
+
+fn legacy_timestamp(id: &str) -> Option<DateTime<Utc>> {
+    let base = legacy_basename(id)?;
+    let ts = id[base.len() + 1..].split('.').next()?;
+    Some(
+        NaiveDateTime::parse_from_str(ts, TIMESTAMP_FORMAT)
+            .ok()?
+            .and_utc(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn record_and_latest_for_keep_same_basenames_apart() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        let a = Path::new("/home/u/.config/a/config");
+        let b = Path::new("/home/u/.config/b/config");
+        let now = Utc::now();
+
+        let id_a = new_backup_name(dir, a, now);
+        std::fs::write(dir.join(&id_a), "a").unwrap();
+        record(dir, &id_a, a).unwrap();
+        let id_b = new_backup_name(dir, b, now);
+        std::fs::write(dir.join(&id_b), "b").unwrap();
+        record(dir, &id_b, b).unwrap();
+
+        assert_ne!(id_a, id_b, "same second, same basename must not collide");
+        let index = BackupIndex::load(dir).unwrap();
+        assert_eq!(index.latest_for(a).unwrap().id, id_a);
+        assert_eq!(index.latest_for(b).unwrap().id, id_b);
+    }
+
+    #[test]
+    fn load_drops_missing_and_lists_legacy_backups() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        record(dir, "gone.20240101T000000Z", Path::new("/home/u/gone")).unwrap();
+        std::fs::write(dir.join(".vimrc.20230102T030405Z"), "old").unwrap();
+
+        let index = BackupIndex::load(dir).unwrap();
+
+        assert!(index.find("gone.20240101T000000Z").is_none());
+        let legacy = index.find(".vimrc.20230102T030405Z").unwrap();
+        assert!(legacy.original.is_none());
+        assert_eq!(
+            legacy.created_at.format("%Y-%m-%d").to_string(),
+            "2023-01-02"
+        );
+        assert!(index.latest_for(Path::new("/home/u/.vimrc")).is_some());
+    }
+
+    #[test]
+    fn forget_removes_entry() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("x.20240101T000000Z"), "").unwrap();
+        record(dir, "x.20240101T000000Z", Path::new("/home/u/x")).unwrap();
+        forget(dir, "x.20240101T000000Z").unwrap();
+        let index = BackupIndex::load(dir).unwrap();
+        // Still on disk, so it shows up again as an unindexed backup
+        assert!(index.find("x.20240101T000000Z").unwrap().original.is_none());
+    }
+}
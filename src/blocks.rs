@@ -0,0 +1,289 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::config::BlockEntry;
+use crate::journal::Journal;
+use crate::manifest::ManifestEntry;
+use crate::symlink::UnlinkResult;
+use crate::utils::rewrite_file;
+
+/// Comment leader written before the markers when the entry sets none.
+pub const DEFAULT_COMMENT: &str = "#";
+
+/// What happened when a block was put in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOutcome {
+    Inserted,
+    Updated,
+    Unchanged,
+    /// The block no longer matches what the last apply wrote; left alone.
+    HandEdited,
+}
+
+/// A deployed block compared with what the manifest says was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockState {
+    Intact,
+    Missing,
+    Edited,
+}
+
+/// Byte offsets of one block: `start..end` covers both marker lines,
+/// `body_start..body_end` the lines between them.
+struct Span {
+    start: usize,
+    body_start: usize,
+    body_end: usize,
+    end: usize,
+}
+
+fn begin_marker(id: &str) -> String {
+    format!(">>> heimdal:{} >>>", id)
+}
+
+fn end_marker(id: &str) -> String {
+    format!("<<< heimdal:{} <<<", id)
+}
+
+/// Find block `id` in `text`. Markers are matched whatever comment leader
+/// precedes them, so unlink and drift checks need not know it.
+fn locate(text: &str, id: &str) -> Result<Option<Span>> {
+    let (begin, end) = (begin_marker(id), end_marker(id));
+    let mut offset = 0;
+    let mut open: Option<(usize, usize)> = None;
+    for line in text.split_inclusive('\n') {
+        let next = offset + line.len();
+        let trimmed = line.trim_end();
+        match open {
+            None if trimmed.ends_with(&begin) => open = Some((offset, next)),
+            Some((start, body_start)) if trimmed.ends_with(&end) => {
+                return Ok(Some(Span {
+                    start,
+                    body_start,
+                    body_end: offset,
+                    end: next,
+                }))
+            }
+            _ => {}
+        }
+        offset = next;
+    }
+    match open {
+        Some(_) => Err(crate::error::HeimdallError::Config(format!(
+            "block 'heimdal:{}' has a start marker but no end marker",
+            id
+        ))
+        .into()),
+        None => Ok(None),
+    }
+}
+
+/// The lines between the markers of block `id`, if `text` has it.
+pub fn body<'a>(text: &'a str, id: &str) -> Result<Option<&'a str>> {
+    Ok(locate(text, id)?.map(|s| &text[s.body_start..s.body_end]))
+}
+
+/// `text` with block `id` set to `body`: replaced in place if present,
+/// appended at the end otherwise.
+pub fn upsert(text: &str, id: &str, comment: &str, body: &str) -> Result<String> {
+    let block = format!(
+        "{c} {}\n{}{c} {}\n",
+        begin_marker(id),
+        with_newline(body),
+        end_marker(id),
+        c = comment
+    );
+    Ok(match locate(text, id)? {
+        Some(span) => format!("{}{}{}", &text[..span.start], block, &text[span.end..]),
+        None if text.is_empty() || text.ends_with('\n') => format!("{}{}", text, block),
+        None => format!("{}\n{}", text, block),
+    })
+}
+
+/// `text` without block `id`, or `None` if it has no such block.
+pub fn remove(text: &str, id: &str) -> Result<Option<String>> {
+    Ok(locate(text, id)?.map(|span| format!("{}{}", &text[..span.start], &text[span.end..])))
+}
+
+/// What the manifest stores for a block: blake3 of its body as written,
+/// i.e. with the trailing newline `upsert` adds.
+pub fn hash(body: &str) -> String {
+    blake3::hash(with_newline(body).as_bytes())
+        .to_hex()
+        .to_string()
+}
+
+fn with_newline(body: &str) -> String {
+    if body.is_empty() || body.ends_with('\n') {
+        body.to_string()
+    } else {
+        format!("{}\n", body)
+    }
+}
+
+/// The body block `entry` should hold: its snippet, rendered when the entry
+/// is a template.
+pub fn render_body(
+    entry: &BlockEntry,
+    variables: &HashMap<String, String>,
+    dotfiles_dir: &Path,
+) -> Result<String> {
+    let src = dotfiles_dir.join(&entry.source);
+    let content = std::fs::read_to_string(&src)
+        .map_err(|e| anyhow::anyhow!("Cannot read block '{}': {}", src.display(), e))?;
+    Ok(if entry.template {
+        let vars = crate::templates::build_vars(variables, &entry.vars, "env")?;
+        crate::templates::render_string(&content, &vars)
+    } else {
+        content
+    })
+}
+
+/// Insert or refresh block `id` in `dest`, creating the file if needed.
+/// A block edited since the last apply is only overwritten with `force`.
+#[allow(clippy::too_many_arguments)]
+pub fn deploy(
+    dest: &Path,
+    id: &str,
+    comment: &str,
+    body: &str,
+    last: Option<&ManifestEntry>,
+    force: bool,
+    dry_run: bool,
+    journal: Option<&Journal>,
+) -> Result<BlockOutcome> {
+    let text = read_or_empty(dest)?;
+    let body = with_newline(body);
+    let outcome = match self::body(&text, id)? {
+        Some(current) if current == body => return Ok(BlockOutcome::Unchanged),
+        Some(current)
+            if !force && last.is_some_and(|e| e.hash.as_deref() != Some(&hash(current))) =>
+        {
+            return Ok(BlockOutcome::HandEdited)
+        }
+        Some(_) => BlockOutcome::Updated,
+        None => BlockOutcome::Inserted,
+    };
+    if !dry_run {
+        rewrite_file(dest, &upsert(&text, id, comment, &body)?, journal)?;
+    }
+    Ok(outcome)
+}
+
+/// Compare the block recorded in `entry` with what is in its file now.
+pub fn check(entry: &ManifestEntry) -> Result<BlockState> {
+    let id = entry.block.as_deref().unwrap_or_default();
+    let text = read_or_empty(&entry.target)?;
+    Ok(match body(&text, id)? {
+        None => BlockState::Missing,
+        Some(current) if entry.hash.as_deref() != Some(&hash(current)) => BlockState::Edited,
+        Some(_) => BlockState::Intact,
+    })
+}
+
+/// Take the block recorded in `entry` out of its file, unless it was edited
+/// by hand since apply wrote it.
+pub fn unlink(
+    entry: &ManifestEntry,
+    dry_run: bool,
+    journal: Option<&Journal>,
+) -> Result<UnlinkResult> {
+    let dest = entry.target.clone();
+    let id = entry.block.as_deref().unwrap_or_default();
+    match check(entry)? {
+        BlockState::Missing => return Ok(UnlinkResult::NotLinked { dest }),
+        BlockState::Edited => {
+            return Ok(UnlinkResult::Skipped {
+                dest,
+                reason: format!("block 'heimdal:{}' was edited by hand — left in place", id),
+            })
+        }
+        BlockState::Intact => {}
+    }
+    if !dry_run {
+        let text = read_or_empty(&dest)?;
+        if let Some(stripped) = remove(&text, id)? {
+            rewrite_file(&dest, &stripped, journal)?;
+        }
+    }
+    Ok(UnlinkResult::Removed { dest })
+}
+
+fn read_or_empty(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(anyhow::anyhow!("Cannot read {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASHRC: &str = "export PATH=/corp/bin:$PATH\n";
+
+    #[test]
+    fn upsert_appends_then_replaces_in_place() {
+        let once = upsert(BASHRC, "aliases", "#", "alias ll='ls -l'").unwrap();
+        assert_eq!(
+            once,
+            "export PATH=/corp/bin:$PATH\n\
+             # >>> heimdal:aliases >>>\nalias ll='ls -l'\n# <<< heimdal:aliases <<<\n"
+        );
+        let twice = upsert(
+            &format!("{}tail\n", once),
+            "aliases",
+            "#",
+            "alias la='ls -a'\n",
+        )
+        .unwrap();
+        assert!(twice.contains("alias la='ls -a'\n"));
+        assert!(!twice.contains("alias ll"));
+        assert!(twice.ends_with("<<< heimdal:aliases <<<\ntail\n"));
+    }
+
+    #[test]
+    fn remove_restores_original_text() {
+        let with = upsert(BASHRC, "a", "#", "x\n").unwrap();
+        let with = upsert(&with, "b", "//", "y\n").unwrap();
+        assert_eq!(body(&with, "b").unwrap(), Some("y\n"));
+        let without_a = remove(&with, "a").unwrap().unwrap();
+        assert!(without_a.contains("// >>> heimdal:b >>>"));
+        assert_eq!(remove(&without_a, "b").unwrap().unwrap(), BASHRC);
+        assert!(remove(BASHRC, "a").unwrap().is_none());
+    }
+
+    #[test]
+    fn unterminated_block_is_an_error() {
+        assert!(body("# >>> heimdal:a >>>\nx\n", "a").is_err());
+    }
+
+    #[test]
+    fn deploy_keeps_hand_edits_unless_forced() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let dest = tmp.path().join(".bashrc");
+        std::fs::write(&dest, BASHRC).unwrap();
+        let run = |body: &str, last: Option<&ManifestEntry>, force: bool| {
+            deploy(&dest, "a", "#", body, last, force, false, None).unwrap()
+        };
+        assert_eq!(run("x", None, false), BlockOutcome::Inserted);
+        assert_eq!(run("x", None, false), BlockOutcome::Unchanged);
+
+        let mut manifest = crate::manifest::Manifest::default();
+        manifest.record_block(&dest, "a", Path::new("/repo/a"), Some(hash("x\n")), "p");
+        let last = manifest.get_block(&dest, "a");
+        assert_eq!(run("y", last, false), BlockOutcome::Updated);
+
+        let edited = std::fs::read_to_string(&dest)
+            .unwrap()
+            .replace("y\n", "mine\n");
+        std::fs::write(&dest, edited).unwrap();
+        manifest.record_block(&dest, "a", Path::new("/repo/a"), Some(hash("y\n")), "p");
+        let last = manifest.get_block(&dest, "a");
+        assert_eq!(check(last.unwrap()).unwrap(), BlockState::Edited);
+        assert_eq!(run("z", last, false), BlockOutcome::HandEdited);
+        assert_eq!(run("z", last, true), BlockOutcome::Updated);
+    }
+}
@@ -32,6 +32,17 @@ pub enum Commands {
     Init(InitArgs),
     /// Apply configuration (create symlinks + install packages)
     Apply(ApplyArgs),
+    /// Show what apply would change, as a diff or JSON
+    Plan(PlanArgs),
+    /// Remove managed symlinks (undo apply)
+    Unlink(UnlinkArgs),
+    /// Move an existing file into the dotfiles repo and link it back
+    Add(AddArgs),
+    /// List, inspect, restore and prune files saved by apply --backup
+    Backups {
+        #[command(subcommand)]
+        action: BackupsCmd,
+    },
     /// Show current status
     Status(StatusArgs),
     /// Pull from remote and apply
@@ -66,6 +77,8 @@ pub enum Commands {
     Wizard,
     /// Validate heimdal.yaml configuration
     Validate(ValidateArgs),
+    /// Print the JSON Schema of heimdal.yaml, for editor completion
+    Schema(SchemaArgs),
     /// Rollback to a previous state
     Rollback(RollbackArgs),
     /// State management
@@ -94,7 +107,11 @@ pub enum Commands {
 pub struct InitArgs {
     #[arg(short, long, help = "Git repository URL for your dotfiles")]
     pub repo: String,
-    #[arg(short, long, help = "Profile name (e.g. work, personal)")]
+    #[arg(
+        short,
+        long,
+        help = "Profile name (e.g. work, personal), or 'auto' to pick the one whose match: block fits this machine"
+    )]
     pub profile: String,
     #[arg(long, help = "Local dotfiles path (default: ~/.dotfiles)")]
     pub path: Option<String>,
@@ -104,16 +121,86 @@ pub struct InitArgs {
 
 #[derive(Args, Default)]
 pub struct ApplyArgs {
+    #[arg(
+        short,
+        long,
+        help = "Switch to this profile first ('auto' picks the one whose match: block fits this machine)"
+    )]
+    pub profile: Option<String>,
     #[arg(short = 'n', long, help = "Preview without making changes")]
     pub dry_run: bool,
     #[arg(short, long, help = "Overwrite existing files")]
     pub force: bool,
     #[arg(long, help = "Backup existing files instead of failing")]
     pub backup: bool,
+    #[arg(
+        short,
+        long,
+        help = "Show a diff and ask what to do with each conflicting file"
+    )]
+    pub interactive: bool,
     #[arg(long, help = "Only create symlinks, skip packages")]
     pub dotfiles_only: bool,
     #[arg(long, help = "Only install packages, skip symlinks")]
     pub packages_only: bool,
+    #[arg(
+        short,
+        long,
+        help = "Deploy up to N files at once (default: number of CPUs, at most 8)"
+    )]
+    pub jobs: Option<usize>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["profile", "force", "backup", "interactive", "dotfiles_only", "packages_only"],
+        help = "Carry out a plan saved by 'heimdal plan --out', refusing if anything changed since"
+    )]
+    pub plan: Option<String>,
+}
+
+#[derive(Args, Default)]
+pub struct PlanArgs {
+    #[arg(long, help = "Print the plan as JSON")]
+    pub json: bool,
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Save the plan for 'heimdal apply --plan'"
+    )]
+    pub out: Option<String>,
+    #[arg(short, long, help = "Plan to overwrite existing files")]
+    pub force: bool,
+    #[arg(long, help = "Plan to back up existing files instead of failing")]
+    pub backup: bool,
+    #[arg(long, help = "Only plan symlinks, skip packages")]
+    pub dotfiles_only: bool,
+    #[arg(long, help = "Only plan packages, skip symlinks")]
+    pub packages_only: bool,
+}
+
+#[derive(Args, Default)]
+pub struct UnlinkArgs {
+    #[arg(short = 'n', long, help = "Preview without making changes")]
+    pub dry_run: bool,
+    #[arg(long, help = "Restore the most recent backup of each removed file")]
+    pub restore: bool,
+}
+
+#[derive(Args, Default)]
+pub struct AddArgs {
+    #[arg(help = "File or directory to adopt (e.g. ~/.config/starship.toml)")]
+    pub path: String,
+    #[arg(short, long, help = "Profile to add it to (default: active profile)")]
+    pub profile: Option<String>,
+    #[arg(long, help = "Register as a template instead of a symlinked dotfile")]
+    pub template: bool,
+    #[arg(long, help = "Stage the moved file and heimdal.yaml with git add")]
+    pub git: bool,
+    #[arg(short, long, help = "Add even if the file looks like it holds secrets")]
+    pub force: bool,
+    #[arg(short = 'n', long, help = "Preview without making changes")]
+    pub dry_run: bool,
 }
 
 #[derive(Args)]
@@ -165,6 +252,14 @@ pub struct ImportArgs {
 pub struct ValidateArgs {
     #[arg(short, long, help = "Path to heimdal.yaml")]
     pub config: Option<String>,
+    #[arg(long, help = "Fail on warnings too, e.g. in CI")]
+    pub strict: bool,
+}
+
+#[derive(Args)]
+pub struct SchemaArgs {
+    #[arg(short, long, value_name = "FILE", help = "Write the schema to FILE")]
+    pub out: Option<String>,
 }
 
 #[derive(Args)]
@@ -179,6 +274,7 @@ pub struct RollbackArgs {
 pub enum ProfileCmd {
     /// Switch to a different profile
     Switch {
+        /// Profile name, or 'auto' to pick the one whose match: block fits this machine
         name: String,
         #[arg(long)]
         no_apply: bool,
@@ -196,8 +292,11 @@ pub enum ProfileCmd {
     /// Create a new profile
     Create {
         name: String,
-        #[arg(long)]
-        extends: Option<String>,
+        #[arg(
+            long,
+            help = "Profile to inherit from (repeat to inherit from several)"
+        )]
+        extends: Vec<String>,
     },
     /// Clone an existing profile
     Clone { source: String, dest: String },
@@ -282,7 +381,10 @@ pub enum StateCmd {
         force: bool,
     },
     /// Check for file drift
-    CheckDrift,
+    CheckDrift {
+        #[arg(long, help = "Copy locally edited copies back into the repo")]
+        pull: bool,
+    },
     /// Check for state conflicts
     CheckConflicts,
     /// Show operation history
@@ -292,6 +394,31 @@ pub enum StateCmd {
     },
 }
 
+#[derive(Subcommand)]
+pub enum BackupsCmd {
+    /// List backups with their original location
+    List,
+    /// Show what changed between a backup and the file now at its original path
+    Diff { id: String },
+    /// Move a backup back to its original path
+    Restore {
+        id: String,
+        #[arg(long, help = "Restore here instead (required for legacy backups)")]
+        to: Option<String>,
+        #[arg(short, long, help = "Replace whatever is at the target now")]
+        force: bool,
+    },
+    /// Delete old backups
+    Prune {
+        #[arg(long, help = "Delete backups older than this (e.g. 30d, 12h, 2w)")]
+        older_than: Option<String>,
+        #[arg(long, help = "Keep only the newest N backups of each file")]
+        keep: Option<usize>,
+        #[arg(short = 'n', long, help = "Preview without deleting")]
+        dry_run: bool,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum AutoSyncCmd {
     /// Enable background sync
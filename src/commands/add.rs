@@ -0,0 +1,248 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::cli::AddArgs;
+use crate::config::{
+    load_config, resolve_profile, write_config, DotfileEntry, HeimdalConfig, TemplateEntry,
+};
+use crate::git::GitRepo;
+use crate::ignore_rules::IgnoreRules;
+use crate::manifest::{hash_path, DeployKind, Manifest};
+use crate::state::State;
+use crate::symlink::{link_one, ApplyContext, LinkResult};
+use crate::utils::{
+    copy_recursive, expand_path, home_dir, info, move_path, step, success, warning,
+};
+
+pub fn run(args: AddArgs) -> Result<()> {
+    let state = State::load()?;
+    let config_path = state.dotfiles_path.join("heimdal.yaml");
+    let mut config = load_config(&config_path)?;
+    let profile_name = args
+        .profile
+        .clone()
+        .unwrap_or_else(|| state.active_profile.clone());
+    let resolved = resolve_profile(&config, &profile_name)?;
+
+    let home = home_dir()?;
+    let target = absolute(&expand_path(&args.path))?;
+    if std::fs::symlink_metadata(&target).is_err() {
+        anyhow::bail!("'{}' does not exist", target.display());
+    }
+    if target.is_symlink() {
+        anyhow::bail!(
+            "'{}' is already a symlink — nothing to adopt",
+            target.display()
+        );
+    }
+    if target.starts_with(&state.dotfiles_path) {
+        anyhow::bail!("'{}' is already inside the dotfiles repo", target.display());
+    }
+    let rel = target.strip_prefix(&home).map_err(|_| {
+        anyhow::anyhow!(
+            "'{}' is outside your home directory ({}); only files under home can be added",
+            target.display(),
+            home.display()
+        )
+    })?;
+    if args.template && target.is_dir() {
+        anyhow::bail!("--template needs a file, not a directory");
+    }
+    if !args.force {
+        if let Some(reason) = crate::secrets::looks_sensitive(&target) {
+            anyhow::bail!(
+                "Refusing to add {}: {}. Keep secrets out of git with 'heimdal secret add', \
+                 or pass --force to add it anyway.",
+                target.display(),
+                reason
+            );
+        }
+    }
+
+    // Mirror the home-relative path inside the repo, as the stow walk expects
+    let rel_str = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let repo_rel = if args.template {
+        format!("{}.tmpl", rel_str)
+    } else {
+        rel_str.clone()
+    };
+    let repo_path = state.dotfiles_path.join(&repo_rel);
+    if std::fs::symlink_metadata(&repo_path).is_ok() {
+        anyhow::bail!(
+            "'{}' already exists in the dotfiles repo",
+            repo_path.display()
+        );
+    }
+    let dest_str = format!("~/{}", rel_str);
+
+    let config_changed = if args.template {
+        add_template(&mut config, &profile_name, &repo_rel, &dest_str)?
+    } else if resolved.dotfiles.is_empty() {
+        // Stow-walk profiles link whatever is in the repo; listing one entry
+        // would switch the whole profile over to explicit mappings.
+        let rules = IgnoreRules::for_profile(&state.dotfiles_path, &config, &resolved)?;
+        if rules.is_ignored(Path::new(&repo_rel)) {
+            warning(&format!(
+                "'{}' matches an ignore pattern and will not be linked by 'heimdal apply'",
+                repo_rel
+            ));
+        }
+        false
+    } else {
+        add_dotfile(&mut config, &profile_name, &rel_str, &dest_str)?
+    };
+
+    if args.dry_run {
+        info("Dry-run mode — no changes will be made");
+        let verb = if args.template { "Copy" } else { "Move" };
+        step(&format!(
+            "[preview] {} {} → {}",
+            verb,
+            target.display(),
+            repo_path.display()
+        ));
+        if config_changed {
+            step(&format!(
+                "[preview] Add '{}' to profile '{}'",
+                repo_rel, profile_name
+            ));
+        }
+        return Ok(());
+    }
+
+    let mut manifest = Manifest::load()?;
+    if args.template {
+        // The file at home already is the rendered output; the repo gets the source
+        if let Some(parent) = repo_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        copy_recursive(&target, &repo_path)?;
+        manifest.record(
+            &target,
+            &repo_path,
+            DeployKind::Template,
+            hash_path(&target),
+            &profile_name,
+        );
+        step(&format!(
+            "Copied {} → {}",
+            target.display(),
+            repo_path.display()
+        ));
+    } else {
+        move_path(&target, &repo_path)?;
+        let ctx = ApplyContext {
+            dotfiles_dir: state.dotfiles_path.clone(),
+            home_dir: home.clone(),
+            dry_run: false,
+            force: false,
+            backup: false,
+            relative_links: config.heimdal.relative_links,
+            ignore: IgnoreRules::none(),
+            prompt: None,
+            journal: None,
+            jobs: 1,
+        };
+        match link_one(&repo_path, &target, &ctx) {
+            Ok(LinkResult::Created { .. }) => {}
+            outcome => {
+                // Put the file back rather than leave it stranded in the repo
+                move_path(&repo_path, &target)?;
+                let other = outcome?;
+                anyhow::bail!("Could not link {}: {:?}", target.display(), other);
+            }
+        }
+        manifest.record(
+            &target,
+            &repo_path,
+            DeployKind::Symlink,
+            hash_path(&repo_path),
+            &profile_name,
+        );
+        step(&format!(
+            "Moved {} → {}",
+            target.display(),
+            repo_path.display()
+        ));
+        step(&format!(
+            "Linked {} → {}",
+            target.display(),
+            repo_path.display()
+        ));
+    }
+    manifest.save()?;
+
+    if config_changed {
+        write_config(&config_path, &config)?;
+        step(&format!(
+            "Added '{}' to profile '{}'",
+            repo_rel, profile_name
+        ));
+    }
+
+    if args.git {
+        let paths = [repo_rel.clone(), "heimdal.yaml".to_string()];
+        GitRepo::open(&state.dotfiles_path).add(&paths)?;
+        step("Staged with git add");
+    }
+
+    success(&format!("Added {}", dest_str));
+    Ok(())
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_owned())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+/// Append a dotfile entry to the profile unless one already targets `dest`.
+fn add_dotfile(
+    config: &mut HeimdalConfig,
+    profile: &str,
+    source: &str,
+    dest: &str,
+) -> Result<bool> {
+    let profile = profile_mut(config, profile)?;
+    let exists = profile.dotfiles.iter().any(|e| e.target() == dest);
+    if !exists {
+        profile
+            .dotfiles
+            .push(DotfileEntry::Simple(source.to_string()));
+    }
+    Ok(!exists)
+}
+
+fn add_template(config: &mut HeimdalConfig, profile: &str, src: &str, dest: &str) -> Result<bool> {
+    let profile = profile_mut(config, profile)?;
+    if profile.templates.iter().any(|t| t.dest == dest) {
+        return Ok(false);
+    }
+    profile.templates.push(TemplateEntry {
+        src: src.to_string(),
+        dest: dest.to_string(),
+        vars: Default::default(),
+        when: None,
+        file_mode: None,
+        dir_mode: None,
+    });
+    Ok(true)
+}
+
+fn profile_mut<'a>(
+    config: &'a mut HeimdalConfig,
+    name: &str,
+) -> Result<&'a mut crate::config::Profile> {
+    config.profiles.get_mut(name).ok_or_else(|| {
+        crate::error::HeimdallError::ProfileNotFound {
+            name: name.to_string(),
+        }
+        .into()
+    })
+}
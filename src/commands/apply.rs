@@ -1,44 +1,376 @@
 use anyhow::Result;
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 
+use crate::blocks::BlockOutcome;
 use crate::cli::ApplyArgs;
-use crate::config::{load_config, resolve_profile};
+use crate::config::{load_config, resolve_profile, select_profile, Profile};
+use crate::error::HeimdallError;
 use crate::hooks::run_hooks;
+use crate::ignore_rules::IgnoreRules;
+use crate::journal::{check_interrupted, Journal};
+use crate::manifest::{hash_path, DeployKind, Manifest, ManifestEntry};
+use crate::merge::MergeTarget;
 use crate::packages::install_for_profile;
+use crate::parallel::map_ordered;
+use crate::plan::{Action, Plan};
 use crate::state::State;
-use crate::symlink::{apply_mappings, apply_stow_walk, print_results, ApplyContext, LinkResult};
-use crate::utils::{home_dir, info, success};
+use crate::symlink::{
+    apply_mappings, apply_stow_walk, backup_and_place, deploy_one, expand_link, finish_link, fold,
+    print_results, replace_one, unfold, unlink_one, ApplyContext, ConflictPrompt, LinkResult,
+    UnlinkResult,
+};
+use crate::utils::{home_dir, info, step, success, warning};
 
 pub fn run(args: ApplyArgs) -> Result<()> {
-    let state = State::load()?;
+    if let Some(plan) = &args.plan {
+        return run_plan(Path::new(plan), args.dry_run);
+    }
+
+    let mut state = State::load()?;
     let config_path = state.dotfiles_path.join("heimdal.yaml");
     let config = load_config(&config_path)?;
+    if let Some(name) = &args.profile {
+        let name = select_profile(&config, name)?;
+        if !config.profiles.contains_key(&name) {
+            return Err(HeimdallError::ProfileNotFound { name }.into());
+        }
+        if name != state.active_profile {
+            // A dry run previews the profile without switching to it
+            if !args.dry_run {
+                state.active_profile = name.clone();
+                state.save()?;
+                success(&format!("Switched to profile '{}'", name));
+            }
+            state.active_profile = name;
+        }
+    }
     let profile = resolve_profile(&config, &state.active_profile)?;
 
     if args.dry_run {
         info("Dry-run mode — no changes will be made");
+    } else {
+        crate::journal::recover()?;
+        crate::journal::install_interrupt_handler();
     }
 
+    // Without a terminal to ask on, conflicts fail the run as before
+    let prompt = (args.interactive && !args.dry_run && std::io::stdin().is_terminal())
+        .then(ConflictPrompt::default);
+    // Questions come one at a time and in config order
+    let jobs = match prompt {
+        Some(_) => 1,
+        None => args.jobs.unwrap_or_else(crate::parallel::default_jobs),
+    };
     let ctx = ApplyContext {
         dotfiles_dir: state.dotfiles_path.clone(),
         home_dir: home_dir()?,
         dry_run: args.dry_run,
         force: args.force,
         backup: args.backup,
+        relative_links: config.heimdal.relative_links,
+        ignore: IgnoreRules::for_profile(&state.dotfiles_path, &config, &profile)?,
+        prompt,
+        journal: if args.dry_run {
+            None
+        } else {
+            Some(Journal::begin()?)
+        },
+        jobs,
+    };
+
+    let mut manifest = Manifest::load()?;
+    let outcome = apply_profile(&args, &state, &profile, &ctx, &mut manifest);
+    settle(&ctx, outcome)?;
+
+    if !args.dry_run {
+        manifest.save()?;
+        let mut s = state;
+        s.last_apply = Some(chrono::Utc::now());
+        s.save()?;
+    }
+
+    success("Apply complete");
+    Ok(())
+}
+
+/// Commit the journal if `outcome` succeeded, or roll back what it recorded.
+fn settle(ctx: &ApplyContext, outcome: Result<()>) -> Result<()> {
+    if let Some(journal) = &ctx.journal {
+        if let Err(e) = outcome {
+            if journal.change_count() > 0 {
+                warning(&format!(
+                    "Apply failed — rolling back {} change(s)",
+                    journal.change_count()
+                ));
+            }
+            journal.rollback()?;
+            return Err(e);
+        }
+        journal.commit()?;
+    }
+    outcome
+}
+
+/// Carry out a plan saved by `heimdal plan --out`, exactly as written. The
+/// plan is refused if anything it was based on has changed since.
+fn run_plan(path: &Path, dry_run: bool) -> Result<()> {
+    let plan = Plan::load(path)?;
+    let state = State::load()?;
+    let config_path = state.dotfiles_path.join("heimdal.yaml");
+    let config = load_config(&config_path)?;
+    let profile = resolve_profile(&config, &state.active_profile)?;
+
+    if !dry_run {
+        crate::journal::recover()?;
+        crate::journal::install_interrupt_handler();
+    }
+
+    let changes = plan.changes_since(&state, &config.files(&config_path), &profile);
+    if !changes.is_empty() {
+        for change in &changes {
+            warning(change);
+        }
+        anyhow::bail!(
+            "{} no longer matches this system ({} change(s) since it was made). \
+             Run 'heimdal plan' again.",
+            path.display(),
+            changes.len()
+        );
+    }
+    if dry_run {
+        plan.print();
+        success(&format!("{} is still current", path.display()));
+        return Ok(());
+    }
+
+    // Conflicts were settled when the plan was made; each step says how
+    let ctx = ApplyContext {
+        dotfiles_dir: state.dotfiles_path.clone(),
+        home_dir: home_dir()?,
+        dry_run: false,
+        force: false,
+        backup: false,
+        relative_links: config.heimdal.relative_links,
+        ignore: IgnoreRules::for_profile(&state.dotfiles_path, &config, &profile)?,
+        prompt: None,
+        journal: Some(Journal::begin()?),
+        jobs: 1,
     };
 
+    let mut manifest = Manifest::load()?;
+    let outcome = execute_plan(&plan, &state, &profile, &ctx, &mut manifest);
+    settle(&ctx, outcome)?;
+
+    manifest.save()?;
+    let mut s = state;
+    s.last_apply = Some(chrono::Utc::now());
+    s.save()?;
+
+    success(&format!(
+        "Applied {} step(s) from {}",
+        plan.steps.len(),
+        path.display()
+    ));
+    Ok(())
+}
+
+/// Run each step of a verified plan in order, recording what it deploys.
+fn execute_plan(
+    plan: &Plan,
+    state: &State,
+    profile: &Profile,
+    ctx: &ApplyContext,
+    manifest: &mut Manifest,
+) -> Result<()> {
+    let missing = |what: &str, dest: &Path| {
+        anyhow::anyhow!("The profile has no {} for {}", what, dest.display())
+    };
+    for planned in &plan.steps {
+        check_interrupted()?;
+        let mut results = Vec::new();
+        match &planned.action {
+            Action::CreateLink {
+                src,
+                dest,
+                mode,
+                relative,
+                perms,
+            }
+            | Action::ReplaceFile {
+                src,
+                dest,
+                mode,
+                relative,
+                perms,
+            }
+            | Action::Backup {
+                src,
+                dest,
+                mode,
+                relative,
+                perms,
+            } => {
+                let created = crate::permissions::missing_ancestors(dest);
+                let result = match &planned.action {
+                    Action::CreateLink { .. } => {
+                        deploy_one(src, dest, *mode, *relative, None, ctx)?
+                    }
+                    Action::ReplaceFile { .. } => replace_one(src, dest, *mode, *relative, ctx)?,
+                    _ => backup_and_place(src, dest, *mode, *relative, ctx)?,
+                };
+                if let LinkResult::Conflict { dest, reason } = &result {
+                    anyhow::bail!("Conflict at {}: {}", dest.display(), reason);
+                }
+                finish_link(&result, perms, &created, ctx)?;
+                results.push(result);
+            }
+            Action::Unfold { dest, from } => unfold(dest, from, ctx, &mut results)?,
+            Action::Fold { src, dest } => fold(src, dest, ctx, &mut results)?,
+            Action::Expand { src, dest } => expand_link(src, dest, ctx, &mut results)?,
+            Action::RenderTemplate { src, dest, .. } => {
+                let tmpl = crate::plan::find_template(profile, &state.dotfiles_path, src, dest)
+                    .ok_or_else(|| missing("template", dest))?;
+                let vars = crate::templates::build_vars(&profile.variables, &tmpl.vars, "env")?;
+                crate::templates::render_file(
+                    src,
+                    dest,
+                    &vars,
+                    false,
+                    ctx.journal.as_ref(),
+                    &tmpl.permissions(),
+                )?;
+                step(&format!("Rendered: {}", dest.display()));
+                manifest.record(
+                    dest,
+                    src,
+                    DeployKind::Template,
+                    hash_path(dest),
+                    &state.active_profile,
+                );
+            }
+            Action::WriteBlock { src, dest, id, .. } => {
+                let entry = crate::plan::find_block(profile, dest, id)
+                    .ok_or_else(|| missing("block", dest))?;
+                let body =
+                    crate::blocks::render_body(entry, &profile.variables, &state.dotfiles_path)?;
+                let outcome = crate::blocks::deploy(
+                    dest,
+                    id,
+                    entry
+                        .comment
+                        .as_deref()
+                        .unwrap_or(crate::blocks::DEFAULT_COMMENT),
+                    &body,
+                    manifest.get_block(dest, id),
+                    true,
+                    false,
+                    ctx.journal.as_ref(),
+                )?;
+                let verb = match outcome {
+                    BlockOutcome::Inserted => "Inserted",
+                    _ => "Updated",
+                };
+                step(&format!(
+                    "{} block heimdal:{} in {}",
+                    verb,
+                    id,
+                    dest.display()
+                ));
+                manifest.record_block(
+                    dest,
+                    id,
+                    src,
+                    Some(crate::blocks::hash(&body)),
+                    &state.active_profile,
+                );
+            }
+            Action::MergeKeys { dest, .. } => {
+                let entry =
+                    crate::plan::find_merge(profile, dest).ok_or_else(|| missing("merge", dest))?;
+                let target = MergeTarget::new(entry, &state.dotfiles_path);
+                let changes = target.deploy(false, ctx.journal.as_ref())?;
+                step(&format!(
+                    "Merged {} key(s) into {}",
+                    changes.len(),
+                    dest.display()
+                ));
+                for change in &changes {
+                    println!("      {}", change.describe());
+                }
+                manifest.record(
+                    &target.dest,
+                    &target.source,
+                    DeployKind::Merge,
+                    hash_path(&target.source),
+                    &state.active_profile,
+                );
+            }
+            Action::Prune { dest, .. } => {
+                if let Some(orphan) = manifest.get(dest).cloned() {
+                    prune_one(ctx, manifest, &orphan)?;
+                }
+            }
+            Action::RemoveBlock { dest, id } => {
+                if let Some(orphan) = manifest.get_block(dest, id).cloned() {
+                    prune_block(ctx, manifest, &orphan)?;
+                }
+            }
+            Action::InstallPackage { manager, package } => {
+                crate::packages::install_one(manager, package)?
+            }
+            Action::RunHook {
+                command,
+                fail_on_error,
+                ..
+            } => crate::hooks::run_hook(command, *fail_on_error)?,
+        }
+
+        print_results(&results, false);
+        for (src, dest, mode) in results.iter().filter_map(LinkResult::linked) {
+            manifest.record(
+                dest,
+                src,
+                mode.into(),
+                hash_path(src),
+                &state.active_profile,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Every step of an apply. Any error returned from here makes `run` roll back
+/// the filesystem changes recorded in `ctx.journal`.
+fn apply_profile(
+    args: &ApplyArgs,
+    state: &State,
+    profile: &Profile,
+    ctx: &ApplyContext,
+    manifest: &mut Manifest,
+) -> Result<()> {
+    // Every target this run deploys; anything else in the manifest is orphaned
+    let mut current: HashSet<PathBuf> = HashSet::new();
+
     if !args.packages_only {
-        run_hooks(&profile.hooks.pre_apply, args.dry_run)?;
+        run_hooks(
+            &profile.hooks.pre_apply,
+            &state.active_profile,
+            args.dry_run,
+        )?;
     }
 
     if !args.dotfiles_only {
-        install_for_profile(&profile, args.dry_run)?;
+        check_interrupted()?;
+        install_for_profile(profile, &state.active_profile, args.dry_run)?;
     }
 
     if !args.packages_only {
         let results = if profile.dotfiles.is_empty() {
-            apply_stow_walk(&ctx)?
+            apply_stow_walk(ctx)?
         } else {
-            apply_mappings(&ctx, &profile.dotfiles, &state.active_profile)?
+            apply_mappings(ctx, &profile.dotfiles, &state.active_profile, manifest)?
         };
 
         print_results(&results, args.dry_run);
@@ -49,34 +381,319 @@ pub fn run(args: ApplyArgs) -> Result<()> {
             .collect();
         if !conflicts.is_empty() {
             anyhow::bail!(
-                "{} conflict(s) found. Use --force to overwrite or --backup to save originals.",
+                "{} conflict(s) found. Use --force to overwrite, --backup to save originals, \
+             or --interactive to decide per file.",
                 conflicts.len()
             );
         }
+
+        // Targets left as they were are still managed, so not orphans
+        current.extend(
+            results
+                .iter()
+                .filter_map(LinkResult::managed)
+                .map(Path::to_owned),
+        );
+        for (src, dest, mode) in results.iter().filter_map(LinkResult::linked) {
+            if !args.dry_run {
+                manifest.record(
+                    dest,
+                    src,
+                    mode.into(),
+                    hash_path(src),
+                    &state.active_profile,
+                );
+            }
+        }
     }
 
-    // Render templates
+    // Render templates, several at once; output follows config order
     if !args.packages_only {
-        for tmpl in &profile.templates {
+        let templates = profile
+            .templates
+            .iter()
+            .filter(|t| crate::condition::holds(t.when.as_deref(), &state.active_profile))
+            .collect();
+        let rendered = map_ordered(templates, ctx.jobs, |tmpl| {
             let src = state.dotfiles_path.join(&tmpl.src);
             let dest = crate::utils::expand_path(&tmpl.dest);
-            let vars = crate::templates::build_vars(&tmpl.vars, "env");
-            if let Err(e) = crate::templates::render_file(&src, &dest, &vars, args.dry_run) {
-                crate::utils::warning(&format!("Template '{}' failed: {}", tmpl.src, e));
+            let outcome = check_interrupted().and_then(|()| {
+                let vars = crate::templates::build_vars(&profile.variables, &tmpl.vars, "env")?;
+                crate::templates::render_file(
+                    &src,
+                    &dest,
+                    &vars,
+                    args.dry_run,
+                    ctx.journal.as_ref(),
+                    &tmpl.permissions(),
+                )
+            });
+            (tmpl, src, dest, outcome)
+        });
+        check_interrupted()?;
+        for (tmpl, src, dest, outcome) in rendered {
+            // A failed render keeps its old output, so it is not an orphan either
+            current.insert(dest.clone());
+            match outcome {
+                Ok(text) if args.dry_run => {
+                    println!("--- [dry-run] Would write: {} ---", dest.display());
+                    print!("{}", text);
+                }
+                Ok(_) => manifest.record(
+                    &dest,
+                    &src,
+                    DeployKind::Template,
+                    hash_path(&dest),
+                    &state.active_profile,
+                ),
+                Err(e) => warning(&format!("Template '{}' failed: {}", tmpl.src, e)),
             }
         }
+
+        let current_blocks = apply_blocks(args, state, profile, ctx, manifest)?;
+        apply_merges(args, state, profile, ctx, manifest, &mut current)?;
+        prune_orphans(ctx, manifest, &current)?;
+        prune_orphan_blocks(ctx, manifest, &current_blocks)?;
     }
 
     if !args.packages_only {
-        run_hooks(&profile.hooks.post_apply, args.dry_run)?;
+        check_interrupted()?;
+        run_hooks(
+            &profile.hooks.post_apply,
+            &state.active_profile,
+            args.dry_run,
+        )?;
     }
 
-    if !args.dry_run {
-        let mut s = state;
-        s.last_apply = Some(chrono::Utc::now());
-        s.save()?;
+    Ok(())
+}
+
+/// Insert or refresh every managed block. Returns the (target, id) pairs the
+/// profile contains, so blocks dropped from the config can be removed.
+fn apply_blocks(
+    args: &ApplyArgs,
+    state: &State,
+    profile: &Profile,
+    ctx: &ApplyContext,
+    manifest: &mut Manifest,
+) -> Result<HashSet<(PathBuf, String)>> {
+    let prefix = if args.dry_run { "[preview] " } else { "" };
+    let mut current = HashSet::new();
+    let mut edited = 0;
+    for entry in &profile.blocks {
+        check_interrupted()?;
+        let src = state.dotfiles_path.join(&entry.source);
+        let dest = crate::utils::expand_path(&entry.target);
+        current.insert((dest.clone(), entry.id.clone()));
+        let body = crate::blocks::render_body(entry, &profile.variables, &state.dotfiles_path)?;
+        let comment = entry
+            .comment
+            .as_deref()
+            .unwrap_or(crate::blocks::DEFAULT_COMMENT);
+
+        let outcome = crate::blocks::deploy(
+            &dest,
+            &entry.id,
+            comment,
+            &body,
+            manifest.get_block(&dest, &entry.id),
+            args.force,
+            args.dry_run,
+            ctx.journal.as_ref(),
+        )?;
+        let label = format!("block heimdal:{} in {}", entry.id, dest.display());
+        match outcome {
+            BlockOutcome::Inserted => step(&format!("{}Inserted {}", prefix, label)),
+            BlockOutcome::Updated => step(&format!("{}Updated {}", prefix, label)),
+            BlockOutcome::Unchanged => info(&format!("Up to date: {}", label)),
+            BlockOutcome::HandEdited => {
+                warning(&format!(
+                    "Conflict: {} was edited by hand since last apply",
+                    label
+                ));
+                edited += 1;
+                continue;
+            }
+        }
+        if !args.dry_run {
+            manifest.record_block(
+                &dest,
+                &entry.id,
+                &src,
+                Some(crate::blocks::hash(&body)),
+                &state.active_profile,
+            );
+        }
+    }
+    if edited > 0 {
+        anyhow::bail!(
+            "{} managed block(s) were edited by hand. Copy the edits into the repo snippet, \
+             or use --force to overwrite them.",
+            edited
+        );
     }
+    Ok(current)
+}
 
-    success("Apply complete");
+/// Deep-merge every `merge:` fragment into its target file, listing the
+/// keys that changed. Like templates, one bad file does not stop the run.
+fn apply_merges(
+    args: &ApplyArgs,
+    state: &State,
+    profile: &Profile,
+    ctx: &ApplyContext,
+    manifest: &mut Manifest,
+    current: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    let prefix = if args.dry_run { "[preview] " } else { "" };
+    for entry in &profile.merges {
+        check_interrupted()?;
+        let target = MergeTarget::new(entry, &state.dotfiles_path);
+        current.insert(target.dest.clone());
+        match target.deploy(args.dry_run, ctx.journal.as_ref()) {
+            Ok(changes) if changes.is_empty() => {
+                info(&format!("Up to date: {}", target.dest.display()))
+            }
+            Ok(changes) => {
+                step(&format!(
+                    "{}Merged {} key(s) into {}",
+                    prefix,
+                    changes.len(),
+                    target.dest.display()
+                ));
+                for change in &changes {
+                    println!("      {}", change.describe());
+                }
+            }
+            Err(e) => {
+                warning(&format!(
+                    "Merge into {} failed: {}",
+                    target.dest.display(),
+                    e
+                ));
+                continue;
+            }
+        }
+        if !args.dry_run {
+            manifest.record(
+                &target.dest,
+                &target.source,
+                DeployKind::Merge,
+                hash_path(&target.source),
+                &state.active_profile,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Take out blocks that earlier applies inserted but the profile no longer
+/// lists, unless they were edited by hand since.
+fn prune_orphan_blocks(
+    ctx: &ApplyContext,
+    manifest: &mut Manifest,
+    current: &HashSet<(PathBuf, String)>,
+) -> Result<()> {
+    for orphan in manifest.orphan_blocks(current) {
+        prune_block(ctx, manifest, &orphan)?;
+    }
+    Ok(())
+}
+
+fn prune_block(ctx: &ApplyContext, manifest: &mut Manifest, orphan: &ManifestEntry) -> Result<()> {
+    let prefix = if ctx.dry_run { "[preview] " } else { "" };
+    let id = orphan.block.as_deref().unwrap_or_default();
+    match crate::blocks::unlink(orphan, ctx.dry_run, ctx.journal.as_ref())? {
+        UnlinkResult::Removed { dest } => step(&format!(
+            "{}Pruned orphaned block heimdal:{} from {}",
+            prefix,
+            id,
+            dest.display()
+        )),
+        UnlinkResult::Skipped { dest, .. } => warning(&format!(
+            "Orphaned block heimdal:{} in {} was edited by hand — left in place",
+            id,
+            dest.display()
+        )),
+        UnlinkResult::NotLinked { .. } | UnlinkResult::Restored { .. } => {}
+    }
+    if !ctx.dry_run {
+        manifest.remove_block(&orphan.target, id);
+    }
+    Ok(())
+}
+
+/// Remove what earlier applies deployed but the resolved profile no longer
+/// contains. Links are only removed while they still point into the dotfiles
+/// dir, and copies or template output only while unchanged since written.
+fn prune_orphans(
+    ctx: &ApplyContext,
+    manifest: &mut Manifest,
+    current: &HashSet<PathBuf>,
+) -> Result<()> {
+    for orphan in manifest.orphans(current) {
+        prune_one(ctx, manifest, &orphan)?;
+    }
+    Ok(())
+}
+
+fn prune_one(ctx: &ApplyContext, manifest: &mut Manifest, orphan: &ManifestEntry) -> Result<()> {
+    let prefix = if ctx.dry_run { "[preview] " } else { "" };
+    let target = &orphan.target;
+    match orphan.kind {
+        DeployKind::Symlink => match unlink_one(target, ctx, false)? {
+            UnlinkResult::Removed { .. } => step(&format!(
+                "{}Pruned orphaned link: {}",
+                prefix,
+                target.display()
+            )),
+            UnlinkResult::Skipped { reason, .. } => warning(&format!(
+                "Orphaned {} left in place: {}",
+                target.display(),
+                reason
+            )),
+            UnlinkResult::NotLinked { .. } | UnlinkResult::Restored { .. } => {}
+        },
+        DeployKind::Merge => {
+            let merge = MergeTarget::recorded(orphan, &[]);
+            match merge.remove(ctx.dry_run, ctx.journal.as_ref()) {
+                Ok(removed) if !removed.is_empty() => step(&format!(
+                    "{}Removed {} merged key(s) from {}",
+                    prefix,
+                    removed.len(),
+                    target.display()
+                )),
+                Ok(_) => {}
+                Err(e) => warning(&format!("Merged keys left in {}: {}", target.display(), e)),
+            }
+        }
+        DeployKind::Block => {}
+        DeployKind::Copy | DeployKind::Hardlink | DeployKind::Template => {
+            if !target.exists() {
+                // already gone
+            } else if hash_path(target) == orphan.hash {
+                if !ctx.dry_run {
+                    match &ctx.journal {
+                        Some(journal) => journal.remove(target)?,
+                        None if target.is_dir() => std::fs::remove_dir_all(target)?,
+                        None => std::fs::remove_file(target)?,
+                    }
+                }
+                step(&format!(
+                    "{}Pruned orphaned file: {}",
+                    prefix,
+                    target.display()
+                ));
+            } else {
+                warning(&format!(
+                    "Orphaned {} was modified since it was deployed — left in place",
+                    target.display()
+                ));
+            }
+        }
+    }
+    if !ctx.dry_run {
+        manifest.remove(target);
+    }
     Ok(())
 }
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::backups::{backup_dir, forget, legacy_basename, BackupEntry, BackupIndex};
+use crate::cli::BackupsCmd;
+use crate::state::State;
+use crate::symlink::links_into;
+use crate::utils::{expand_path, info, move_path, step, success};
+
+pub fn run(action: BackupsCmd) -> Result<()> {
+    let state = State::load()?;
+    let dir = backup_dir(&state.dotfiles_path);
+    match action {
+        BackupsCmd::List => list(&dir),
+        BackupsCmd::Diff { id } => diff(&dir, &id),
+        BackupsCmd::Restore { id, to, force } => {
+            restore(&dir, &state.dotfiles_path, &id, to.as_deref(), force)
+        }
+        BackupsCmd::Prune {
+            older_than,
+            keep,
+            dry_run,
+        } => prune(&dir, older_than.as_deref(), keep, dry_run),
+    }
+}
+
+fn list(dir: &Path) -> Result<()> {
+    let index = BackupIndex::load(dir)?;
+    if index.entries.is_empty() {
+        info("No backups.");
+        return Ok(());
+    }
+    println!("{:<40} {:<17} ORIGINAL", "ID", "CREATED");
+    for e in index.entries.iter().rev() {
+        println!(
+            "{:<40} {:<17} {}",
+            e.id,
+            e.created_at.format("%Y-%m-%d %H:%M"),
+            original_label(e)
+        );
+    }
+    Ok(())
+}
+
+fn diff(dir: &Path, id: &str) -> Result<()> {
+    let entry = find(dir, id)?;
+    let Some(original) = &entry.original else {
+        anyhow::bail!(
+            "Backup '{}' predates the backup index, so its original path is unknown",
+            id
+        );
+    };
+    if !original.exists() {
+        info(&format!("{} no longer exists", original.display()));
+        return Ok(());
+    }
+    crate::utils::print_diff(&dir.join(&entry.id), original);
+    Ok(())
+}
+
+fn restore(dir: &Path, dotfiles_dir: &Path, id: &str, to: Option<&str>, force: bool) -> Result<()> {
+    let entry = find(dir, id)?;
+    let target = match (to, &entry.original) {
+        (Some(to), _) => expand_path(to),
+        (None, Some(original)) => original.clone(),
+        (None, None) => anyhow::bail!(
+            "Backup '{}' predates the backup index; pass --to <path> to choose where it goes",
+            id
+        ),
+    };
+
+    if std::fs::symlink_metadata(&target).is_ok() {
+        // A link back into the repo is what apply put there; anything else
+        // is user data and needs --force.
+        if !links_into(&target, dotfiles_dir) && !force {
+            anyhow::bail!(
+                "{} already exists. Use --force to replace it",
+                target.display()
+            );
+        }
+        if target.is_dir() && !target.is_symlink() {
+            std::fs::remove_dir_all(&target)?;
+        } else {
+            std::fs::remove_file(&target)?;
+        }
+    }
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    move_path(&dir.join(&entry.id), &target)?;
+    forget(dir, &entry.id)?;
+    success(&format!("Restored {} → {}", entry.id, target.display()));
+    Ok(())
+}
+
+/// A backup goes if it is older than `older_than`, or if `keep` newer
+/// backups of the same file already exist.
+fn prune(dir: &Path, older_than: Option<&str>, keep: Option<usize>, dry_run: bool) -> Result<()> {
+    if older_than.is_none() && keep.is_none() {
+        anyhow::bail!("Nothing to prune by: pass --older-than and/or --keep");
+    }
+    let cutoff = older_than
+        .map(parse_age)
+        .transpose()?
+        .map(|age| Utc::now() - age);
+    let index = BackupIndex::load(dir)?;
+
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    let mut doomed: Vec<&BackupEntry> = Vec::new();
+    for e in index.entries.iter().rev() {
+        let count = seen.entry(group_key(e)).or_default();
+        *count += 1;
+        let too_many = keep.is_some_and(|k| *count > k);
+        let too_old = cutoff.is_some_and(|c| e.created_at < c);
+        if too_many || too_old {
+            doomed.push(e);
+        }
+    }
+
+    if doomed.is_empty() {
+        info("Nothing to prune.");
+        return Ok(());
+    }
+    let prefix = if dry_run { "[preview] " } else { "" };
+    for e in &doomed {
+        if !dry_run {
+            let path = dir.join(&e.id);
+            if path.is_dir() && !path.is_symlink() {
+                std::fs::remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+            forget(dir, &e.id)?;
+        }
+        step(&format!(
+            "{}Deleted {} ({})",
+            prefix,
+            e.id,
+            original_label(e)
+        ));
+    }
+    success(&format!("Pruned {} backup(s)", doomed.len()));
+    Ok(())
+}
+
+fn find(dir: &Path, id: &str) -> Result<BackupEntry> {
+    BackupIndex::load(dir)?
+        .find(id)
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("No backup '{}'. Run 'heimdal backups list'", id))
+}
+
+fn original_label(e: &BackupEntry) -> String {
+    match &e.original {
+        Some(p) => p.display().to_string(),
+        None => "(unknown — made before the backup index)".to_string(),
+    }
+}
+
+/// Backups of one file: by original path, or by name for legacy backups.
+fn group_key(e: &BackupEntry) -> PathBuf {
+    match &e.original {
+        Some(p) => p.clone(),
+        None => PathBuf::from(legacy_basename(&e.id).unwrap_or(&e.id)),
+    }
+}
+
+/// Parse `30d`, `12h`, `2w` or `45m`.
+fn parse_age(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let unit_at = s.char_indices().last().map_or(0, |(i, _)| i);
+    let (num, unit) = s.split_at(unit_at);
+    let n: i64 = num
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid age '{}'. Use e.g. 30d, 12h, 2w", s))?;
+    match unit {
+        "m" => Ok(Duration::minutes(n)),
+        "h" => Ok(Duration::hours(n)),
+        "d" => Ok(Duration::days(n)),
+        "w" => Ok(Duration::weeks(n)),
+        _ => anyhow::bail!("Invalid age '{}'. Use e.g. 30d, 12h, 2w", s),
+    }
+}
@@ -1,5 +1,5 @@
 use crate::cli::InitArgs;
-use crate::config::{create_minimal_config, load_config};
+use crate::config::{create_minimal_config, load_config, select_profile, AUTO_PROFILE};
 use crate::error::HeimdallError;
 use crate::git::GitRepo;
 use crate::state::State;
@@ -52,15 +52,23 @@ pub fn run(args: InitArgs) -> Result<()> {
                 dotfiles_path.display()
             );
         }
+        if args.profile == AUTO_PROFILE {
+            anyhow::bail!(
+                "No heimdal.yaml found in '{}', so there are no match: blocks to pick a profile by. \
+                 Name the profile instead of 'auto'.",
+                dotfiles_path.display()
+            );
+        }
         step("No heimdal.yaml found, creating minimal config...");
         create_minimal_config(&config_path, &args.profile)?;
     }
 
     // 4. Load and validate config
     let config = load_config(&config_path)?;
+    let profile = select_profile(&config, &args.profile)?;
 
     // 5. Verify the requested profile exists
-    if !config.profiles.contains_key(&args.profile) {
+    if !config.profiles.contains_key(&profile) {
         let mut available: Vec<_> = config.profiles.keys().cloned().collect();
         available.sort();
         eprintln!(
@@ -71,20 +79,16 @@ pub fn run(args: InitArgs) -> Result<()> {
                 available.join(", ")
             }
         );
-        return Err(HeimdallError::ProfileNotFound { name: args.profile }.into());
+        return Err(HeimdallError::ProfileNotFound { name: profile }.into());
     }
 
     // 6. Write state file
-    State::create(
-        args.profile.clone(),
-        dotfiles_path.clone(),
-        args.repo.clone(),
-    )?;
+    State::create(profile.clone(), dotfiles_path.clone(), args.repo.clone())?;
 
     // 7. Print success + next steps
     success(&format!(
         "Initialized heimdal with profile '{}' in {}",
-        args.profile,
+        profile,
         dotfiles_path.display()
     ));
     info("Next steps:");
@@ -1,5 +1,7 @@
+pub mod add;
 pub mod apply;
 pub mod autosync;
+pub mod backups;
 pub mod commit;
 pub mod diff;
 pub mod history;
@@ -7,12 +9,15 @@ pub mod import;
 pub mod init;
 pub mod key;
 pub mod packages;
+pub mod plan;
 pub mod profile;
 pub mod rollback;
+pub mod schema;
 pub mod secret;
 pub mod state;
 pub mod status;
 pub mod sync;
 pub mod template;
+pub mod unlink;
 pub mod validate;
 pub mod wizard;
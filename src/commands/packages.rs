@@ -1,5 +1,5 @@
 use crate::cli::PackagesCmd;
-use crate::config::load_config;
+use crate::config::{load_config, write_config};
 use crate::state::State;
 use crate::utils::{info, success, warning};
 use anyhow::Result;
@@ -50,6 +50,20 @@ fn list() -> Result<()> {
     list_pm!(pkgs.pacman, "pacman");
     list_pm!(pkgs.apk, "apk");
 
+    for group in &pkgs.conditional {
+        let here = match crate::condition::holds(Some(&group.when), &state.active_profile) {
+            true => "applies here",
+            false => "not on this machine",
+        };
+        println!("when {} ({}):", group.when, here);
+        for (manager, names) in group.packages.lists() {
+            if !names.is_empty() {
+                println!("  {}: {}", manager, names.join(", "));
+                any = true;
+            }
+        }
+    }
+
     if !any {
         info("No packages configured for this profile.");
     }
@@ -239,11 +253,3 @@ fn pkg_info(name: &str) -> Result<()> {
     info(&format!("  dnf info {}", name));
     Ok(())
 }
-
-fn write_config(path: &std::path::Path, config: &crate::config::HeimdalConfig) -> Result<()> {
-    let content = serde_yaml_ng::to_string(config)?;
-    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
-    std::fs::write(&tmp, &content)?;
-    std::fs::rename(&tmp, path)?;
-    Ok(())
-}
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::cli::PlanArgs;
+use crate::config::{load_config, resolve_profile};
+use crate::ignore_rules::IgnoreRules;
+use crate::manifest::Manifest;
+use crate::state::State;
+use crate::symlink::ApplyContext;
+use crate::utils::{home_dir, success};
+
+pub fn run(args: PlanArgs) -> Result<()> {
+    let state = State::load()?;
+    let config_path = state.dotfiles_path.join("heimdal.yaml");
+    let config = load_config(&config_path)?;
+    let profile = resolve_profile(&config, &state.active_profile)?;
+
+    // A dry run decides everything apply would, without touching anything
+    let ctx = ApplyContext {
+        dotfiles_dir: state.dotfiles_path.clone(),
+        home_dir: home_dir()?,
+        dry_run: true,
+        force: args.force,
+        backup: args.backup,
+        relative_links: config.heimdal.relative_links,
+        ignore: IgnoreRules::for_profile(&state.dotfiles_path, &config, &profile)?,
+        prompt: None,
+        journal: None,
+        jobs: crate::parallel::default_jobs(),
+    };
+    let manifest = Manifest::load()?;
+    let plan = crate::plan::build(
+        &state,
+        &config.files(&config_path),
+        &profile,
+        &ctx,
+        &manifest,
+        args.dotfiles_only,
+        args.packages_only,
+    )?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+    } else {
+        plan.print();
+    }
+    if let Some(out) = &args.out {
+        plan.save(Path::new(out))?;
+        if args.json {
+            return Ok(());
+        }
+        success(&format!(
+            "Plan saved to {} — run 'heimdal apply --plan {}' to carry it out",
+            out, out
+        ));
+    }
+    Ok(())
+}
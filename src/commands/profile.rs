@@ -1,5 +1,9 @@
 use crate::cli::ProfileCmd;
-use crate::config::{load_config, resolve_profile, DotfileEntry, HeimdalConfig, Profile};
+use crate::condition::{Condition, Host};
+use crate::config::{
+    load_config, matching_profiles, resolve_profile, resolve_with_origins, select_profile,
+    write_config, DotfileEntry, HookEntry, HookStrategy, Origins, Profile, ProfileHooks,
+};
 use crate::error::HeimdallError;
 use crate::state::State;
 use crate::utils::{info, success};
@@ -11,7 +15,7 @@ pub fn run(action: ProfileCmd) -> Result<()> {
         ProfileCmd::Current => current(),
         ProfileCmd::Switch { name, no_apply } => switch(&name, no_apply),
         ProfileCmd::Show { name, resolved } => show(name.as_deref(), resolved),
-        ProfileCmd::Create { name, extends } => create(&name, extends.as_deref()),
+        ProfileCmd::Create { name, extends } => create(&name, &extends),
         ProfileCmd::Clone { source, dest } => clone_profile(&source, &dest),
         ProfileCmd::Diff { profile1, profile2 } => diff_profiles(profile1.as_deref(), &profile2),
     }
@@ -24,12 +28,18 @@ fn list() -> Result<()> {
 
     let mut names: Vec<_> = config.profiles.keys().cloned().collect();
     names.sort();
+    let matching = matching_profiles(&config, &Host::current());
 
     for name in &names {
-        if name == &state.active_profile {
-            println!("* {}", name); // active marker
+        let marker = if name == &state.active_profile {
+            '*' // active marker
         } else {
-            println!("  {}", name);
+            ' '
+        };
+        if matching.contains(name) {
+            println!("{} {}  (matches this machine)", marker, name);
+        } else {
+            println!("{} {}", marker, name);
         }
     }
     Ok(())
@@ -45,6 +55,8 @@ fn switch(name: &str, no_apply: bool) -> Result<()> {
     let mut state = State::load()?;
     let config_path = state.dotfiles_path.join("heimdal.yaml");
     let config = load_config(&config_path)?;
+    let name = select_profile(&config, name)?;
+    let name = name.as_str();
 
     if !config.profiles.contains_key(name) {
         let mut available: Vec<_> = config.profiles.keys().cloned().collect();
@@ -71,6 +83,9 @@ fn switch(name: &str, no_apply: bool) -> Result<()> {
     Ok(())
 }
 
+/// Selects one list of resolved item origins.
+type Pick = fn(&Origins) -> &Vec<String>;
+
 fn show(name: Option<&str>, resolved: bool) -> Result<()> {
     let state = State::load()?;
     let config_path = state.dotfiles_path.join("heimdal.yaml");
@@ -78,29 +93,77 @@ fn show(name: Option<&str>, resolved: bool) -> Result<()> {
 
     let profile_name = name.unwrap_or(&state.active_profile);
 
-    let profile = if resolved {
-        resolve_profile(&config, profile_name)?
+    let (profile, origins) = if resolved {
+        let (profile, origins) = resolve_with_origins(&config, profile_name)?;
+        (profile, Some(origins))
     } else {
-        config
+        let profile = config
             .profiles
             .get(profile_name)
             .ok_or_else(|| HeimdallError::ProfileNotFound {
                 name: profile_name.to_string(),
             })?
-            .clone()
+            .clone();
+        (profile, None)
+    };
+
+    // Resolved items inherited from elsewhere say where from
+    let inherited = |pick: Pick, i: usize| {
+        origins
+            .as_ref()
+            .and_then(|o| pick(o).get(i))
+            .filter(|origin| *origin != profile_name)
+            .cloned()
+    };
+    let from = |pick: Pick, i: usize| {
+        inherited(pick, i)
+            .map(|origin| format!("  (from {})", origin))
+            .unwrap_or_default()
     };
 
     println!("Profile: {}", profile_name);
-    if let Some(ext) = &profile.extends {
-        println!("Extends: {}", ext);
+    if let Some(machine) = &config.profiles[profile_name].machine {
+        println!("Matches: {}", machine);
+    }
+    match &origins {
+        Some(origins) if origins.layers.len() > 1 => {
+            let order: Vec<_> = origins.layers.iter().map(|l| l.to_string()).collect();
+            println!("Resolution order: {}", order.join(" → "));
+        }
+        Some(_) => {}
+        None => {
+            if !profile.extends.is_empty() {
+                println!("Extends: {}", profile.extends.join(", "));
+            }
+            if !profile.include.is_empty() {
+                println!("Includes: {}", profile.include.join(", "));
+            }
+            let exclude = &profile.exclude;
+            for (what, items) in [
+                ("dotfiles", &exclude.dotfiles),
+                ("packages", &exclude.packages),
+                ("templates", &exclude.templates),
+            ] {
+                if !items.is_empty() {
+                    println!("Excludes {}: {}", what, items.join(", "));
+                }
+            }
+        }
     }
 
     if !profile.dotfiles.is_empty() {
         println!("\nDotfiles:");
-        for entry in &profile.dotfiles {
+        for (i, entry) in profile.dotfiles.iter().enumerate() {
+            let origin = from(|o| &o.dotfiles, i);
             match entry {
-                DotfileEntry::Simple(s) => println!("  - {}", s),
-                DotfileEntry::Mapped(m) => println!("  - {} → {}", m.source, m.target),
+                DotfileEntry::Simple(s) => println!("  - {}{}", s, origin),
+                DotfileEntry::Mapped(m) => println!(
+                    "  - {} → {}{}{}",
+                    m.source,
+                    m.target,
+                    when(m.when.as_deref()),
+                    origin
+                ),
             }
         }
     } else {
@@ -108,24 +171,130 @@ fn show(name: Option<&str>, resolved: bool) -> Result<()> {
     }
 
     println!("\nPackages:");
-    let pkgs = &profile.packages;
-    if !pkgs.homebrew.is_empty() {
-        println!("  homebrew: {}", pkgs.homebrew.join(", "));
+    for (manager, names) in profile.packages.lists() {
+        if names.is_empty() {
+            continue;
+        }
+        let names: Vec<_> = names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| {
+                match origins
+                    .as_ref()
+                    .and_then(|o| o.packages.get(manager)?.get(i))
+                    .filter(|origin| *origin != profile_name)
+                {
+                    Some(origin) => format!("{} (from {})", n, origin),
+                    None => n.clone(),
+                }
+            })
+            .collect();
+        println!("  {}: {}", manager, names.join(", "));
+    }
+    for group in &profile.packages.conditional {
+        let lists: Vec<_> = group
+            .packages
+            .lists()
+            .into_iter()
+            .filter(|(_, names)| !names.is_empty())
+            .map(|(manager, names)| format!("{}: {}", manager, names.join(", ")))
+            .collect();
+        println!("  {}{}", lists.join("; "), when(Some(&group.when)));
     }
-    if !pkgs.apt.is_empty() {
-        println!("  apt: {}", pkgs.apt.join(", "));
+
+    if !profile.templates.is_empty() {
+        println!("\nTemplates:");
+        for (i, t) in profile.templates.iter().enumerate() {
+            println!(
+                "  - {} → {}{}{}",
+                t.src,
+                t.dest,
+                when(t.when.as_deref()),
+                from(|o| &o.templates, i)
+            );
+        }
     }
-    if !pkgs.dnf.is_empty() {
-        println!("  dnf: {}", pkgs.dnf.join(", "));
+    if !profile.blocks.is_empty() {
+        println!("\nBlocks:");
+        for (i, b) in profile.blocks.iter().enumerate() {
+            println!(
+                "  - heimdal:{} in {}{}",
+                b.id,
+                b.target,
+                from(|o| &o.blocks, i)
+            );
+        }
     }
-    if !pkgs.pacman.is_empty() {
-        println!("  pacman: {}", pkgs.pacman.join(", "));
+    if !profile.merges.is_empty() {
+        println!("\nMerge:");
+        for (i, m) in profile.merges.iter().enumerate() {
+            println!("  - {} → {}{}", m.source, m.target, from(|o| &o.merges, i));
+        }
+    }
+
+    if !profile.variables.is_empty() {
+        println!("\nVariables:");
+        let mut names: Vec<_> = profile.variables.keys().collect();
+        names.sort();
+        for name in names {
+            let origin = match origins.as_ref().and_then(|o| o.variables.get(name)) {
+                Some(origin) if origin != profile_name => format!("  (from {})", origin),
+                _ => String::new(),
+            };
+            println!("  {}: {}{}", name, profile.variables[name], origin);
+        }
+    }
+
+    let hooks = &profile.hooks;
+    if ProfileHooks::PHASES
+        .iter()
+        .any(|phase| !hooks.phase(phase).is_empty())
+    {
+        println!("\nHooks:");
+        for phase in ProfileHooks::PHASES {
+            // The raw profile says how it combines with what it inherits
+            let strategy = match hooks.merge.as_ref().map(|m| m.strategy(phase)) {
+                None | Some(HookStrategy::Replace) => None,
+                Some(HookStrategy::Append) => Some("after"),
+                Some(HookStrategy::Prepend) => Some("before"),
+            };
+            if let (None, Some(when)) = (&origins, strategy) {
+                println!("  {}: (runs {} inherited hooks)", phase, when);
+            }
+            for (i, hook) in hooks.phase(phase).iter().enumerate() {
+                let hook = match hook {
+                    HookEntry::Simple(command) => command.clone(),
+                    HookEntry::Ref { name } => format!("ref {}", name),
+                    HookEntry::Full {
+                        name,
+                        command,
+                        when: condition,
+                        ..
+                    } => {
+                        let name = name.as_ref().map(|n| format!("[{}] ", n));
+                        let name = name.unwrap_or_default();
+                        format!("{}{}{}", name, command, when(condition.as_deref()))
+                    }
+                };
+                let origin = match origins.as_ref().and_then(|o| o.hooks.phase(phase).get(i)) {
+                    Some(origin) if origin != profile_name => format!("  (from {})", origin),
+                    _ => String::new(),
+                };
+                println!("  {}: {}{}", phase, hook, origin);
+            }
+        }
     }
 
     Ok(())
 }
 
-fn create(name: &str, extends: Option<&str>) -> Result<()> {
+fn when(condition: Option<&Condition>) -> String {
+    condition
+        .map(|c| format!("  [when {}]", c))
+        .unwrap_or_default()
+}
+
+fn create(name: &str, extends: &[String]) -> Result<()> {
     let state = State::load()?;
     let config_path = state.dotfiles_path.join("heimdal.yaml");
     let mut config = load_config(&config_path)?;
@@ -137,7 +306,7 @@ fn create(name: &str, extends: Option<&str>) -> Result<()> {
         );
     }
 
-    if let Some(parent) = extends {
+    for parent in extends {
         if !config.profiles.contains_key(parent) {
             return Err(HeimdallError::ProfileNotFound {
                 name: parent.to_string(),
@@ -147,7 +316,7 @@ fn create(name: &str, extends: Option<&str>) -> Result<()> {
     }
 
     let new_profile = Profile {
-        extends: extends.map(|s| s.to_string()),
+        extends: extends.to_vec(),
         ..Default::default()
     };
 
@@ -192,22 +361,10 @@ fn diff_profiles(profile1: Option<&str>, profile2: &str) -> Result<()> {
 
     println!("Diff: {} vs {}", name1, profile2);
     println!("\nDotfiles in {} but not {}:", name1, profile2);
-    let p1_sources: std::collections::HashSet<String> = p1
-        .dotfiles
-        .iter()
-        .map(|e| match e {
-            DotfileEntry::Simple(s) => s.clone(),
-            DotfileEntry::Mapped(m) => m.source.clone(),
-        })
-        .collect();
-    let p2_sources: std::collections::HashSet<String> = p2
-        .dotfiles
-        .iter()
-        .map(|e| match e {
-            DotfileEntry::Simple(s) => s.clone(),
-            DotfileEntry::Mapped(m) => m.source.clone(),
-        })
-        .collect();
+    let p1_sources: std::collections::HashSet<String> =
+        p1.dotfiles.iter().map(|e| e.source().to_string()).collect();
+    let p2_sources: std::collections::HashSet<String> =
+        p2.dotfiles.iter().map(|e| e.source().to_string()).collect();
 
     for s in p1_sources.difference(&p2_sources) {
         println!("  - {}", s);
@@ -219,12 +376,3 @@ fn diff_profiles(profile1: Option<&str>, profile2: &str) -> Result<()> {
 
     Ok(())
 }
-
-/// Serialize config back to YAML and write atomically.
-fn write_config(path: &std::path::Path, config: &HeimdalConfig) -> Result<()> {
-    let content = serde_yaml_ng::to_string(config)?;
-    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
-    std::fs::write(&tmp, &content)?;
-    std::fs::rename(&tmp, path)?;
-    Ok(())
-}
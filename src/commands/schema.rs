@@ -0,0 +1,15 @@
+use crate::cli::SchemaArgs;
+use crate::utils::{expand_path, success};
+use anyhow::Result;
+
+pub fn run(args: SchemaArgs) -> Result<()> {
+    let schema = serde_json::to_string_pretty(&crate::schema::config_schema())?;
+    match &args.out {
+        Some(out) => {
+            std::fs::write(expand_path(out), format!("{}\n", schema))?;
+            success(&format!("Wrote the heimdal.yaml schema to {}", out));
+        }
+        None => println!("{}", schema),
+    }
+    Ok(())
+}
@@ -1,13 +1,17 @@
+use crate::blocks::BlockState;
 use crate::cli::StateCmd;
+use crate::manifest::{hash_path, DeployKind, Manifest, ManifestEntry};
+use crate::merge::MergeTarget;
 use crate::state::State;
 use crate::utils::info;
 use anyhow::Result;
+use std::io::IsTerminal;
 
 pub fn run(action: StateCmd) -> Result<()> {
     match action {
         StateCmd::LockInfo => lock_info(),
         StateCmd::Unlock { force: _ } => unlock(),
-        StateCmd::CheckDrift => check_drift(),
+        StateCmd::CheckDrift { pull } => check_drift(pull),
         StateCmd::CheckConflicts => check_conflicts(),
         StateCmd::History { limit } => history(limit),
     }
@@ -23,51 +27,170 @@ fn unlock() -> Result<()> {
     Ok(())
 }
 
-fn check_drift() -> Result<()> {
+fn check_drift(pull: bool) -> Result<()> {
     let state = State::load()?;
-    let config_path = state.dotfiles_path.join("heimdal.yaml");
-    let config = crate::config::load_config(&config_path)?;
-    let profile = crate::config::resolve_profile(&config, &state.active_profile)?;
+    let mut manifest = Manifest::load()?;
 
-    let mut drift_count = 0;
-    for entry in &profile.dotfiles {
-        let (src_rel, target_str) = match entry {
-            crate::config::DotfileEntry::Simple(s) => (s.as_str(), format!("~/{}", s)),
-            crate::config::DotfileEntry::Mapped(m) => (m.source.as_str(), m.target.clone()),
-        };
-        let src = state.dotfiles_path.join(src_rel);
-        let dest = crate::utils::expand_path(&target_str);
+    let entries: Vec<ManifestEntry> = manifest
+        .for_profile(&state.active_profile)
+        .cloned()
+        .collect();
+    if entries.is_empty() {
+        info(&format!(
+            "Nothing deployed for profile '{}' yet. Run 'heimdal apply'.",
+            state.active_profile
+        ));
+        return Ok(());
+    }
 
-        if !dest.is_symlink() {
-            crate::utils::warning(&format!(
-                "Not linked: {} (run 'heimdal apply')",
-                dest.display()
-            ));
-            drift_count += 1;
-        } else if let Ok(link_target) = std::fs::read_link(&dest) {
-            if link_target != src {
-                crate::utils::warning(&format!(
-                    "Drift: {} → {} (expected → {})",
-                    dest.display(),
-                    link_target.display(),
-                    src.display()
-                ));
-                drift_count += 1;
+    // Merge settings (format, array strategy) live only in the config
+    let merges = crate::config::load_config(&state.dotfiles_path.join("heimdal.yaml"))
+        .and_then(|c| crate::config::resolve_profile(&c, &state.active_profile))
+        .map(|p| p.merges)
+        .unwrap_or_default();
+    let interactive = std::io::stdin().is_terminal();
+    let mut drift_count = 0;
+    let mut pulled = 0;
+    for entry in &entries {
+        let dest = &entry.target;
+        if let Some(exposed) = crate::permissions::exposure_warning(dest) {
+            crate::utils::warning(&exposed);
+        }
+        match entry.kind {
+            DeployKind::Symlink => {
+                if !dest.is_symlink() {
+                    crate::utils::warning(&format!(
+                        "Not linked: {} (run 'heimdal apply')",
+                        dest.display()
+                    ));
+                    drift_count += 1;
+                } else if let Ok(link_target) = std::fs::read_link(dest) {
+                    if !crate::symlink::points_to(dest, &entry.source) {
+                        crate::utils::warning(&format!(
+                            "Drift: {} → {} (expected → {})",
+                            dest.display(),
+                            link_target.display(),
+                            entry.source.display()
+                        ));
+                        drift_count += 1;
+                    }
+                }
+            }
+            DeployKind::Merge => {
+                let merge = MergeTarget::recorded(entry, &merges);
+                match merge.drift() {
+                    Ok(diverged) => {
+                        for change in &diverged {
+                            crate::utils::warning(&format!(
+                                "Modified: {} key '{}' is {}, heimdal sets {}",
+                                dest.display(),
+                                change.key,
+                                change
+                                    .old
+                                    .as_ref()
+                                    .map_or("unset".to_string(), |v| v.to_string()),
+                                change.new
+                            ));
+                        }
+                        if !diverged.is_empty() {
+                            drift_count += 1;
+                        }
+                    }
+                    Err(e) => {
+                        crate::utils::warning(&format!("Cannot check {}: {}", dest.display(), e));
+                        drift_count += 1;
+                    }
+                }
+            }
+            DeployKind::Block => {
+                let id = entry.block.as_deref().unwrap_or_default();
+                match crate::blocks::check(entry)? {
+                    BlockState::Intact => {}
+                    BlockState::Missing => {
+                        crate::utils::warning(&format!(
+                            "Missing: block heimdal:{} in {} (run 'heimdal apply')",
+                            id,
+                            dest.display()
+                        ));
+                        drift_count += 1;
+                    }
+                    BlockState::Edited => {
+                        crate::utils::warning(&format!(
+                            "Modified: block heimdal:{} in {} (edited by hand; copy the change into {})",
+                            id,
+                            dest.display(),
+                            entry.source.display()
+                        ));
+                        drift_count += 1;
+                    }
+                }
+            }
+            DeployKind::Copy | DeployKind::Hardlink | DeployKind::Template => {
+                if !dest.exists() {
+                    crate::utils::warning(&format!(
+                        "Missing: {} (run 'heimdal apply')",
+                        dest.display()
+                    ));
+                    drift_count += 1;
+                } else if hash_path(dest) != entry.hash {
+                    crate::utils::warning(&format!(
+                        "Modified: {} (edited since it was deployed from {})",
+                        dest.display(),
+                        entry.source.display()
+                    ));
+                    // Template output can't be pulled back: the repo holds the template
+                    let pullable = entry.kind != DeployKind::Template;
+                    if pullable
+                        && (pull
+                            || (interactive
+                                && crate::utils::confirm(&format!(
+                                    "Pull local changes to {} back into the repo?",
+                                    dest.display()
+                                ))))
+                    {
+                        pull_into_repo(entry, &mut manifest)?;
+                        pulled += 1;
+                    } else {
+                        drift_count += 1;
+                    }
+                }
             }
         }
     }
 
+    if pulled > 0 {
+        manifest.save()?;
+        crate::utils::success(&format!(
+            "Pulled {} local change(s) into the repo. Review with 'heimdal diff'.",
+            pulled
+        ));
+    }
+
     if drift_count == 0 {
-        crate::utils::success("No drift detected — all symlinks are correct.");
+        crate::utils::success("No drift detected — all deployed files are correct.");
     } else {
         anyhow::bail!(
-            "{} drifted symlink(s) found. Run 'heimdal apply' to fix.",
+            "{} drifted file(s) found. Run 'heimdal apply' to fix.",
             drift_count
         );
     }
     Ok(())
 }
 
+/// Copy an edited deployed copy back over its repo source.
+fn pull_into_repo(entry: &ManifestEntry, manifest: &mut Manifest) -> Result<()> {
+    let (src, dest) = (&entry.source, &entry.target);
+    if src.is_dir() {
+        std::fs::remove_dir_all(src)?;
+    } else if src.exists() {
+        std::fs::remove_file(src)?;
+    }
+    crate::utils::copy_recursive(dest, src)?;
+    manifest.record(dest, src, entry.kind, hash_path(dest), &entry.profile);
+    crate::utils::step(&format!("Pulled {} → {}", dest.display(), src.display()));
+    Ok(())
+}
+
 fn check_conflicts() -> Result<()> {
     let state = State::load()?;
     let config_path = state.dotfiles_path.join("heimdal.yaml");
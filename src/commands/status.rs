@@ -1,8 +1,13 @@
+use crate::blocks::BlockState;
 use crate::cli::StatusArgs;
+use crate::config::{load_config, resolve_profile};
 use crate::git::{GitRepo, GitStatus};
+use crate::ignore_rules::IgnoreRules;
+use crate::manifest::{DeployKind, Manifest};
 use crate::state::State;
 use crate::utils::{info, success, warning};
 use anyhow::Result;
+use std::path::Path;
 
 pub fn run(_args: StatusArgs) -> Result<()> {
     let state = State::load()?;
@@ -30,9 +35,19 @@ pub fn run(_args: StatusArgs) -> Result<()> {
         info("Last sync:     never");
     }
 
-    // Git status
+    // Git status. Untracked files matching the ignore rules are never
+    // deployed, so they are left out rather than reported as pending work.
+    let rules = ignore_rules(&state).unwrap_or_else(|_| IgnoreRules::none());
     let repo = GitRepo::open(&state.dotfiles_path);
-    match repo.status() {
+    let status = repo.status().map(|files| {
+        files
+            .into_iter()
+            .filter(|f| {
+                !(matches!(f.status, GitStatus::Untracked) && rules.is_ignored(Path::new(&f.path)))
+            })
+            .collect::<Vec<_>>()
+    });
+    match status {
         Ok(files) if files.is_empty() => success("Working tree clean"),
         Ok(files) => {
             warning(&format!("{} uncommitted change(s):", files.len()));
@@ -49,5 +64,42 @@ pub fn run(_args: StatusArgs) -> Result<()> {
         Err(e) => warning(&format!("Could not read git status: {}", e)),
     }
 
+    print_blocks(&state)?;
     Ok(())
 }
+
+/// Managed blocks live in files heimdal does not own, so `git status` says
+/// nothing about them; show whether each is still as apply left it.
+fn print_blocks(state: &State) -> Result<()> {
+    let manifest = Manifest::load()?;
+    let blocks: Vec<_> = manifest
+        .for_profile(&state.active_profile)
+        .filter(|e| e.kind == DeployKind::Block)
+        .collect();
+    if blocks.is_empty() {
+        return Ok(());
+    }
+    info(&format!("Managed blocks: {}", blocks.len()));
+    for entry in blocks {
+        let label = format!(
+            "heimdal:{} in {}",
+            entry.block.as_deref().unwrap_or_default(),
+            entry.target.display()
+        );
+        match crate::blocks::check(entry) {
+            Ok(BlockState::Intact) => info(&format!("  ✓ {}", label)),
+            Ok(BlockState::Missing) => {
+                warning(&format!("  Missing: {} (run 'heimdal apply')", label))
+            }
+            Ok(BlockState::Edited) => warning(&format!("  Edited by hand: {}", label)),
+            Err(e) => warning(&format!("  {}: {}", label, e)),
+        }
+    }
+    Ok(())
+}
+
+fn ignore_rules(state: &State) -> Result<IgnoreRules> {
+    let config = load_config(&state.dotfiles_path.join("heimdal.yaml"))?;
+    let profile = resolve_profile(&config, &state.active_profile)?;
+    IgnoreRules::for_profile(&state.dotfiles_path, &config, &profile)
+}
@@ -17,7 +17,7 @@ pub fn run(args: SyncArgs) -> Result<()> {
     }
 
     // pre_sync hooks
-    run_hooks(&profile.hooks.pre_sync, args.dry_run)?;
+    run_hooks(&profile.hooks.pre_sync, &state.active_profile, args.dry_run)?;
 
     // pull
     let repo = GitRepo::open(&state.dotfiles_path);
@@ -38,7 +38,11 @@ pub fn run(args: SyncArgs) -> Result<()> {
     })?;
 
     // post_sync hooks
-    run_hooks(&profile.hooks.post_sync, args.dry_run)?;
+    run_hooks(
+        &profile.hooks.post_sync,
+        &state.active_profile,
+        args.dry_run,
+    )?;
 
     if !args.dry_run {
         state.last_sync = Some(chrono::Utc::now());
@@ -1,9 +1,10 @@
 use crate::cli::TemplateCmd;
-use crate::config::{load_config, resolve_profile};
+use crate::config::{load_config, resolve_profile, resolve_with_origins};
 use crate::state::State;
-use crate::templates::{build_vars, render_string};
+use crate::templates::{build_vars, local_vars, render_string};
 use crate::utils::info;
 use anyhow::Result;
+use std::collections::HashMap;
 
 pub fn run(action: TemplateCmd) -> Result<()> {
     match action {
@@ -21,7 +22,13 @@ fn list() -> Result<()> {
         info("No templates configured for this profile.");
     } else {
         for t in &profile.templates {
-            println!("  {} → {}", t.src, t.dest);
+            match &t.when {
+                Some(when) if !crate::condition::holds(Some(when), &state.active_profile) => {
+                    println!("  {} → {}  (skipped here: when {})", t.src, t.dest, when)
+                }
+                Some(when) => println!("  {} → {}  (when {})", t.src, t.dest, when),
+                None => println!("  {} → {}", t.src, t.dest),
+            }
         }
     }
     Ok(())
@@ -46,7 +53,7 @@ fn preview(src_name: &str, profile_name: Option<&str>) -> Result<()> {
         })?;
 
     let src_path = state.dotfiles_path.join(&entry.src);
-    let vars = build_vars(&entry.vars, "env");
+    let vars = build_vars(&profile.variables, &entry.vars, "env")?;
     let content = std::fs::read_to_string(&src_path)?;
     print!("{}", render_string(&content, &vars));
     Ok(())
@@ -56,25 +63,61 @@ fn variables(profile_name: Option<&str>) -> Result<()> {
     let state = State::load()?;
     let config = load_config(&state.dotfiles_path.join("heimdal.yaml"))?;
     let prof_name = profile_name.unwrap_or(&state.active_profile);
-    let profile = resolve_profile(&config, prof_name)?;
+    let (profile, origins) = resolve_with_origins(&config, prof_name)?;
+    let local = local_vars()?;
+    let local_path = crate::utils::local_variables_path()?;
+
+    println!(
+        "Later sources win: system variables, variables:, a template's vars, then {}",
+        local_path.display()
+    );
+    let sorted = |vars: &HashMap<String, String>| {
+        let mut pairs: Vec<_> = vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        pairs.sort();
+        pairs
+    };
+    let overridden = |k: &str, by_profile: bool| {
+        if local.contains_key(k) {
+            "  (overridden locally)"
+        } else if by_profile && profile.variables.contains_key(k) {
+            "  (overridden by variables:)"
+        } else {
+            ""
+        }
+    };
+
+    println!("\nSystem variables:");
+    for (k, v) in sorted(&crate::templates::system_vars()) {
+        println!("  {}: {}{}", k, v, overridden(&k, true));
+    }
 
-    let sys = crate::templates::system_vars();
-    println!("System variables:");
-    let mut keys: Vec<_> = sys.keys().collect();
-    keys.sort();
-    for k in keys {
-        println!("  {}: {}", k, sys[k]);
+    if !profile.variables.is_empty() {
+        println!("\nVariables:");
+        for (k, v) in sorted(&profile.variables) {
+            let from = origins
+                .variables
+                .get(&k)
+                .filter(|origin| *origin != prof_name)
+                .map(|origin| format!("  (from {})", origin))
+                .unwrap_or_default();
+            println!("  {}: {}{}{}", k, v, from, overridden(&k, false));
+        }
     }
 
     for tmpl in &profile.templates {
         if !tmpl.vars.is_empty() {
             println!("\nVars for {}:", tmpl.src);
-            let mut pairs: Vec<_> = tmpl.vars.iter().collect();
-            pairs.sort_by_key(|(k, _)| (*k).clone());
-            for (k, v) in pairs {
-                println!("  {}: {}", k, v);
+            for (k, v) in sorted(&tmpl.vars) {
+                println!("  {}: {}{}", k, v, overridden(&k, false));
             }
         }
     }
+
+    if !local.is_empty() {
+        println!("\nLocal overrides ({}):", local_path.display());
+        for (k, v) in sorted(&local) {
+            println!("  {}: {}", k, v);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::cli::UnlinkArgs;
+use crate::config::{load_config, resolve_profile};
+use crate::ignore_rules::IgnoreRules;
+use crate::manifest::{DeployKind, Manifest};
+use crate::merge::MergeTarget;
+use crate::state::State;
+use crate::symlink::{
+    print_unlink_results, unlink_copy, unlink_mappings, unlink_one, unlink_stow_walk, ApplyContext,
+    UnlinkResult,
+};
+use crate::utils::{home_dir, info, step, success, warning};
+
+pub fn run(args: UnlinkArgs) -> Result<()> {
+    let state = State::load()?;
+    let config_path = state.dotfiles_path.join("heimdal.yaml");
+    let config = load_config(&config_path)?;
+    let profile = resolve_profile(&config, &state.active_profile)?;
+
+    if args.dry_run {
+        info("Dry-run mode — no changes will be made");
+    }
+
+    let ctx = ApplyContext {
+        dotfiles_dir: state.dotfiles_path.clone(),
+        home_dir: home_dir()?,
+        dry_run: args.dry_run,
+        force: false,
+        backup: false,
+        relative_links: config.heimdal.relative_links,
+        // Links made before a path was ignored are still ours to remove
+        ignore: IgnoreRules::none(),
+        prompt: None,
+        journal: None,
+        jobs: 1,
+    };
+
+    // The manifest knows exactly what apply deployed; the config-derived walk
+    // then catches links made before the manifest existed.
+    let mut manifest = Manifest::load()?;
+    let entries: Vec<_> = manifest
+        .for_profile(&state.active_profile)
+        .filter(|e| {
+            !matches!(
+                e.kind,
+                DeployKind::Template | DeployKind::Block | DeployKind::Merge
+            )
+        })
+        .cloned()
+        .collect();
+    let blocks: Vec<_> = manifest
+        .for_profile(&state.active_profile)
+        .filter(|e| e.kind == DeployKind::Block)
+        .cloned()
+        .collect();
+    let merges: Vec<_> = manifest
+        .for_profile(&state.active_profile)
+        .filter(|e| e.kind == DeployKind::Merge)
+        .cloned()
+        .collect();
+    let recorded: Vec<PathBuf> = entries.iter().map(|e| e.target.clone()).collect();
+    let mut results = Vec::new();
+    for entry in &entries {
+        results.push(match entry.kind {
+            DeployKind::Symlink => unlink_one(&entry.target, &ctx, args.restore)?,
+            _ => unlink_copy(entry, &ctx, args.restore)?,
+        });
+    }
+
+    let derived = if profile.dotfiles.is_empty() {
+        unlink_stow_walk(&ctx, args.restore)?
+    } else {
+        unlink_mappings(&ctx, &profile.dotfiles, args.restore)?
+    };
+    results.extend(
+        derived
+            .into_iter()
+            .filter(|r| !recorded.iter().any(|t| t == r.dest())),
+    );
+
+    print_unlink_results(&results, args.dry_run);
+
+    // Blocks come out of files we do not own; the files themselves stay
+    let prefix = if args.dry_run { "[preview] " } else { "" };
+    for entry in &blocks {
+        let id = entry.block.as_deref().unwrap_or_default();
+        let result = crate::blocks::unlink(entry, args.dry_run, None)?;
+        match &result {
+            UnlinkResult::Removed { dest } => step(&format!(
+                "{}Removed block heimdal:{} from {}",
+                prefix,
+                id,
+                dest.display()
+            )),
+            UnlinkResult::Skipped { dest, reason } => {
+                info(&format!("Skipped {}: {}", dest.display(), reason))
+            }
+            UnlinkResult::NotLinked { .. } | UnlinkResult::Restored { .. } => {}
+        }
+        if !args.dry_run {
+            manifest.remove_block(&entry.target, id);
+        }
+    }
+
+    // Merged keys come out the same way; the app's own keys stay
+    for entry in &merges {
+        let merge = MergeTarget::recorded(entry, &profile.merges);
+        match merge.remove(args.dry_run, None) {
+            Ok(removed) if !removed.is_empty() => step(&format!(
+                "{}Removed {} merged key(s) from {}",
+                prefix,
+                removed.len(),
+                entry.target.display()
+            )),
+            Ok(_) => {}
+            Err(e) => warning(&format!(
+                "Merged keys left in {}: {}",
+                entry.target.display(),
+                e
+            )),
+        }
+        if !args.dry_run {
+            manifest.remove(&entry.target);
+        }
+    }
+
+    if !args.dry_run {
+        for r in &results {
+            if matches!(
+                r,
+                UnlinkResult::Removed { .. } | UnlinkResult::Restored { .. }
+            ) || recorded.iter().any(|t| t == r.dest())
+            {
+                manifest.remove(r.dest());
+            }
+        }
+        manifest.save()?;
+    }
+    success("Unlink complete");
+    Ok(())
+}
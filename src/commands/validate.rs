@@ -1,7 +1,9 @@
 use crate::cli::ValidateArgs;
 use crate::config::{load_config, validate_config};
-use crate::utils::{success, warning};
+use crate::utils::success;
+use crate::validation::{check, Finding, Severity};
 use anyhow::Result;
+use colored::Colorize;
 
 pub fn run(args: ValidateArgs) -> Result<()> {
     // Determine config path
@@ -21,27 +23,82 @@ pub fn run(args: ValidateArgs) -> Result<()> {
     };
 
     let config = load_config(&config_path)?;
-    let errors = validate_config(&config);
+    let mut findings: Vec<Finding> = validate_config(&config)
+        .into_iter()
+        .map(|message| Finding {
+            severity: Severity::Error,
+            scope: String::new(),
+            message,
+        })
+        .collect();
+    if let Some(dotfiles_dir) = config_path.parent() {
+        findings.extend(
+            crate::permissions::config_warnings(&config, dotfiles_dir)
+                .into_iter()
+                .map(|message| Finding {
+                    severity: Severity::Warning,
+                    scope: String::new(),
+                    message,
+                }),
+        );
+        findings.extend(check(&config, dotfiles_dir));
+    }
+    report(&findings);
 
-    if errors.is_empty() {
-        let profile_count = config.profiles.len();
-        success(&format!(
-            "Config is valid ({} profile{})",
-            profile_count,
-            if profile_count == 1 { "" } else { "s" }
-        ));
-        // List profile names
-        let mut names: Vec<_> = config.profiles.keys().collect();
-        names.sort();
-        for name in names {
-            crate::utils::info(&format!("  \u{2022} {}", name));
+    let errors = count(&findings, Severity::Error);
+    let warnings = count(&findings, Severity::Warning);
+    if errors > 0 || (args.strict && warnings > 0) {
+        if args.strict && warnings > 0 {
+            anyhow::bail!(
+                "{} validation error(s) and {} warning(s) found (--strict)",
+                errors,
+                warnings
+            );
         }
-    } else {
-        for err in &errors {
-            warning(err);
+        anyhow::bail!("{} validation error(s) found", errors);
+    }
+
+    let profile_count = config.profiles.len();
+    success(&format!(
+        "Config is valid ({} profile{}{})",
+        profile_count,
+        if profile_count == 1 { "" } else { "s" },
+        match warnings {
+            0 => String::new(),
+            1 => ", 1 warning".to_string(),
+            n => format!(", {} warnings", n),
         }
-        anyhow::bail!("{} validation error(s) found", errors.len());
+    ));
+    // List profile names
+    let mut names: Vec<_> = config.profiles.keys().collect();
+    names.sort();
+    for name in names {
+        crate::utils::info(&format!("  \u{2022} {}", name));
     }
 
     Ok(())
 }
+
+/// Print findings grouped under their scope, those without one first.
+fn report(findings: &[Finding]) {
+    let mut scope = "";
+    for finding in findings {
+        if finding.scope != scope {
+            scope = &finding.scope;
+            eprintln!("{}:", scope);
+        }
+        let marker = match finding.severity {
+            Severity::Error => "✗".red().bold(),
+            Severity::Warning => "⚠".yellow(),
+        };
+        let indent = if scope.is_empty() { "" } else { "  " };
+        eprintln!(
+            "{}{} {}: {}",
+            indent, marker, finding.severity, finding.message
+        );
+    }
+}
+
+fn count(findings: &[Finding], severity: Severity) -> usize {
+    findings.iter().filter(|f| f.severity == severity).count()
+}
@@ -0,0 +1,323 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A `when:` condition. Every field that is set must hold; lists match if
+/// any of their values does. Dotfile mappings, templates, hooks and package
+/// groups all take one, and a profile's `match:` block is one too.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Condition {
+    /// `macos` or `linux`.
+    #[serde(default)]
+    pub os: Vec<String>,
+    /// Linux distribution, as detected from `/etc/os-release`: `debian`,
+    /// `ubuntu`, `fedora`, `rhel`, `centos`, `arch`, `manjaro`, `alpine` or
+    /// `other`. Derived distros match their parent: `debian` holds on Ubuntu,
+    /// `arch` on Manjaro, `rhel` on CentOS.
+    #[serde(default)]
+    pub distro: Vec<String>,
+    /// CPU architecture, `x86_64` (or `amd64`) and `aarch64` (or `arm64`).
+    #[serde(default)]
+    pub arch: Vec<String>,
+    /// Glob the hostname must match.
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Login name.
+    #[serde(default)]
+    pub user: Vec<String>,
+    /// Active profile.
+    #[serde(default)]
+    pub profile: Vec<String>,
+    /// Variables that must be set, each to a value matching a glob. `"*"`
+    /// only asks for the variable to be set.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Programs that must be on `PATH`.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Paths that must exist. `~` and variables are expanded.
+    #[serde(default)]
+    pub file: Vec<String>,
+    /// Conditions of which at least one must hold.
+    #[serde(default)]
+    pub any: Vec<Condition>,
+    /// Conditions that must all hold.
+    #[serde(default)]
+    pub all: Vec<Condition>,
+    /// A condition that must not hold.
+    #[serde(default)]
+    pub not: Option<Box<Condition>>,
+}
+
+/// What a condition is checked against, besides the environment and
+/// filesystem, which are looked at directly.
+#[derive(Debug, Clone, Copy)]
+pub struct Host<'a> {
+    pub os: &'a str,
+    pub distro: Option<&'a str>,
+    pub arch: &'a str,
+    pub hostname: &'a str,
+    pub user: &'a str,
+}
+
+impl Host<'static> {
+    pub fn current() -> Self {
+        Host {
+            os: crate::utils::os_name(),
+            distro: crate::utils::distro_name(),
+            arch: std::env::consts::ARCH,
+            hostname: crate::utils::hostname(),
+            user: crate::utils::username(),
+        }
+    }
+}
+
+/// Whether `condition` holds on this machine with `profile` active. No
+/// condition always holds.
+pub fn holds(condition: Option<&Condition>, profile: &str) -> bool {
+    condition.map_or(true, |c| c.matches(&Host::current(), profile))
+}
+
+impl Condition {
+    pub fn matches(&self, host: &Host, profile: &str) -> bool {
+        let one_of = |values: &[String], actual: &str| {
+            values.is_empty() || values.iter().any(|v| v == actual)
+        };
+        one_of(&self.os, host.os)
+            && (self.distro.is_empty()
+                || host.distro.is_some_and(|d| {
+                    std::iter::successors(Some(d), |d| parent_distro(d))
+                        .any(|d| self.distro.iter().any(|v| v == d))
+                }))
+            && (self.arch.is_empty() || self.arch.iter().any(|a| arch_name(a) == host.arch))
+            && one_of(&self.profile, profile)
+            && one_of(&self.user, host.user)
+            && self
+                .hostname
+                .as_ref()
+                .map_or(true, |p| hostname_matches(p, host.hostname))
+            && self.env.iter().all(|(name, pattern)| {
+                std::env::var(name).is_ok_and(|value| glob_matches(pattern, &value))
+            })
+            && self.command.iter().all(|c| on_path(c))
+            && self
+                .file
+                .iter()
+                .all(|f| crate::utils::expand_path(f).exists())
+            && (self.any.is_empty() || self.any.iter().any(|c| c.matches(host, profile)))
+            && self.all.iter().all(|c| c.matches(host, profile))
+            && self
+                .not
+                .as_ref()
+                .map_or(true, |c| !c.matches(host, profile))
+    }
+}
+
+/// The values `os:` can match.
+pub const OS_NAMES: [&str; 2] = ["macos", "linux"];
+
+impl Condition {
+    /// Mistakes that make this condition, or one nested in it, never hold
+    /// the way it reads: an unknown `os` or a hostname glob that does not
+    /// compile.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .os
+            .iter()
+            .filter(|os| !OS_NAMES.contains(&os.as_str()))
+            .map(|os| format!("os '{}' is never matched (expected macos or linux)", os))
+            .collect();
+        if let Some(pattern) = &self.hostname {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                problems.push(format!("hostname glob '{}' is invalid: {}", pattern, e));
+            }
+        }
+        for nested in self.any.iter().chain(&self.all).chain(self.not.as_deref()) {
+            problems.extend(nested.problems());
+        }
+        problems
+    }
+}
+
+/// The distro `distro` derives from, as its `/etc/os-release` `ID_LIKE`
+/// names it.
+fn parent_distro(distro: &str) -> Option<&'static str> {
+    match distro {
+        "ubuntu" => Some("debian"),
+        "centos" => Some("rhel"),
+        "rhel" => Some("fedora"),
+        "manjaro" => Some("arch"),
+        _ => None,
+    }
+}
+
+/// `amd64`/`x64` and `arm64` as Rust names them.
+fn arch_name(arch: &str) -> &str {
+    match arch {
+        "amd64" | "x64" => "x86_64",
+        "arm64" => "aarch64",
+        other => other,
+    }
+}
+
+fn hostname_matches(pattern: &str, hostname: &str) -> bool {
+    match glob::Pattern::new(pattern) {
+        Ok(pat) => pat.matches(hostname),
+        Err(_) => {
+            crate::utils::warning(&format!(
+                "Invalid hostname glob pattern '{}' — treating condition as not met",
+                pattern
+            ));
+            false
+        }
+    }
+}
+
+/// A value that isn't a valid glob has to match exactly.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    glob::Pattern::new(pattern).map_or(pattern == value, |p| p.matches(value))
+}
+
+fn on_path(command: &str) -> bool {
+    if command.contains('/') {
+        return crate::utils::expand_path(command).is_file();
+    }
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| is_executable(&dir.join(command)))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &std::path::Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+/// A compact one-line form, e.g. `os=linux distro=ubuntu|debian not(command=nvim)`.
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        for (name, values) in [
+            ("os", &self.os),
+            ("distro", &self.distro),
+            ("arch", &self.arch),
+            ("user", &self.user),
+            ("profile", &self.profile),
+            ("command", &self.command),
+            ("file", &self.file),
+        ] {
+            if !values.is_empty() {
+                parts.push(format!("{}={}", name, values.join("|")));
+            }
+        }
+        if let Some(pattern) = &self.hostname {
+            parts.push(format!("hostname={}", pattern));
+        }
+        for (name, pattern) in &self.env {
+            parts.push(format!("${}={}", name, pattern));
+        }
+        let group = |conds: &[Condition]| {
+            conds
+                .iter()
+                .map(|c| format!("({})", c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if !self.any.is_empty() {
+            parts.push(format!("any[{}]", group(&self.any)));
+        }
+        if !self.all.is_empty() {
+            parts.push(format!("all[{}]", group(&self.all)));
+        }
+        if let Some(not) = &self.not {
+            parts.push(format!("not({})", not));
+        }
+        if parts.is_empty() {
+            return write!(f, "always");
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: Host = Host {
+        os: "linux",
+        distro: Some("ubuntu"),
+        arch: "aarch64",
+        hostname: "work-laptop",
+        user: "alice",
+    };
+
+    fn parse(yaml: &str) -> Condition {
+        serde_yaml_ng::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn fields_are_anded() {
+        assert!(
+            parse("{os: [linux], distro: [debian, ubuntu], arch: [arm64]}").matches(&HOST, "x")
+        );
+        assert!(!parse("{os: [linux], distro: [fedora]}").matches(&HOST, "x"));
+        assert!(!parse("{arch: [amd64]}").matches(&HOST, "x"));
+        let mac = Host {
+            os: "macos",
+            distro: None,
+            ..HOST
+        };
+        assert!(!parse("{distro: [ubuntu]}").matches(&mac, "x"));
+    }
+
+    #[test]
+    fn derived_distros_match_their_parent() {
+        assert!(parse("{distro: [debian]}").matches(&HOST, "x"));
+        let centos = Host {
+            distro: Some("centos"),
+            ..HOST
+        };
+        assert!(parse("{distro: [rhel]}").matches(&centos, "x"));
+        assert!(parse("{distro: [fedora]}").matches(&centos, "x"));
+        let debian = Host {
+            distro: Some("debian"),
+            ..HOST
+        };
+        assert!(!parse("{distro: [ubuntu]}").matches(&debian, "x"));
+    }
+
+    #[test]
+    fn combinators() {
+        let c = parse("{any: [{hostname: 'home-*'}, {profile: [work]}], not: {os: [macos]}}");
+        assert!(c.matches(&HOST, "work"));
+        assert!(!c.matches(&HOST, "personal"));
+        let c = parse("{all: [{os: [linux]}, {not: {arch: [x86_64]}}]}");
+        assert!(c.matches(&HOST, "x"));
+        assert_eq!(
+            parse("{any: [{os: [macos]}, {distro: [arch]}], not: {command: [nvim]}}").to_string(),
+            "any[(os=macos), (distro=arch)] not(command=nvim)"
+        );
+    }
+
+    #[test]
+    fn env_command_and_file() {
+        std::env::set_var("HEIMDAL_CONDITION_TEST", "work-42");
+        assert!(parse("{env: {HEIMDAL_CONDITION_TEST: 'work-*'}}").matches(&HOST, "x"));
+        assert!(!parse("{env: {HEIMDAL_CONDITION_TEST: home}}").matches(&HOST, "x"));
+        assert!(!parse("{env: {HEIMDAL_CONDITION_UNSET: '*'}}").matches(&HOST, "x"));
+
+        assert!(parse("{command: [sh]}").matches(&HOST, "x"));
+        assert!(!parse("{command: [no-such-command-heimdal]}").matches(&HOST, "x"));
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let file = format!("{{file: ['{}']}}", tmp.path().display());
+        assert!(parse(&file).matches(&HOST, "x"));
+        assert!(!parse("{file: [/no/such/heimdal/file]}").matches(&HOST, "x"));
+    }
+}
@@ -1,27 +1,61 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+use crate::condition::{Condition, Host};
+use crate::merge::MergeFormat;
+use crate::permissions::{FileMode, Permissions};
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct HeimdalConfig {
+    /// Settings for heimdal itself.
     pub heimdal: HeimdalMeta,
+    /// Other YAML files, or globs of them, relative to this one. Their
+    /// profiles, mixins, packages and ignore patterns are merged in.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Profiles by name. One is active on each machine.
+    #[serde(default)]
     pub profiles: HashMap<String, Profile>,
+    /// Profile fragments that profiles pull in with `include:`. A mixin
+    /// cannot be activated on its own.
+    #[serde(default)]
+    pub mixins: HashMap<String, Profile>,
+    /// Packages every profile installs.
     #[serde(default)]
     pub packages: PackageMap,
+    /// Gitignore-style patterns for files never to deploy.
     #[serde(default)]
     pub ignore: Vec<String>,
+    /// Template variables every profile sees. Profiles can override them.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, String>,
+    /// Shell history syncing.
     #[serde(default)]
     pub history: Option<HistoryConfig>,
+    /// Which file each part of the config was loaded from.
+    #[serde(skip)]
+    pub sources: Sources,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct HeimdalMeta {
+    /// Config format version.
     pub version: String,
+    /// Git URL of the dotfiles repo.
     #[serde(default)]
     pub repo: Option<String>,
+    /// Write symlink targets relative to the link, so links survive the
+    /// dotfiles repo or home directory moving together.
+    #[serde(default)]
+    pub relative_links: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct HistoryConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -35,10 +69,24 @@ fn max_age_days_default() -> u32 {
     90
 }
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Profile {
+    /// Parent profiles, one name or a list. Each is applied in order, later
+    /// ones layered over earlier ones, and a shared ancestor only once.
+    #[serde(default, with = "one_or_many")]
+    #[schemars(with = "one_or_many::OneOrMany")]
+    pub extends: Vec<String>,
+    /// Mixins, layered after the parents and before the profile itself.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Inherited entries this profile drops.
     #[serde(default)]
-    pub extends: Option<String>,
+    pub exclude: Exclude,
+    /// The machines `--profile auto` picks this profile on. Not inherited.
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub machine: Option<Condition>,
+    /// Files to deploy. Empty deploys every top-level file.
     #[serde(default)]
     pub dotfiles: Vec<DotfileEntry>,
     #[serde(default)]
@@ -47,37 +95,148 @@ pub struct Profile {
     pub hooks: ProfileHooks,
     #[serde(default)]
     pub templates: Vec<TemplateEntry>,
+    /// Marked sections kept up to date in files heimdal does not own.
+    #[serde(default)]
+    pub blocks: Vec<BlockEntry>,
+    /// Keys deep-merged into JSON, YAML or TOML files.
+    #[serde(default, rename = "merge")]
+    pub merges: Vec<MergeEntry>,
     #[serde(default)]
     pub ignore: Vec<String>,
+    /// Template variables, overriding inherited ones of the same name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, String>,
+}
+
+/// `extends: base` and `extends: [base, work]` both deserialize to a list,
+/// and a single parent is written back as a plain name.
+mod one_or_many {
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize, JsonSchema)]
+    #[serde(untagged)]
+    pub enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    pub fn serialize<S: Serializer>(names: &[String], s: S) -> Result<S::Ok, S::Error> {
+        match names {
+            [] => s.serialize_none(),
+            [one] => one.serialize(s),
+            many => many.serialize(s),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+        Ok(match Option::<OneOrMany>::deserialize(d)? {
+            None => vec![],
+            Some(OneOrMany::One(name)) => vec![name],
+            Some(OneOrMany::Many(names)) => names,
+        })
+    }
+}
+
+/// Inherited entries a profile drops before adding its own. A dotfile or
+/// template the profile lists itself needs no exclude: it replaces the
+/// inherited one with the same target.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Exclude {
+    /// By source or target.
+    #[serde(default)]
+    pub dotfiles: Vec<String>,
+    /// By name, from every package manager's list.
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// By src or dest.
+    #[serde(default)]
+    pub templates: Vec<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(untagged)]
 pub enum DotfileEntry {
     Simple(String),
     Mapped(DotfileMapping),
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+impl DotfileEntry {
+    pub fn source(&self) -> &str {
+        match self {
+            DotfileEntry::Simple(s) => s,
+            DotfileEntry::Mapped(m) => &m.source,
+        }
+    }
+
+    /// Where the entry is deployed, as written (`~/` not yet expanded).
+    pub fn target(&self) -> String {
+        match self {
+            DotfileEntry::Simple(s) => format!("~/{}", s),
+            DotfileEntry::Mapped(m) => m.target.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct DotfileMapping {
+    /// Path in the dotfiles repo.
     pub source: String,
+    /// Where it is deployed.
     pub target: String,
+    /// Deploy only where this holds.
     #[serde(default)]
-    pub when: Option<DotfileCondition>,
-}
-
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
-pub struct DotfileCondition {
+    pub when: Option<Box<Condition>>,
     #[serde(default)]
-    pub os: Vec<String>,
+    pub mode: DeployMode,
+    /// Per-entry override of `heimdal.relative_links`.
     #[serde(default)]
-    pub hostname: Option<String>,
+    pub relative_links: Option<bool>,
+    /// Mode for the deployed file. For symlinks this is set on the repo file
+    /// the link points at, since git does not keep anything but the x bit.
     #[serde(default)]
-    pub profile: Vec<String>,
+    pub file_mode: Option<FileMode>,
+    /// Mode for deployed directories and for parent directories apply creates.
+    #[serde(default)]
+    pub dir_mode: Option<FileMode>,
 }
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+impl DotfileMapping {
+    pub fn permissions(&self) -> Permissions {
+        Permissions {
+            file: self.file_mode,
+            dir: self.dir_mode,
+        }
+    }
+}
+
+/// How a dotfile is placed at its target. Copies and hardlinks are for apps
+/// that replace their config via atomic rename or refuse to follow symlinks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployMode {
+    #[default]
+    Symlink,
+    Copy,
+    Hardlink,
+}
+
+impl DeployMode {
+    pub fn verb(&self) -> &'static str {
+        match self {
+            DeployMode::Symlink => "Linked",
+            DeployMode::Copy => "Copied",
+            DeployMode::Hardlink => "Hardlinked",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct PackageMap {
+    /// Installed with whichever manager this machine has.
     #[serde(default)]
     pub common: Vec<String>,
     #[serde(default)]
@@ -92,12 +251,103 @@ pub struct PackageMap {
     pub pacman: Vec<String>,
     #[serde(default)]
     pub apk: Vec<String>,
+    /// Mac App Store apps.
     #[serde(default)]
     pub mas: Vec<serde_json::Value>,
+    /// Packages only for machines that match a condition.
+    #[serde(default)]
+    pub conditional: Vec<ConditionalPackages>,
 }
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ConditionalPackages {
+    /// Machines these packages are for.
+    pub when: Condition,
+    #[serde(flatten)]
+    pub packages: PackageMap,
+}
+
+impl PackageMap {
+    pub fn is_empty(&self) -> bool {
+        self.mas.is_empty()
+            && self.conditional.is_empty()
+            && self.lists().iter().all(|(_, names)| names.is_empty())
+    }
+
+    /// Remove `names` from every list, conditional groups included.
+    pub fn drop_names(&mut self, names: &[String]) {
+        for (_, list) in self.lists_mut() {
+            list.retain(|n| !names.contains(n));
+        }
+        for group in &mut self.conditional {
+            group.packages.drop_names(names);
+        }
+    }
+
+    /// These packages plus those of every conditional group that matches
+    /// this machine, with nothing listed twice.
+    pub fn for_host(&self, profile: &str) -> PackageMap {
+        let mut packages = PackageMap {
+            conditional: Vec::new(),
+            ..self.clone()
+        };
+        for group in &self.conditional {
+            if !crate::condition::holds(Some(&group.when), profile) {
+                continue;
+            }
+            let extra = group.packages.for_host(profile);
+            for ((_, ours), (_, theirs)) in packages.lists_mut().into_iter().zip(extra.lists()) {
+                for name in theirs {
+                    if !ours.contains(name) {
+                        ours.push(name.clone());
+                    }
+                }
+            }
+            for app in extra.mas {
+                if !packages.mas.contains(&app) {
+                    packages.mas.push(app);
+                }
+            }
+        }
+        packages
+    }
+
+    /// Every list of package names with its field name. `mas` holds app
+    /// store entries rather than names and is left out.
+    pub fn lists(&self) -> [(&'static str, &Vec<String>); 7] {
+        [
+            ("common", &self.common),
+            ("homebrew", &self.homebrew),
+            ("homebrew_casks", &self.homebrew_casks),
+            ("apt", &self.apt),
+            ("dnf", &self.dnf),
+            ("pacman", &self.pacman),
+            ("apk", &self.apk),
+        ]
+    }
+
+    pub fn lists_mut(&mut self) -> [(&'static str, &mut Vec<String>); 7] {
+        [
+            ("common", &mut self.common),
+            ("homebrew", &mut self.homebrew),
+            ("homebrew_casks", &mut self.homebrew_casks),
+            ("apt", &mut self.apt),
+            ("dnf", &mut self.dnf),
+            ("pacman", &mut self.pacman),
+            ("apk", &mut self.apk),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ProfileHooks {
+    /// How each phase combines with the hooks inherited from parents. When
+    /// unset, a phase this profile lists hooks for replaces the inherited
+    /// ones and any other phase keeps them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge: Option<HookMerge>,
     #[serde(default)]
     pub pre_apply: Vec<HookEntry>,
     #[serde(default)]
@@ -108,16 +358,121 @@ pub struct ProfileHooks {
     pub post_sync: Vec<HookEntry>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+impl ProfileHooks {
+    pub const PHASES: [&'static str; 4] = ["pre_apply", "post_apply", "pre_sync", "post_sync"];
+
+    /// The hooks of one of `PHASES`.
+    pub fn phase(&self, phase: &str) -> &Vec<HookEntry> {
+        match phase {
+            "pre_apply" => &self.pre_apply,
+            "post_apply" => &self.post_apply,
+            "pre_sync" => &self.pre_sync,
+            "post_sync" => &self.post_sync,
+            _ => unreachable!("unknown hook phase '{}'", phase),
+        }
+    }
+
+    pub fn phase_mut(&mut self, phase: &str) -> &mut Vec<HookEntry> {
+        match phase {
+            "pre_apply" => &mut self.pre_apply,
+            "post_apply" => &mut self.post_apply,
+            "pre_sync" => &mut self.pre_sync,
+            "post_sync" => &mut self.post_sync,
+            _ => unreachable!("unknown hook phase '{}'", phase),
+        }
+    }
+}
+
+/// How a profile's hooks for a phase combine with those it inherits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStrategy {
+    /// Drop the inherited hooks. Lifecycle hooks are usually specific to
+    /// one profile, so this is the default.
+    #[default]
+    Replace,
+    /// Run the inherited hooks first, then these.
+    Append,
+    /// Run these first, then the inherited hooks.
+    Prepend,
+}
+
+impl HookStrategy {
+    pub fn combine<T>(self, inherited: Vec<T>, own: Vec<T>) -> Vec<T> {
+        match self {
+            HookStrategy::Replace => own,
+            HookStrategy::Append => inherited.into_iter().chain(own).collect(),
+            HookStrategy::Prepend => own.into_iter().chain(inherited).collect(),
+        }
+    }
+}
+
+/// `merge: append` sets the strategy of every phase, while
+/// `merge: {pre_apply: append}` sets it per phase; unlisted phases replace.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 #[serde(untagged)]
+pub enum HookMerge {
+    All(HookStrategy),
+    Phases(PhaseStrategies),
+}
+
+impl Default for HookMerge {
+    fn default() -> Self {
+        HookMerge::All(HookStrategy::Replace)
+    }
+}
+
+impl HookMerge {
+    pub fn strategy(&self, phase: &str) -> HookStrategy {
+        match self {
+            HookMerge::All(strategy) => *strategy,
+            HookMerge::Phases(phases) => match phase {
+                "pre_apply" => phases.pre_apply,
+                "post_apply" => phases.post_apply,
+                "pre_sync" => phases.pre_sync,
+                "post_sync" => phases.post_sync,
+                _ => HookStrategy::Replace,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PhaseStrategies {
+    #[serde(default)]
+    pub pre_apply: HookStrategy,
+    #[serde(default)]
+    pub post_apply: HookStrategy,
+    #[serde(default)]
+    pub pre_sync: HookStrategy,
+    #[serde(default)]
+    pub post_sync: HookStrategy,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(deny_unknown_fields)]
 pub enum HookEntry {
     Simple(String),
+    /// Runs the hook a parent profile or mixin defines under this name:
+    /// `- ref: brew-update`.
+    Ref {
+        #[serde(rename = "ref")]
+        name: String,
+    },
     Full {
+        /// Lets profiles that inherit this one pull the hook in with `ref:`.
+        #[serde(default)]
+        name: Option<String>,
         command: String,
         #[serde(default)]
         description: Option<String>,
         #[serde(default)]
         os: Vec<String>,
+        /// Run only where this holds.
+        #[serde(default)]
+        when: Option<Box<Condition>>,
         #[serde(default = "default_true")]
         fail_on_error: bool,
     },
@@ -126,12 +481,231 @@ fn default_true() -> bool {
     true
 }
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct TemplateEntry {
+    /// Template in the dotfiles repo.
     pub src: String,
+    /// Where it is rendered to.
     pub dest: String,
+    /// Variables for this template only.
     #[serde(default)]
     pub vars: HashMap<String, String>,
+    /// Render only where this holds.
+    #[serde(default)]
+    pub when: Option<Box<Condition>>,
+    /// Mode for the rendered file.
+    #[serde(default)]
+    pub file_mode: Option<FileMode>,
+    /// Mode for parent directories created for the rendered file.
+    #[serde(default)]
+    pub dir_mode: Option<FileMode>,
+}
+
+impl TemplateEntry {
+    pub fn permissions(&self) -> Permissions {
+        Permissions {
+            file: self.file_mode,
+            dir: self.dir_mode,
+        }
+    }
+}
+
+/// A marked section kept up to date inside a file heimdal does not own, such
+/// as a `.bashrc` that ships with the machine image.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct BlockEntry {
+    /// Names the block in its markers: `# >>> heimdal:<id> >>>`.
+    pub id: String,
+    /// Snippet in the dotfiles repo whose content goes between the markers.
+    pub source: String,
+    pub target: String,
+    /// Render `source` with template variables before inserting it.
+    #[serde(default)]
+    pub template: bool,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    /// Comment leader for the marker lines. Defaults to `#`.
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// Keys from a repo fragment deep-merged into a JSON, YAML or TOML file that
+/// an app also writes to, like VS Code's `settings.json`.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct MergeEntry {
+    /// Fragment in the dotfiles repo holding the keys heimdal owns.
+    pub source: String,
+    pub target: String,
+    /// Guessed from the target's extension when omitted.
+    #[serde(default)]
+    pub format: Option<MergeFormat>,
+    #[serde(default)]
+    pub arrays: ArrayStrategy,
+}
+
+/// How an array in a merge fragment combines with the one in the target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrayStrategy {
+    /// The fragment's array replaces the target's.
+    #[default]
+    Replace,
+    /// The fragment's items go after the target's own.
+    Append,
+    /// Like append, but no item appears twice.
+    Unique,
+}
+
+/// The parts of a config that came from files pulled in with `include:`,
+/// so edits are written back to the file each item came from.
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    files: Vec<IncludedFile>,
+    loaded: HashMap<PathBuf, Loaded>,
+}
+
+/// A file's text and what it deserialized to, so an edit can change just
+/// the lines involved and leave alone files it didn't touch.
+#[derive(Debug, Clone)]
+struct Loaded {
+    text: String,
+    value: serde_yaml_ng::Value,
+}
+
+impl HeimdalConfig {
+    /// `path`, the file this config was loaded from, and every file it includes.
+    pub fn files<'a>(&'a self, path: &'a Path) -> Vec<&'a Path> {
+        std::iter::once(path)
+            .chain(self.sources.files.iter().map(|f| f.path.as_path()))
+            .collect()
+    }
+}
+
+impl Sources {
+    /// Every file read and its text, the main file first.
+    pub fn texts(&self) -> Vec<(&Path, &str)> {
+        let mut texts: Vec<_> = self
+            .loaded
+            .iter()
+            .map(|(path, loaded)| (path.as_path(), loaded.text.as_str()))
+            .collect();
+        texts.sort_by_key(|(path, _)| {
+            self.files
+                .iter()
+                .position(|f| f.path == *path)
+                .map_or(0, |i| i + 1)
+        });
+        texts
+    }
+
+    /// Whether `path` was pulled in with `include:` rather than loaded directly.
+    pub fn is_included(&self, path: &Path) -> bool {
+        self.files.iter().any(|f| f.path == path)
+    }
+
+    /// The included file a profile is defined in, if not the main one.
+    pub fn profile_file(&self, name: &str) -> Option<&Path> {
+        self.files
+            .iter()
+            .find(|f| f.profiles.iter().any(|p| p == name))
+            .map(|f| f.path.as_path())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IncludedFile {
+    path: PathBuf,
+    include: Vec<String>,
+    profiles: Vec<String>,
+    mixins: Vec<String>,
+    packages: PackageMap,
+    ignore: Vec<String>,
+}
+
+/// An included file: the lists heimdal.yaml has, without its settings.
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Fragment {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    profiles: HashMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    mixins: HashMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "PackageMap::is_empty")]
+    packages: PackageMap,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ignore: Vec<String>,
+}
+
+/// Write config changes back and atomically. Every command that edits
+/// heimdal.yaml goes through here. Profiles and mixins go back to the file
+/// they were included from; new ones land in heimdal.yaml. Only the lines
+/// that changed are rewritten, so comments and layout survive.
+pub fn write_config(path: &Path, config: &HeimdalConfig) -> anyhow::Result<()> {
+    let mut main = config.clone();
+    for file in &config.sources.files {
+        let mut fragment = Fragment {
+            include: file.include.clone(),
+            packages: file.packages.clone(),
+            ignore: file.ignore.clone(),
+            ..Default::default()
+        };
+        for name in &file.profiles {
+            if let Some(profile) = main.profiles.remove(name) {
+                fragment.profiles.insert(name.clone(), profile);
+            }
+        }
+        for name in &file.mixins {
+            if let Some(mixin) = main.mixins.remove(name) {
+                fragment.mixins.insert(name.clone(), mixin);
+            }
+        }
+        for ((_, ours), (_, theirs)) in main
+            .packages
+            .lists_mut()
+            .into_iter()
+            .zip(file.packages.lists())
+        {
+            remove_each(ours, theirs);
+        }
+        remove_each(&mut main.packages.mas, &file.packages.mas);
+        remove_each(&mut main.packages.conditional, &file.packages.conditional);
+        remove_each(&mut main.ignore, &file.ignore);
+        write_file(&file.path, &fragment, &config.sources)?;
+    }
+    write_file(path, &main, &config.sources)
+}
+
+/// Remove one occurrence of each of `items` from `list`.
+fn remove_each<T: PartialEq>(list: &mut Vec<T>, items: &[T]) {
+    for item in items {
+        if let Some(i) = list.iter().position(|x| x == item) {
+            list.remove(i);
+        }
+    }
+}
+
+fn write_file<T>(path: &Path, value: &T, sources: &Sources) -> anyhow::Result<()>
+where
+    T: Serialize + serde::de::DeserializeOwned,
+{
+    let edited = match sources.loaded.get(path) {
+        Some(loaded) if loaded.value == serde_yaml_ng::to_value(value)? => return Ok(()),
+        Some(loaded) => crate::yaml_edit::update(&loaded.text, &loaded.value, value),
+        None => None,
+    };
+    let content = match edited {
+        Some(content) => content,
+        None => serde_yaml_ng::to_string(value)?,
+    };
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    std::fs::write(&tmp, &content)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
 }
 
 pub fn load_config(path: &Path) -> anyhow::Result<HeimdalConfig> {
@@ -142,115 +716,699 @@ pub fn load_config(path: &Path) -> anyhow::Result<HeimdalConfig> {
             e
         ))
     })?;
-    serde_yaml_ng::from_str(&content)
-        .map_err(|e| crate::error::HeimdallError::Config(e.to_string()).into())
+    let mut config: HeimdalConfig = parse(path, &content)?;
+    let value = serde_yaml_ng::to_value(&config)?;
+    config.sources.loaded.insert(
+        path.to_path_buf(),
+        Loaded {
+            text: content,
+            value,
+        },
+    );
+    let mut seen = HashSet::from([path.canonicalize()?]);
+    let include = config.include.clone();
+    load_includes(&mut config, path, path, &include, &mut seen)?;
+    Ok(config)
+}
+
+/// Parse one file, locating any error as `file:line:column`.
+fn parse<T: serde::de::DeserializeOwned>(path: &Path, content: &str) -> anyhow::Result<T> {
+    serde_yaml_ng::from_str(content).map_err(|e| {
+        let msg = e.to_string();
+        let located = match e.location() {
+            Some(at) => {
+                let suffix = format!(" at line {} column {}", at.line(), at.column());
+                format!(
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    at.line(),
+                    at.column(),
+                    msg.replacen(&suffix, "", 1)
+                )
+            }
+            None => format!("{}: {}", path.display(), msg),
+        };
+        crate::error::HeimdallError::Config(located).into()
+    })
+}
+
+/// Merge the files `from` includes into `config`, depth first. A file is
+/// read once however many patterns match it.
+fn load_includes(
+    config: &mut HeimdalConfig,
+    main: &Path,
+    from: &Path,
+    patterns: &[String],
+    seen: &mut HashSet<PathBuf>,
+) -> anyhow::Result<()> {
+    let dir = from.parent().unwrap_or(Path::new("."));
+    for pattern in patterns {
+        for path in expand_include(dir, pattern, from)? {
+            if !seen.insert(path.canonicalize()?) {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path).map_err(|e| {
+                crate::error::HeimdallError::Config(format!(
+                    "{}: cannot read included {}: {}",
+                    from.display(),
+                    path.display(),
+                    e
+                ))
+            })?;
+            let fragment: Fragment = parse(&path, &content)?;
+            let value = serde_yaml_ng::to_value(&fragment)?;
+            config.sources.loaded.insert(
+                path.clone(),
+                Loaded {
+                    text: content.clone(),
+                    value,
+                },
+            );
+
+            let mut file = IncludedFile {
+                path: path.clone(),
+                include: fragment.include,
+                profiles: Vec::new(),
+                mixins: Vec::new(),
+                packages: fragment.packages.clone(),
+                ignore: fragment.ignore.clone(),
+            };
+            for (name, profile) in fragment.profiles {
+                if config.profiles.contains_key(&name) {
+                    let other = config.sources.profile_file(&name).unwrap_or(main);
+                    return Err(duplicate("profile", &name, &path, &content, other));
+                }
+                file.profiles.push(name.clone());
+                config.profiles.insert(name, profile);
+            }
+            for (name, mixin) in fragment.mixins {
+                if config.mixins.contains_key(&name) {
+                    let other = config
+                        .sources
+                        .files
+                        .iter()
+                        .find(|f| f.mixins.contains(&name))
+                        .map_or(main, |f| f.path.as_path());
+                    return Err(duplicate("mixin", &name, &path, &content, other));
+                }
+                file.mixins.push(name.clone());
+                config.mixins.insert(name, mixin);
+            }
+            for ((_, ours), (_, theirs)) in config
+                .packages
+                .lists_mut()
+                .into_iter()
+                .zip(fragment.packages.lists())
+            {
+                ours.extend(theirs.iter().cloned());
+            }
+            config.packages.mas.extend(fragment.packages.mas);
+            config
+                .packages
+                .conditional
+                .extend(fragment.packages.conditional);
+            config.ignore.extend(fragment.ignore);
+
+            let nested = file.include.clone();
+            config.sources.files.push(file);
+            load_includes(config, main, &path, &nested, seen)?;
+        }
+    }
+    Ok(())
+}
+
+/// The files an include pattern names. A glob may match nothing; a plain
+/// path must exist.
+fn expand_include(dir: &Path, pattern: &str, from: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let full = dir.join(crate::utils::expand_path(pattern));
+    if !pattern.contains(['*', '?', '[']) {
+        if !full.is_file() {
+            return Err(crate::error::HeimdallError::Config(format!(
+                "{}: included file {} does not exist",
+                from.display(),
+                full.display()
+            ))
+            .into());
+        }
+        return Ok(vec![full]);
+    }
+    let matches = glob::glob(&full.to_string_lossy()).map_err(|e| {
+        crate::error::HeimdallError::Config(format!(
+            "{}: bad include pattern '{}': {}",
+            from.display(),
+            pattern,
+            e
+        ))
+    })?;
+    let mut paths: Vec<PathBuf> = matches
+        .filter_map(|m| m.ok())
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn duplicate(kind: &str, name: &str, path: &Path, content: &str, other: &Path) -> anyhow::Error {
+    let line = content
+        .lines()
+        .position(|l| {
+            l.starts_with(' ')
+                && l.trim_start()
+                    .trim_start_matches(['"', '\''])
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.trim_start_matches(['"', '\'']).starts_with(':'))
+        })
+        .map_or(String::new(), |i| format!(":{}", i + 1));
+    crate::error::HeimdallError::Config(format!(
+        "{}{}: {} '{}' is already defined in {}",
+        path.display(),
+        line,
+        kind,
+        name,
+        other.display()
+    ))
+    .into()
+}
+
+/// The profile name that stands for "whichever profile's `match:` fits".
+pub const AUTO_PROFILE: &str = "auto";
+
+/// Profiles whose `match:` block fits `host`, sorted. A profile without one
+/// never matches.
+pub fn matching_profiles(config: &HeimdalConfig, host: &Host) -> Vec<String> {
+    let mut names: Vec<_> = config
+        .profiles
+        .iter()
+        .filter(|(name, p)| p.machine.as_ref().is_some_and(|m| m.matches(host, name)))
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+/// `name` itself, or for `auto` the one profile whose `match:` block fits
+/// this machine. None or several fitting is an error.
+pub fn select_profile(config: &HeimdalConfig, name: &str) -> anyhow::Result<String> {
+    if name != AUTO_PROFILE {
+        return Ok(name.to_string());
+    }
+    let host = Host::current();
+    let mut matching = matching_profiles(config, &host);
+    match matching.len() {
+        1 => {
+            let name = matching.remove(0);
+            crate::utils::info(&format!("Profile '{}' matches this machine", name));
+            Ok(name)
+        }
+        0 => Err(crate::error::HeimdallError::Config(format!(
+            "No profile matches this machine (hostname {}, user {}, os {}{}, arch {}). \
+             Add a match: block to one, or name the profile instead of 'auto'.",
+            host.hostname,
+            host.user,
+            host.os,
+            host.distro
+                .map(|d| format!(", distro {}", d))
+                .unwrap_or_default(),
+            host.arch
+        ))
+        .into()),
+        _ => Err(crate::error::HeimdallError::Config(format!(
+            "Profiles {} all match this machine. \
+             Narrow their match: blocks, or name the profile instead of 'auto'.",
+            matching.join(", ")
+        ))
+        .into()),
+    }
 }
 
 pub fn resolve_profile(config: &HeimdalConfig, name: &str) -> anyhow::Result<Profile> {
-    let mut profile = resolve_recursive(config, name, &mut Vec::new())?;
-    // Prepend top-level packages so profile-specific ones take effect after
-    profile.packages = merge_packages(config.packages.clone(), profile.packages);
-    Ok(profile)
+    Ok(resolve_with_origins(config, name)?.0)
 }
 
-fn resolve_recursive(
+/// A profile or mixin taking part in a resolution.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Layer {
+    pub name: String,
+    pub mixin: bool,
+}
+
+impl Layer {
+    fn profile(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            mixin: false,
+        }
+    }
+
+    fn mixin(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            mixin: true,
+        }
+    }
+}
+
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.mixin {
+            write!(f, "mixin {}", self.name)
+        } else {
+            f.write_str(&self.name)
+        }
+    }
+}
+
+/// Where each item of a resolved profile came from. Every list runs parallel
+/// to the same list in the resolved `Profile`: entry i names the layer that
+/// contributed item i.
+#[derive(Debug, Clone, Default)]
+pub struct Origins {
+    /// The order layers were applied in, the profile itself last.
+    pub layers: Vec<Layer>,
+    pub dotfiles: Vec<String>,
+    /// By `PackageMap` field name, `mas` included.
+    pub packages: HashMap<&'static str, Vec<String>>,
+    pub hooks: HookOrigins,
+    pub templates: Vec<String>,
+    pub blocks: Vec<String>,
+    pub merges: Vec<String>,
+    pub ignore: Vec<String>,
+    /// By variable name, the layer that set it last.
+    pub variables: HashMap<String, String>,
+}
+
+/// Origins of the resolved hooks, per phase.
+#[derive(Debug, Clone, Default)]
+pub struct HookOrigins {
+    pub pre_apply: Vec<String>,
+    pub post_apply: Vec<String>,
+    pub pre_sync: Vec<String>,
+    pub post_sync: Vec<String>,
+}
+
+impl HookOrigins {
+    pub fn phase(&self, phase: &str) -> &Vec<String> {
+        match phase {
+            "pre_apply" => &self.pre_apply,
+            "post_apply" => &self.post_apply,
+            "pre_sync" => &self.pre_sync,
+            "post_sync" => &self.post_sync,
+            _ => unreachable!("unknown hook phase '{}'", phase),
+        }
+    }
+
+    fn phase_mut(&mut self, phase: &str) -> &mut Vec<String> {
+        match phase {
+            "pre_apply" => &mut self.pre_apply,
+            "post_apply" => &mut self.post_apply,
+            "pre_sync" => &mut self.pre_sync,
+            "post_sync" => &mut self.post_sync,
+            _ => unreachable!("unknown hook phase '{}'", phase),
+        }
+    }
+}
+
+/// Layer `own` over `items`: inherited items sharing a key with any of
+/// `own` are replaced by those, in the first one's place; the rest of `own`
+/// is appended. Items of one layer never replace each other, so entries for
+/// one target under different conditions all survive. `origins` runs
+/// parallel to `items`.
+fn merge_keyed<T, K: PartialEq>(
+    items: &mut Vec<T>,
+    origins: &mut Vec<String>,
+    own: Vec<T>,
+    from: &str,
+    key: impl Fn(&T) -> K,
+) {
+    let inherited = std::mem::take(items)
+        .into_iter()
+        .zip(std::mem::take(origins));
+    let own_keys: Vec<K> = own.iter().map(&key).collect();
+    let mut own: Vec<Option<T>> = own.into_iter().map(Some).collect();
+    for (item, origin) in inherited {
+        let k = key(&item);
+        if !own_keys.contains(&k) {
+            items.push(item);
+            origins.push(origin);
+            continue;
+        }
+        // Later inherited items with this key find theirs already placed
+        for (i, own_key) in own_keys.iter().enumerate() {
+            if *own_key == k {
+                if let Some(replacement) = own[i].take() {
+                    items.push(replacement);
+                    origins.push(from.to_string());
+                }
+            }
+        }
+    }
+    for item in own.into_iter().flatten() {
+        items.push(item);
+        origins.push(from.to_string());
+    }
+}
+
+/// Drop the items `excluded` matches, along with their origins.
+fn drop_excluded<T>(items: &mut Vec<T>, origins: &mut Vec<String>, excluded: impl Fn(&T) -> bool) {
+    (*items, *origins) = std::mem::take(items)
+        .into_iter()
+        .zip(std::mem::take(origins))
+        .filter(|(item, _)| !excluded(item))
+        .unzip();
+}
+
+/// Layer `own` over the profile resolved so far. `hook_origins` are the
+/// origins of `own`'s hooks: `from`, or for a `ref:` the layer that defined
+/// the hook.
+fn layer_onto(
+    profile: &mut Profile,
+    origins: &mut Origins,
+    own: Profile,
+    from: &str,
+    hook_origins: HookOrigins,
+) {
+    use crate::utils::expand_path;
+    let exclude = &own.exclude;
+
+    drop_excluded(&mut profile.dotfiles, &mut origins.dotfiles, |d| {
+        exclude
+            .dotfiles
+            .iter()
+            .any(|e| e == d.source() || expand_path(e) == expand_path(&d.target()))
+    });
+    merge_keyed(
+        &mut profile.dotfiles,
+        &mut origins.dotfiles,
+        own.dotfiles,
+        from,
+        |d| expand_path(&d.target()),
+    );
+
+    let mut own_packages = own.packages;
+    for ((field, names), (_, own_names)) in profile
+        .packages
+        .lists_mut()
+        .into_iter()
+        .zip(own_packages.lists_mut())
+    {
+        let names_origins = origins.packages.entry(field).or_default();
+        drop_excluded(names, names_origins, |n| exclude.packages.contains(n));
+        merge_keyed(names, names_origins, std::mem::take(own_names), from, |n| {
+            n.clone()
+        });
+    }
+    // App store entries are keyed by id
+    let mas_origins = origins.packages.entry("mas").or_default();
+    let mas_key = |v: &serde_json::Value| v.get("id").cloned().unwrap_or_else(|| v.clone());
+    drop_excluded(&mut profile.packages.mas, mas_origins, |v| {
+        exclude.packages.iter().any(|e| {
+            v.as_str() == Some(e.as_str())
+                || v.get("name").and_then(|n| n.as_str()) == Some(e.as_str())
+        })
+    });
+    merge_keyed(
+        &mut profile.packages.mas,
+        mas_origins,
+        own_packages.mas,
+        from,
+        mas_key,
+    );
+    // Conditional groups are settled per machine at install time, so they
+    // only accumulate; exclusions still reach into inherited ones
+    for group in &mut profile.packages.conditional {
+        group.packages.drop_names(&exclude.packages);
+    }
+    profile
+        .packages
+        .conditional
+        .extend(own_packages.conditional);
+
+    let mut own_hooks = own.hooks;
+    let mut hook_origins = hook_origins;
+    for phase in ProfileHooks::PHASES {
+        let strategy = match &own_hooks.merge {
+            Some(merge) => merge.strategy(phase),
+            // A layer with no hooks for the phase passes the inherited ones on
+            None if own_hooks.phase(phase).is_empty() => continue,
+            None => HookStrategy::Replace,
+        };
+        *profile.hooks.phase_mut(phase) = strategy.combine(
+            std::mem::take(profile.hooks.phase_mut(phase)),
+            std::mem::take(own_hooks.phase_mut(phase)),
+        );
+        *origins.hooks.phase_mut(phase) = strategy.combine(
+            std::mem::take(origins.hooks.phase_mut(phase)),
+            std::mem::take(hook_origins.phase_mut(phase)),
+        );
+    }
+
+    drop_excluded(&mut profile.templates, &mut origins.templates, |t| {
+        exclude
+            .templates
+            .iter()
+            .any(|e| *e == t.src || expand_path(e) == expand_path(&t.dest))
+    });
+    merge_keyed(
+        &mut profile.templates,
+        &mut origins.templates,
+        own.templates,
+        from,
+        |t| expand_path(&t.dest),
+    );
+    merge_keyed(
+        &mut profile.blocks,
+        &mut origins.blocks,
+        own.blocks,
+        from,
+        |b| (expand_path(&b.target), b.id.clone()),
+    );
+    // Several fragments may merge into one file, so these only accumulate
+    origins
+        .merges
+        .extend(vec![from.to_string(); own.merges.len()]);
+    profile.merges.extend(own.merges);
+    merge_keyed(
+        &mut profile.ignore,
+        &mut origins.ignore,
+        own.ignore,
+        from,
+        |i| i.clone(),
+    );
+    for (name, value) in own.variables {
+        origins.variables.insert(name.clone(), from.to_string());
+        profile.variables.insert(name, value);
+    }
+}
+
+/// Resolve `name` and record which layer each item came from.
+pub fn resolve_with_origins(
     config: &HeimdalConfig,
     name: &str,
-    chain: &mut Vec<String>,
-) -> anyhow::Result<Profile> {
-    if chain.contains(&name.to_string()) {
-        return Err(anyhow::anyhow!(
-            "Circular extends detected: {} -> {}",
-            chain.join(" -> "),
-            name
-        ));
+) -> anyhow::Result<(Profile, Origins)> {
+    let layers = linearize(config, name)?;
+    resolve_layers(config, layers).map_err(|e| crate::error::HeimdallError::Config(e).into())
+}
+
+/// Fold `layers`, as returned by `linearize`, into one profile.
+fn resolve_layers(
+    config: &HeimdalConfig,
+    layers: Vec<Layer>,
+) -> Result<(Profile, Origins), String> {
+    let mut profile = Profile::default();
+    let mut origins = Origins::default();
+    // Top-level packages go first so profile-specific ones take effect after
+    let top_level = Profile {
+        packages: config.packages.clone(),
+        ..Default::default()
+    };
+    layer_onto(
+        &mut profile,
+        &mut origins,
+        top_level,
+        "top-level packages",
+        HookOrigins::default(),
+    );
+    let top_level = Profile {
+        variables: config.variables.clone(),
+        ..Default::default()
+    };
+    layer_onto(
+        &mut profile,
+        &mut origins,
+        top_level,
+        "top-level variables",
+        HookOrigins::default(),
+    );
+    // Hooks that `ref:` can name, with the layer that defined each
+    let mut named: HashMap<String, (HookEntry, String)> = HashMap::new();
+    for layer in &layers {
+        let mut own = lookup(config, layer)
+            .expect("linearize only returns layers that exist")
+            .clone();
+        let label = layer.to_string();
+        let defined: Vec<(String, HookEntry)> = ProfileHooks::PHASES
+            .iter()
+            .flat_map(|phase| own.hooks.phase(phase))
+            .filter_map(|hook| match hook {
+                HookEntry::Full {
+                    name: Some(name), ..
+                } => Some((name.clone(), hook.clone())),
+                _ => None,
+            })
+            .collect();
+        let hook_origins = resolve_hook_refs(&mut own.hooks, &named, layer)?;
+        for (name, hook) in defined {
+            named.insert(name, (hook, label.clone()));
+        }
+        layer_onto(&mut profile, &mut origins, own, &label, hook_origins);
     }
-    let profile = config
-        .profiles
-        .get(name)
-        .ok_or_else(|| crate::error::HeimdallError::ProfileNotFound {
-            name: name.to_string(),
-        })?
-        .clone();
+    origins.layers = layers;
+    Ok((profile, origins))
+}
+
+/// The order `name` and everything it extends or includes are applied in:
+/// ancestors first, each exactly once, the profile itself last. This is C3
+/// linearization, so a layer always comes after its own parents and the
+/// order of every `extends` list is kept.
+pub fn linearize(config: &HeimdalConfig, name: &str) -> anyhow::Result<Vec<Layer>> {
+    let mut order =
+        resolve_recursive(config, &Layer::profile(name), &mut Vec::new()).map_err(|e| match e {
+            Unresolvable::Missing(layer) if !layer.mixin => {
+                crate::error::HeimdallError::ProfileNotFound { name: layer.name }.into()
+            }
+            e => anyhow::Error::from(crate::error::HeimdallError::Config(e.to_string())),
+        })?;
+    order.reverse();
+    Ok(order)
+}
+
+/// Why a profile cannot be resolved.
+#[derive(Debug)]
+enum Unresolvable {
+    Missing(Layer),
+    /// The layers of the cycle, the first repeated at the end.
+    Cycle(Vec<Layer>),
+    Inconsistent(Layer),
+}
 
-    match &profile.extends.clone() {
-        None => Ok(profile),
-        Some(parent_name) => {
-            chain.push(name.to_string());
-            let parent = resolve_recursive(config, parent_name, chain)?;
-            Ok(merge_profiles(parent, profile))
+impl std::fmt::Display for Unresolvable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unresolvable::Missing(layer) => write!(f, "Unknown {}", describe(layer)),
+            Unresolvable::Cycle(cycle) => {
+                let names: Vec<_> = cycle.iter().map(|l| l.to_string()).collect();
+                write!(f, "Circular extends detected: {}", names.join(" -> "))
+            }
+            Unresolvable::Inconsistent(layer) => write!(
+                f,
+                "Cannot order the parents of {}: its extends/include lists disagree \
+                 with the order their own parents are listed in",
+                describe(layer)
+            ),
         }
     }
 }
 
-fn merge_profiles(base: Profile, child: Profile) -> Profile {
-    Profile {
-        extends: None,
-        dotfiles: {
-            let mut d = base.dotfiles;
-            d.extend(child.dotfiles);
-            d
-        },
-        packages: merge_packages(base.packages, child.packages),
-        // Hooks: child completely replaces parent hooks (not merged).
-        // A child profile that wants parent hooks must explicitly repeat them.
-        // This is intentional — lifecycle hooks are profile-specific scripts.
-        hooks: child.hooks,
-        templates: {
-            let mut t = base.templates;
-            t.extend(child.templates);
-            t
-        },
-        ignore: {
-            let mut i = base.ignore;
-            i.extend(child.ignore);
-            i
-        },
+/// Replace each `ref:` in `hooks` with the hook a parent defined under that
+/// name, and return where every hook came from.
+fn resolve_hook_refs(
+    hooks: &mut ProfileHooks,
+    named: &HashMap<String, (HookEntry, String)>,
+    layer: &Layer,
+) -> Result<HookOrigins, String> {
+    let mut origins = HookOrigins::default();
+    for phase in ProfileHooks::PHASES {
+        for hook in hooks.phase_mut(phase).iter_mut() {
+            let origin = match hook {
+                HookEntry::Ref { name } => {
+                    let (parent_hook, origin) = named.get(name.as_str()).ok_or_else(|| {
+                        format!(
+                            "Hook ref '{}' in {} does not name a hook of its parents",
+                            name,
+                            describe(layer)
+                        )
+                    })?;
+                    *hook = parent_hook.clone();
+                    origin.clone()
+                }
+                _ => layer.to_string(),
+            };
+            origins.phase_mut(phase).push(origin);
+        }
     }
+    Ok(origins)
 }
 
-fn merge_packages(base: PackageMap, child: PackageMap) -> PackageMap {
-    PackageMap {
-        common: {
-            let mut v = base.common;
-            v.extend(child.common);
-            v
-        },
-        homebrew: {
-            let mut v = base.homebrew;
-            v.extend(child.homebrew);
-            v
-        },
-        homebrew_casks: {
-            let mut v = base.homebrew_casks;
-            v.extend(child.homebrew_casks);
-            v
-        },
-        apt: {
-            let mut v = base.apt;
-            v.extend(child.apt);
-            v
-        },
-        dnf: {
-            let mut v = base.dnf;
-            v.extend(child.dnf);
-            v
-        },
-        pacman: {
-            let mut v = base.pacman;
-            v.extend(child.pacman);
-            v
-        },
-        apk: {
-            let mut v = base.apk;
-            v.extend(child.apk);
-            v
-        },
-        mas: {
-            let mut v = base.mas;
-            v.extend(child.mas);
-            v
-        },
+fn describe(layer: &Layer) -> String {
+    if layer.mixin {
+        format!("mixin '{}'", layer.name)
+    } else {
+        format!("profile '{}'", layer.name)
+    }
+}
+
+fn lookup<'a>(config: &'a HeimdalConfig, layer: &Layer) -> Option<&'a Profile> {
+    if layer.mixin {
+        config.mixins.get(&layer.name)
+    } else {
+        config.profiles.get(&layer.name)
+    }
+}
+
+/// The layers `profile` extends and includes, in the order they apply.
+fn parents(profile: &Profile) -> Vec<Layer> {
+    profile
+        .extends
+        .iter()
+        .map(|n| Layer::profile(n))
+        .chain(profile.include.iter().map(|n| Layer::mixin(n)))
+        .collect()
+}
+
+/// C3 linearization of `layer`, most specific first. `chain` holds the
+/// layers being resolved above this one, to catch cycles.
+fn resolve_recursive(
+    config: &HeimdalConfig,
+    layer: &Layer,
+    chain: &mut Vec<Layer>,
+) -> Result<Vec<Layer>, Unresolvable> {
+    if let Some(pos) = chain.iter().position(|l| l == layer) {
+        let mut cycle = chain[pos..].to_vec();
+        cycle.push(layer.clone());
+        return Err(Unresolvable::Cycle(cycle));
+    }
+    let profile = lookup(config, layer).ok_or_else(|| Unresolvable::Missing(layer.clone()))?;
+
+    // Later parents win, so they rank first in the linearization
+    let mut bases = parents(profile);
+    bases.reverse();
+    chain.push(layer.clone());
+    let mut sequences = Vec::with_capacity(bases.len() + 1);
+    for base in &bases {
+        sequences.push(resolve_recursive(config, base, chain)?);
+    }
+    chain.pop();
+    sequences.push(bases);
+
+    let mut order = vec![layer.clone()];
+    loop {
+        sequences.retain(|s| !s.is_empty());
+        if sequences.is_empty() {
+            return Ok(order);
+        }
+        // The first head that is not waiting behind anything in another tail
+        let next = sequences
+            .iter()
+            .map(|s| &s[0])
+            .find(|head| !sequences.iter().any(|s| s[1..].contains(head)))
+            .cloned()
+            .ok_or_else(|| Unresolvable::Inconsistent(layer.clone()))?;
+        for s in &mut sequences {
+            if s[0] == next {
+                s.remove(0);
+            }
+        }
+        order.push(next);
     }
 }
 
@@ -259,58 +1417,86 @@ fn merge_packages(base: PackageMap, child: PackageMap) -> PackageMap {
 pub fn validate_config(config: &HeimdalConfig) -> Vec<String> {
     let mut errors = Vec::new();
 
-    // Check extends targets exist
-    for (name, profile) in &config.profiles {
-        if let Some(parent) = &profile.extends {
+    errors.extend(crate::schema::unknown_keys(config));
+
+    if config.profiles.contains_key(AUTO_PROFILE) {
+        errors.push(format!(
+            "Profile name '{}' is reserved for picking a profile by its match: block",
+            AUTO_PROFILE
+        ));
+    }
+
+    // Check extends and include targets exist
+    let mut layers: Vec<(Layer, &Profile)> = config
+        .profiles
+        .iter()
+        .map(|(name, p)| (Layer::profile(name), p))
+        .chain(
+            config
+                .mixins
+                .iter()
+                .map(|(name, p)| (Layer::mixin(name), p)),
+        )
+        .collect();
+    layers.sort_by(|a, b| (a.0.mixin, &a.0.name).cmp(&(b.0.mixin, &b.0.name)));
+    for (layer, profile) in &layers {
+        let who = if layer.mixin {
+            format!("Mixin '{}'", layer.name)
+        } else {
+            format!("Profile '{}'", layer.name)
+        };
+        for parent in &profile.extends {
             if !config.profiles.contains_key(parent.as_str()) {
+                errors.push(format!("{} extends '{}' which does not exist", who, parent));
+            }
+        }
+        for mixin in &profile.include {
+            if !config.mixins.contains_key(mixin.as_str()) {
                 errors.push(format!(
-                    "Profile '{}' extends '{}' which does not exist",
-                    name, parent
+                    "{} includes mixin '{}' which does not exist",
+                    who, mixin
                 ));
             }
         }
     }
 
-    // Check for circular extends — report each cycle only once
-    let mut reported_cycles: std::collections::HashSet<String> = std::collections::HashSet::new();
-    for name in config.profiles.keys() {
-        let mut chain: Vec<&str> = vec![];
-        let mut current = name.as_str();
-        loop {
-            if let Some(pos) = chain.iter().position(|&n| n == current) {
-                // Build canonical cycle key (sort the cycle nodes to deduplicate)
-                let cycle_nodes = &chain[pos..];
-                let mut sorted_key: Vec<&str> = cycle_nodes.to_vec();
-                sorted_key.sort_unstable();
-                let key = sorted_key.join(",");
-                if reported_cycles.insert(key) {
-                    // Show full cycle with closing node
-                    let mut display = chain[pos..].to_vec();
-                    display.push(current);
-                    errors.push(format!(
-                        "Circular extends detected: {}",
-                        display.join(" → ")
-                    ));
+    // Check every profile and mixin linearizes — report each cycle only once
+    let mut reported: std::collections::HashSet<String> = std::collections::HashSet::new();
+    for (layer, _) in &layers {
+        match resolve_recursive(config, layer, &mut Vec::new()) {
+            Ok(_) | Err(Unresolvable::Missing(_)) => {} // already reported above
+            Err(Unresolvable::Cycle(cycle)) => {
+                // Canonical key: the cycle's layers, sorted
+                let mut key: Vec<String> = cycle[1..].iter().map(|l| l.to_string()).collect();
+                key.sort_unstable();
+                if reported.insert(key.join(",")) {
+                    let names: Vec<_> = cycle.iter().map(|l| l.to_string()).collect();
+                    errors.push(format!("Circular extends detected: {}", names.join(" → ")));
                 }
-                break;
             }
-            chain.push(current);
-            match config
-                .profiles
-                .get(current)
-                .and_then(|p| p.extends.as_deref())
-            {
-                None => break,
-                Some(next) => {
-                    if !config.profiles.contains_key(next) {
-                        break; // Unknown extends already reported in previous loop
-                    }
-                    current = next;
+            Err(e) => {
+                if reported.insert(e.to_string()) {
+                    errors.push(e.to_string());
                 }
             }
         }
     }
 
+    // Check every hook ref names a hook of the profile's parents
+    for (layer, _) in &layers {
+        if layer.mixin {
+            continue; // checked in each profile that includes it
+        }
+        let Ok(order) = linearize(config, &layer.name) else {
+            continue;
+        };
+        if let Err(e) = resolve_layers(config, order) {
+            if reported.insert(e.clone()) {
+                errors.push(e);
+            }
+        }
+    }
+
     // Check dotfile source paths are relative and don't traverse outside dotfiles dir
     for (prof_name, profile) in &config.profiles {
         for entry in &profile.dotfiles {
@@ -337,6 +1523,30 @@ pub fn validate_config(config: &HeimdalConfig) -> Vec<String> {
         }
     }
 
+    // Block ids end up in marker lines and must be unique per target file
+    for (prof_name, profile) in &config.profiles {
+        let mut seen = std::collections::HashSet::new();
+        for block in &profile.blocks {
+            if block.id.is_empty()
+                || !block
+                    .id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+            {
+                errors.push(format!(
+                    "Profile '{}': block id '{}' may only contain letters, digits, '-', '_' and '.'",
+                    prof_name, block.id
+                ));
+            }
+            if !seen.insert((block.target.as_str(), block.id.as_str())) {
+                errors.push(format!(
+                    "Profile '{}': block '{}' appears twice in {}",
+                    prof_name, block.id, block.target
+                ));
+            }
+        }
+    }
+
     errors
 }
 
@@ -361,11 +1571,16 @@ pub fn create_minimal_config(path: &std::path::Path, profile_name: &str) -> anyh
         heimdal: HeimdalMeta {
             version: "1".to_string(),
             repo: None,
+            relative_links: false,
         },
+        include: vec![],
         profiles,
+        mixins: HashMap::new(),
         packages: PackageMap::default(),
         ignore: vec![],
+        variables: HashMap::new(),
         history: None,
+        sources: Default::default(),
     };
 
     if let Some(parent) = path.parent() {
@@ -375,3 +1590,46 @@ pub fn create_minimal_config(path: &std::path::Path, profile_name: &str) -> anyh
     std::fs::write(path, content)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_without_hooks_keep_inherited_ones() {
+        let config: HeimdalConfig = serde_yaml_ng::from_str(
+            r#"
+heimdal:
+  version: "1"
+mixins:
+  rust:
+    packages:
+      common: [rustup]
+profiles:
+  base:
+    hooks:
+      pre_apply: [upd]
+      post_apply: [done]
+  work:
+    extends: base
+    include: [rust]
+    hooks:
+      merge: append
+      pre_apply: [echo work]
+"#,
+        )
+        .unwrap();
+        let profile = resolve_profile(&config, "work").unwrap();
+        let commands = |hooks: &[HookEntry]| -> Vec<String> {
+            hooks
+                .iter()
+                .map(|h| match h {
+                    HookEntry::Simple(command) => command.clone(),
+                    other => panic!("unexpected hook {:?}", other),
+                })
+                .collect()
+        };
+        assert_eq!(commands(&profile.hooks.pre_apply), ["upd", "echo work"]);
+        assert_eq!(commands(&profile.hooks.post_apply), ["done"]);
+    }
+}
@@ -183,6 +183,21 @@ impl GitRepo {
         Ok(())
     }
 
+    /// Stage paths (relative to the repo root) without committing.
+    pub fn add(&self, paths: &[String]) -> Result<()> {
+        let status = Command::new("git")
+            .arg("add")
+            .arg("--")
+            .args(paths)
+            .current_dir(&self.path)
+            .status()
+            .map_err(|e| crate::error::HeimdallError::Git(format!("Cannot run git: {}", e)))?;
+        if !status.success() {
+            return Err(crate::error::HeimdallError::Git("git add failed".to_string()).into());
+        }
+        Ok(())
+    }
+
     pub fn pull(&self, dry_run: bool) -> Result<()> {
         if dry_run {
             crate::utils::info("[dry-run] Would run: git pull");
@@ -1,43 +1,64 @@
 use crate::config::HookEntry;
 use anyhow::Result;
 
-pub fn run_hooks(hooks: &[HookEntry], dry_run: bool) -> Result<()> {
-    for hook in hooks {
-        let (cmd, fail_on_error, os_filter): (&str, bool, &[String]) = match hook {
-            HookEntry::Simple(s) => (s.as_str(), true, &[]),
-            HookEntry::Full {
-                command,
-                fail_on_error,
-                os,
-                ..
-            } => (command.as_str(), *fail_on_error, os.as_slice()),
-        };
-
-        if !os_filter.is_empty() && !os_filter.iter().any(|o| o == crate::utils::os_name()) {
-            continue;
-        }
-
+pub fn run_hooks(hooks: &[HookEntry], profile: &str, dry_run: bool) -> Result<()> {
+    for (cmd, fail_on_error) in applicable(hooks, profile) {
         if dry_run {
             crate::utils::info(&format!("Would run hook: {}", cmd));
             continue;
         }
+        run_hook(&cmd, fail_on_error)?;
+    }
+    Ok(())
+}
+
+/// The hooks whose `os` and `when` match this machine with `profile`
+/// active, as (command, fail_on_error).
+pub fn applicable(hooks: &[HookEntry], profile: &str) -> Vec<(String, bool)> {
+    hooks
+        .iter()
+        .filter_map(|hook| {
+            let (cmd, fail_on_error, os_filter, when): (&str, bool, &[String], _) = match hook {
+                HookEntry::Simple(s) => (s.as_str(), true, &[], None),
+                // Replaced by the named hook when the profile is resolved
+                HookEntry::Ref { .. } => return None,
+                HookEntry::Full {
+                    command,
+                    fail_on_error,
+                    os,
+                    when,
+                    ..
+                } => (
+                    command.as_str(),
+                    *fail_on_error,
+                    os.as_slice(),
+                    when.as_deref(),
+                ),
+            };
+            let os_matches =
+                os_filter.is_empty() || os_filter.iter().any(|o| o == crate::utils::os_name());
+            (os_matches && crate::condition::holds(when, profile))
+                .then(|| (cmd.to_string(), fail_on_error))
+        })
+        .collect()
+}
 
-        crate::utils::step(&format!("Hook: {}", cmd));
-        let status = std::process::Command::new("sh")
-            .args(["-c", cmd])
-            .status()?;
+pub fn run_hook(cmd: &str, fail_on_error: bool) -> Result<()> {
+    crate::utils::step(&format!("Hook: {}", cmd));
+    let status = std::process::Command::new("sh")
+        .args(["-c", cmd])
+        .status()?;
 
-        if !status.success() {
-            let code = status.code().unwrap_or(-1);
-            if fail_on_error {
-                return Err(crate::error::HeimdallError::HookFailed {
-                    command: cmd.to_string(),
-                    code,
-                }
-                .into());
-            } else {
-                crate::utils::warning(&format!("Hook failed (ignored): {} (exit {})", cmd, code));
+    if !status.success() {
+        let code = status.code().unwrap_or(-1);
+        if fail_on_error {
+            return Err(crate::error::HeimdallError::HookFailed {
+                command: cmd.to_string(),
+                code,
             }
+            .into());
+        } else {
+            crate::utils::warning(&format!("Hook failed (ignored): {} (exit {})", cmd, code));
         }
     }
     Ok(())
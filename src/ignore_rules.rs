@@ -0,0 +1,155 @@
+use anyhow::Result;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::config::{HeimdalConfig, Profile};
+
+/// Repo-local ignore file, read with the same syntax as `.gitignore`.
+pub const IGNORE_FILE: &str = ".heimdalignore";
+
+/// Gitignore-style patterns deciding which paths under the dotfiles dir are
+/// never deployed: globs, `!negation`, `dir/`-only and `/anchored` patterns.
+pub struct IgnoreRules {
+    matcher: Gitignore,
+}
+
+impl IgnoreRules {
+    pub fn none() -> Self {
+        Self {
+            matcher: Gitignore::empty(),
+        }
+    }
+
+    /// `.heimdalignore` first, then `patterns` in order — later patterns win,
+    /// so a config entry can `!re-include` something the file ignores.
+    pub fn load(root: &Path, patterns: &[String]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+        let file = root.join(IGNORE_FILE);
+        if file.exists() {
+            if let Some(e) = builder.add(&file) {
+                return Err(crate::error::HeimdallError::Config(format!(
+                    "{}: {}",
+                    file.display(),
+                    e
+                ))
+                .into());
+            }
+        }
+        for pattern in patterns {
+            builder.add_line(None, pattern).map_err(|e| {
+                crate::error::HeimdallError::Config(format!(
+                    "invalid ignore pattern '{}': {}",
+                    pattern, e
+                ))
+            })?;
+        }
+        let matcher = builder
+            .build()
+            .map_err(|e| crate::error::HeimdallError::Config(e.to_string()))?;
+        Ok(Self { matcher })
+    }
+
+    /// Global `ignore:` followed by the resolved profile's own patterns.
+    pub fn for_profile(root: &Path, config: &HeimdalConfig, profile: &Profile) -> Result<Self> {
+        let patterns: Vec<String> = config
+            .ignore
+            .iter()
+            .chain(&profile.ignore)
+            .cloned()
+            .collect();
+        Self::load(root, &patterns)
+    }
+
+    /// True if `path` (absolute under the root, or relative to it) or any of
+    /// its parent directories is ignored. Paths outside the root never are.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let rel = if path.is_absolute() {
+            match path.strip_prefix(self.matcher.path()) {
+                Ok(rel) => rel,
+                Err(_) => return false,
+            }
+        } else {
+            path
+        };
+        if rel.as_os_str().is_empty() {
+            return false;
+        }
+        let is_dir = self.matcher.path().join(rel).is_dir();
+        self.matcher
+            .matched_path_or_any_parents(rel, is_dir)
+            .is_ignore()
+    }
+
+    /// True if anything inside directory `dir` is ignored. Such a directory
+    /// cannot be folded into one symlink without exposing the ignored entries.
+    pub fn any_within(&self, dir: &Path) -> bool {
+        if self.matcher.is_empty() {
+            return false;
+        }
+        WalkDir::new(dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .any(|e| self.is_ignored(e.path()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn rules(tmp: &TempDir, patterns: &[&str]) -> IgnoreRules {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        IgnoreRules::load(tmp.path(), &patterns).unwrap()
+    }
+
+    #[test]
+    fn glob_and_negation() {
+        let tmp = TempDir::new().unwrap();
+        let r = rules(&tmp, &["*.md", "!KEEP.md"]);
+        assert!(r.is_ignored(Path::new("README.md")));
+        assert!(r.is_ignored(&tmp.path().join("nvim").join("notes.md")));
+        assert!(!r.is_ignored(Path::new("KEEP.md")));
+        assert!(!r.is_ignored(Path::new(".vimrc")));
+    }
+
+    #[test]
+    fn directory_only_pattern() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::create_dir(tmp.path().join("cache")).unwrap();
+        std::fs::write(tmp.path().join("cache").join("x"), "").unwrap();
+        std::fs::write(tmp.path().join("cachefile"), "").unwrap();
+        let r = rules(&tmp, &["cache/"]);
+        assert!(r.is_ignored(&tmp.path().join("cache")));
+        assert!(r.is_ignored(&tmp.path().join("cache").join("x")));
+        assert!(!r.is_ignored(&tmp.path().join("cachefile")));
+        assert!(r.any_within(tmp.path()));
+    }
+
+    #[test]
+    fn reads_heimdalignore_file() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(tmp.path().join(IGNORE_FILE), "# comment\n.DS_Store\n").unwrap();
+        let r = rules(&tmp, &[]);
+        assert!(r.is_ignored(Path::new(".config/.DS_Store")));
+        assert!(!r.is_ignored(Path::new(".config")));
+    }
+
+    #[test]
+    fn config_patterns_override_file() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(tmp.path().join(IGNORE_FILE), "*.local\n").unwrap();
+        let r = rules(&tmp, &["!.zshrc.local"]);
+        assert!(!r.is_ignored(Path::new(".zshrc.local")));
+        assert!(r.is_ignored(Path::new(".bashrc.local")));
+    }
+
+    #[test]
+    fn paths_outside_root_are_not_ignored() {
+        let tmp = TempDir::new().unwrap();
+        let r = rules(&tmp, &["*"]);
+        assert!(!r.is_ignored(Path::new("/somewhere/else")));
+    }
+}
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::path::Path;
 
 use crate::config::{DotfileEntry, DotfileMapping, HeimdalConfig, HeimdalMeta, Profile};
+use crate::ignore_rules::IgnoreRules;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum SourceTool {
@@ -74,13 +75,20 @@ pub fn import_from(path: &Path, tool: Option<SourceTool>) -> Result<ImportResult
         None => detect_tool(path).unwrap_or(SourceTool::Stow), // default to stow-style walk
     };
 
-    match resolved_tool {
+    let mut result = match resolved_tool {
         SourceTool::Stow => import_stow(path),
         SourceTool::Dotbot => import_dotbot(path),
         SourceTool::Chezmoi => import_chezmoi(path),
         SourceTool::Yadm => import_yadm(path),
         SourceTool::Homesick => import_homesick(path),
-    }
+    }?;
+
+    // A `.heimdalignore` already in the source repo applies to the import too
+    let rules = IgnoreRules::load(path, &[])?;
+    result
+        .dotfiles
+        .retain(|(src, _)| !rules.is_ignored(Path::new(src)));
+    Ok(result)
 }
 
 /// Stow: each top-level file/dir maps to ~/.<name>
@@ -89,6 +97,7 @@ fn import_stow(path: &Path) -> Result<ImportResult> {
         ".git",
         ".stowrc",
         ".stow-local-ignore",
+        crate::ignore_rules::IGNORE_FILE,
         "README.md",
         "LICENSE",
         ".DS_Store",
@@ -273,6 +282,10 @@ pub fn generate_heimdal_yaml(result: &ImportResult, profile_name: &str) -> Resul
                 source: src.clone(),
                 target: target.clone(),
                 when: None,
+                mode: Default::default(),
+                relative_links: None,
+                file_mode: None,
+                dir_mode: None,
             }));
         }
     }
@@ -290,11 +303,16 @@ pub fn generate_heimdal_yaml(result: &ImportResult, profile_name: &str) -> Resul
         heimdal: HeimdalMeta {
             version: "1".to_string(),
             repo: None,
+            relative_links: false,
         },
+        include: vec![],
         profiles,
+        mixins: HashMap::new(),
         packages: crate::config::PackageMap::default(),
         ignore: vec![],
+        variables: HashMap::new(),
         history: None,
+        sources: Default::default(),
     };
 
     Ok(serde_yaml_ng::to_string(&config)?)
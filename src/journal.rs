@@ -0,0 +1,458 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::permissions::FileMode;
+use crate::utils::move_path;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// One filesystem mutation made during apply, with enough detail to undo it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalOp {
+    CreatedDir {
+        path: PathBuf,
+    },
+    /// A symlink to `source` was made at `path`. Journals left by older
+    /// versions have no `source`; any link at `path` is taken to be theirs.
+    CreatedLink {
+        path: PathBuf,
+        #[serde(default)]
+        source: Option<PathBuf>,
+    },
+    /// A copied or hardlinked file (or copied directory tree) was placed.
+    CreatedCopy {
+        path: PathBuf,
+    },
+    /// `path` was moved into the journal stash instead of being deleted.
+    Removed {
+        path: PathBuf,
+        stash: PathBuf,
+    },
+    /// `path` was moved to the backup directory.
+    Backup {
+        path: PathBuf,
+        backup: PathBuf,
+    },
+    /// `path` was written; any previous content was stashed first.
+    Wrote {
+        path: PathBuf,
+        stash: Option<PathBuf>,
+    },
+}
+
+/// Append-only record of an in-progress apply. Every op is flushed to
+/// `~/.heimdal/journal/journal.jsonl` before the change it describes is
+/// made, so a run killed outright can still be rolled back by the next
+/// `heimdal apply`.
+pub struct Journal {
+    dir: PathBuf,
+    /// `journal.jsonl`, kept open for the whole run.
+    log: std::fs::File,
+    ops: Mutex<Vec<JournalOp>>,
+    /// Stash names are handed out up front so parallel deploys never share one.
+    stashes: AtomicUsize,
+}
+
+impl Journal {
+    pub fn begin() -> Result<Self> {
+        let dir = crate::utils::journal_dir()?;
+        std::fs::create_dir_all(dir.join("stash"))?;
+        std::fs::write(dir.join("journal.jsonl"), "")?;
+        let log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("journal.jsonl"))?;
+        Ok(Self {
+            dir,
+            log,
+            ops: Mutex::new(Vec::new()),
+            stashes: AtomicUsize::new(0),
+        })
+    }
+
+    fn record(&self, op: JournalOp) -> Result<()> {
+        {
+            let mut ops = self.ops.lock().unwrap();
+            writeln!(&self.log, "{}", serde_json::to_string(&op)?)?;
+            ops.push(op);
+        }
+        // Outside the lock, so parallel deploys share syncs instead of
+        // queueing for one each; the caller still waits for its op to land
+        self.log.sync_data()?;
+        Ok(())
+    }
+
+    fn next_stash(&self) -> PathBuf {
+        let n = self.stashes.fetch_add(1, Ordering::Relaxed);
+        self.dir.join("stash").join(format!("{}", n))
+    }
+
+    pub fn change_count(&self) -> usize {
+        self.ops.lock().unwrap().len()
+    }
+
+    /// Move `path` out of the way instead of deleting it.
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        let stash = self.next_stash();
+        self.record(JournalOp::Removed {
+            path: path.to_owned(),
+            stash: stash.clone(),
+        })?;
+        Ok(move_path(path, &stash)?)
+    }
+
+    pub fn backup(&self, path: &Path, backup: &Path) -> Result<()> {
+        self.record(JournalOp::Backup {
+            path: path.to_owned(),
+            backup: backup.to_owned(),
+        })?;
+        Ok(move_path(path, backup)?)
+    }
+
+    /// `create_dir_all` that records each directory it actually creates,
+    /// giving each `mode` from the start.
+    pub fn create_dir_all(&self, path: &Path, mode: Option<FileMode>) -> Result<()> {
+        let missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+            .collect();
+        for dir in missing.into_iter().rev() {
+            self.record(JournalOp::CreatedDir {
+                path: dir.to_owned(),
+            })?;
+            match crate::permissions::create_dir(dir, mode) {
+                Ok(()) => {}
+                // Another worker created it first; its op undoes it
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && dir.is_dir() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that a symlink to `source` is about to be created at `path`.
+    pub fn creating_link(&self, path: &Path, source: &Path) -> Result<()> {
+        self.record(JournalOp::CreatedLink {
+            path: path.to_owned(),
+            source: Some(source.to_owned()),
+        })
+    }
+
+    /// Record that a copy or hardlink is about to be placed at `path`.
+    pub fn creating_copy(&self, path: &Path) -> Result<()> {
+        self.record(JournalOp::CreatedCopy {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Write `content` to `path` as a new file created with `mode`, after
+    /// stashing what was there.
+    pub fn write(&self, path: &Path, content: &str, mode: Option<FileMode>) -> Result<()> {
+        let stash = (path.exists() || path.is_symlink()).then(|| self.next_stash());
+        self.record(JournalOp::Wrote {
+            path: path.to_owned(),
+            stash: stash.clone(),
+        })?;
+        if let Some(stash) = &stash {
+            move_path(path, stash)?;
+        }
+        crate::permissions::create_file(path, content, mode)?;
+        Ok(())
+    }
+
+    /// Undo every recorded op in reverse order, then discard the journal.
+    /// If any step fails, the journal and stash are kept for the next run
+    /// to retry, since the stash may hold the only copy of a file.
+    pub fn rollback(&self) -> Result<()> {
+        let ops = std::mem::take(&mut *self.ops.lock().unwrap());
+        let failed = undo(&ops);
+        if !failed.is_empty() {
+            return keep_unfinished(&self.dir, &failed);
+        }
+        self.finish()
+    }
+
+    /// Apply succeeded: drop the journal and everything stashed with it.
+    pub fn commit(&self) -> Result<()> {
+        self.ops.lock().unwrap().clear();
+        self.finish()
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Undo `ops` in reverse order. Returns the ops that could not be undone,
+/// in journal order.
+fn undo(ops: &[JournalOp]) -> Vec<JournalOp> {
+    let mut failed = Vec::new();
+    for op in ops.iter().rev() {
+        if let Err(e) = undo_one(op) {
+            crate::utils::warning(&format!("Rollback step failed ({:?}): {}", op, e));
+            failed.push(op.clone());
+        }
+    }
+    failed.reverse();
+    failed
+}
+
+/// Ops are journaled before they run, so the change an op describes may
+/// never have happened; then there is nothing to undo.
+fn undo_one(op: &JournalOp) -> std::io::Result<()> {
+    let present = |path: &Path| path.exists() || path.is_symlink();
+    match op {
+        JournalOp::CreatedDir { path } if path.is_dir() => std::fs::remove_dir(path),
+        // Only the link this op made; one already there made creating it fail
+        JournalOp::CreatedLink { path, source }
+            if path.is_symlink()
+                && source
+                    .as_ref()
+                    .map_or(true, |s| crate::symlink::points_to(path, s)) =>
+        {
+            std::fs::remove_file(path)
+        }
+        JournalOp::CreatedCopy { path } if path.is_dir() && !path.is_symlink() => {
+            std::fs::remove_dir_all(path)
+        }
+        JournalOp::CreatedCopy { path } if present(path) => std::fs::remove_file(path),
+        JournalOp::Removed { path, stash } if present(stash) => move_path(stash, path),
+        JournalOp::Backup { path, backup } if present(backup) => move_path(backup, path),
+        JournalOp::Wrote {
+            path,
+            stash: Some(stash),
+        } if present(stash) => {
+            if present(path) {
+                std::fs::remove_file(path)?;
+            }
+            move_path(stash, path)
+        }
+        JournalOp::Wrote { path, stash: None } if present(path) => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+/// Leave only the ops still to undo in the journal, and keep the stash, so
+/// the next `heimdal apply` tries again instead of losing what it holds.
+fn keep_unfinished(dir: &Path, failed: &[JournalOp]) -> Result<()> {
+    let mut lines = String::new();
+    for op in failed {
+        lines.push_str(&serde_json::to_string(op)?);
+        lines.push('\n');
+    }
+    std::fs::write(dir.join("journal.jsonl"), lines)?;
+    anyhow::bail!(
+        "{} change(s) could not be rolled back; the journal and stashed files are kept in {}",
+        failed.len(),
+        dir.display()
+    )
+}
+
+/// Roll back a journal left behind by an apply that was killed before it
+/// could clean up. Returns true if there was anything to recover.
+pub fn recover() -> Result<bool> {
+    let dir = crate::utils::journal_dir()?;
+    let path = dir.join("journal.jsonl");
+    if !path.exists() {
+        return Ok(false);
+    }
+    let file = std::fs::File::open(&path)?;
+    let ops: Vec<JournalOp> = std::io::BufReader::new(file)
+        .lines()
+        .map_while(|l| l.ok())
+        .filter_map(|l| serde_json::from_str(&l).ok())
+        .collect();
+    if !ops.is_empty() {
+        crate::utils::warning(&format!(
+            "A previous apply was interrupted — rolling back {} change(s)",
+            ops.len()
+        ));
+        let failed = undo(&ops);
+        if !failed.is_empty() {
+            keep_unfinished(&dir, &failed)?;
+        }
+    }
+    std::fs::remove_dir_all(&dir)?;
+    Ok(!ops.is_empty())
+}
+
+/// Route Ctrl-C into a flag that apply polls, so an interrupted run unwinds
+/// through the normal error path and rolls back instead of dying mid-way.
+pub fn install_interrupt_handler() {
+    // set_handler fails if called twice in one process (e.g. sync → apply)
+    let _ = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst));
+}
+
+pub fn check_interrupted() -> Result<()> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        anyhow::bail!("Interrupted");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn journal(tmp: &TempDir) -> Journal {
+        let dir = tmp.path().join("journal");
+        std::fs::create_dir_all(dir.join("stash")).unwrap();
+        std::fs::write(dir.join("journal.jsonl"), "").unwrap();
+        Journal {
+            log: std::fs::OpenOptions::new()
+                .append(true)
+                .open(dir.join("journal.jsonl"))
+                .unwrap(),
+            dir,
+            ops: Mutex::new(Vec::new()),
+            stashes: AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn rollback_restores_removed_and_overwritten_files() {
+        let tmp = TempDir::new().unwrap();
+        let j = journal(&tmp);
+        let removed = tmp.path().join("removed");
+        std::fs::write(&removed, "keep me").unwrap();
+        let written = tmp.path().join("written");
+        std::fs::write(&written, "before").unwrap();
+
+        j.remove(&removed).unwrap();
+        j.write(&written, "after", None).unwrap();
+        assert!(!removed.exists());
+        j.rollback().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&removed).unwrap(), "keep me");
+        assert_eq!(std::fs::read_to_string(&written).unwrap(), "before");
+        assert!(!tmp.path().join("journal").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_creates_file_and_dirs_with_their_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = TempDir::new().unwrap();
+        let j = journal(&tmp);
+        let dir = tmp.path().join("ssh");
+        let config = dir.join("config");
+        j.create_dir_all(&dir, Some(FileMode(0o700))).unwrap();
+        j.write(&config, "secret", Some(FileMode(0o600))).unwrap();
+
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&config), 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_removes_created_dirs_and_links() {
+        let tmp = TempDir::new().unwrap();
+        let j = journal(&tmp);
+        let nested = tmp.path().join("a").join("b");
+        j.create_dir_all(&nested, None).unwrap();
+        let link = nested.join("link");
+        j.creating_link(&link, tmp.path()).unwrap();
+        std::os::unix::fs::symlink(tmp.path(), &link).unwrap();
+        assert_eq!(j.change_count(), 3);
+
+        j.rollback().unwrap();
+
+        assert!(!tmp.path().join("a").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_keeps_link_it_did_not_make() {
+        let tmp = TempDir::new().unwrap();
+        let j = journal(&tmp);
+        let link = tmp.path().join("link");
+        let theirs = tmp.path().join("theirs");
+        std::os::unix::fs::symlink(&theirs, &link).unwrap();
+        // Creating ours then fails, as the link is already there
+        j.creating_link(&link, &tmp.path().join("ours")).unwrap();
+
+        j.rollback().unwrap();
+
+        assert_eq!(std::fs::read_link(&link).unwrap(), theirs);
+    }
+
+    #[test]
+    fn rollback_passes_over_ops_that_never_ran() {
+        let tmp = TempDir::new().unwrap();
+        let j = journal(&tmp);
+        let existing = tmp.path().join("existing");
+        std::fs::write(&existing, "mine").unwrap();
+        // Journaled, then killed before the change was made
+        j.record(JournalOp::Removed {
+            path: existing.clone(),
+            stash: j.next_stash(),
+        })
+        .unwrap();
+        j.record(JournalOp::Wrote {
+            path: existing.clone(),
+            stash: Some(j.next_stash()),
+        })
+        .unwrap();
+
+        j.rollback().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "mine");
+    }
+
+    #[test]
+    fn failed_rollback_keeps_journal_and_stash() {
+        let tmp = TempDir::new().unwrap();
+        let j = journal(&tmp);
+        let f = tmp.path().join("f");
+        std::fs::write(&f, "only copy").unwrap();
+        j.remove(&f).unwrap();
+        // Something now in the way of moving the original back
+        std::fs::create_dir_all(f.join("in-the-way")).unwrap();
+
+        assert!(j.rollback().is_err());
+
+        let stash = tmp.path().join("journal").join("stash").join("0");
+        assert_eq!(std::fs::read_to_string(stash).unwrap(), "only copy");
+        let log =
+            std::fs::read_to_string(tmp.path().join("journal").join("journal.jsonl")).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.contains("\"op\":\"removed\""));
+    }
+
+    #[test]
+    fn rollback_moves_backup_back() {
+        let tmp = TempDir::new().unwrap();
+        let j = journal(&tmp);
+        let orig = tmp.path().join("orig");
+        std::fs::write(&orig, "x").unwrap();
+        let backup = tmp.path().join("backups").join("orig.bak");
+        j.backup(&orig, &backup).unwrap();
+        assert!(backup.exists());
+
+        j.rollback().unwrap();
+
+        assert!(!backup.exists());
+        assert_eq!(std::fs::read_to_string(&orig).unwrap(), "x");
+    }
+
+    #[test]
+    fn commit_discards_stash() {
+        let tmp = TempDir::new().unwrap();
+        let j = journal(&tmp);
+        let f = tmp.path().join("f");
+        std::fs::write(&f, "x").unwrap();
+        j.remove(&f).unwrap();
+        j.commit().unwrap();
+        assert!(!f.exists());
+        assert!(!tmp.path().join("journal").exists());
+    }
+}
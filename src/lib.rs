@@ -1,17 +1,30 @@
+pub mod backups;
+pub mod blocks;
 pub mod cli;
 pub mod commands;
+pub mod condition;
 pub mod config;
 pub mod crypto;
 pub mod error;
 pub mod git;
 pub mod history;
 pub mod hooks;
+pub mod ignore_rules;
 pub mod import;
+pub mod journal;
 pub mod key;
+pub mod manifest;
+pub mod merge;
 pub mod packages;
+pub mod parallel;
+pub mod permissions;
+pub mod plan;
 pub mod profile;
+pub mod schema;
 pub mod secrets;
 pub mod state;
 pub mod symlink;
 pub mod templates;
 pub mod utils;
+pub mod validation;
+pub mod yaml_edit;
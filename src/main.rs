@@ -1,20 +1,33 @@
+mod backups;
+mod blocks;
 mod cli;
 mod commands;
+mod condition;
 mod config;
 mod crypto;
 mod error;
 mod git;
 mod history;
 mod hooks;
+mod ignore_rules;
 mod import;
+mod journal;
 mod key;
+mod manifest;
+mod merge;
 mod packages;
+mod parallel;
+mod permissions;
+mod plan;
 mod profile;
+mod schema;
 mod secrets;
 mod state;
 mod symlink;
 mod templates;
 mod utils;
+mod validation;
+mod yaml_edit;
 
 use anyhow::Result;
 use clap::Parser;
@@ -40,6 +53,10 @@ fn run(cli: Cli) -> Result<()> {
     match cli.command {
         Commands::Init(args) => commands::init::run(args),
         Commands::Apply(args) => commands::apply::run(args),
+        Commands::Plan(args) => commands::plan::run(args),
+        Commands::Unlink(args) => commands::unlink::run(args),
+        Commands::Add(args) => commands::add::run(args),
+        Commands::Backups { action } => commands::backups::run(action),
         Commands::Status(args) => commands::status::run(args),
         Commands::Sync(args) => commands::sync::run(args),
         Commands::Diff(args) => commands::diff::run(args),
@@ -51,6 +68,7 @@ fn run(cli: Cli) -> Result<()> {
         Commands::Import(args) => commands::import::run(args),
         Commands::Wizard => commands::wizard::run(),
         Commands::Validate(args) => commands::validate::run(args),
+        Commands::Schema(args) => commands::schema::run(args),
         Commands::Rollback(args) => commands::rollback::run(args),
         Commands::State { action } => commands::state::run(action),
         Commands::AutoSync { action } => commands::autosync::run(action),
@@ -0,0 +1,288 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::config::DeployMode;
+
+/// What heimdal put at a target path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployKind {
+    Symlink,
+    Copy,
+    Hardlink,
+    Template,
+    /// A marked block inside a file heimdal does not own.
+    Block,
+    /// Keys deep-merged into a structured file heimdal does not own.
+    Merge,
+}
+
+impl From<DeployMode> for DeployKind {
+    fn from(mode: DeployMode) -> Self {
+        match mode {
+            DeployMode::Symlink => DeployKind::Symlink,
+            DeployMode::Copy => DeployKind::Copy,
+            DeployMode::Hardlink => DeployKind::Hardlink,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub target: PathBuf,
+    pub source: PathBuf,
+    pub kind: DeployKind,
+    /// blake3 of the deployed content: the source for links and copies, the
+    /// rendered output for templates, the block body for blocks. `None` when
+    /// the path could not be read.
+    pub hash: Option<String>,
+    pub profile: String,
+    pub deployed_at: DateTime<Utc>,
+    /// Block id for `Block` entries. One target can hold several blocks, so
+    /// entries are keyed by target and block together.
+    #[serde(default)]
+    pub block: Option<String>,
+}
+
+/// Machine-local record of every file heimdal deployed, kept next to
+/// `state.json` so orphaned links can be found after config changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub entries: Vec<ManifestEntry>,
+    /// Position of each (target, block) in `entries`, built on first lookup
+    /// so recording thousands of targets is not quadratic.
+    #[serde(skip)]
+    index: OnceCell<HashMap<(PathBuf, Option<String>), usize>>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            version: 1,
+            entries: Vec::new(),
+            index: OnceCell::new(),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn path() -> Result<PathBuf> {
+        crate::utils::manifest_path()
+    }
+
+    /// Load the manifest, returning an empty one if nothing was deployed yet.
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&content).map_err(|e| {
+            crate::error::HeimdallError::State(format!("manifest {}: {}", path.display(), e)).into()
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Insert or replace the entry for `target`.
+    pub fn record(
+        &mut self,
+        target: &Path,
+        source: &Path,
+        kind: DeployKind,
+        hash: Option<String>,
+        profile: &str,
+    ) {
+        self.upsert(target, None, source, kind, hash, profile);
+    }
+
+    /// Insert or replace the entry for block `id` in `target`.
+    pub fn record_block(
+        &mut self,
+        target: &Path,
+        id: &str,
+        source: &Path,
+        hash: Option<String>,
+        profile: &str,
+    ) {
+        self.upsert(target, Some(id), source, DeployKind::Block, hash, profile);
+    }
+
+    fn upsert(
+        &mut self,
+        target: &Path,
+        block: Option<&str>,
+        source: &Path,
+        kind: DeployKind,
+        hash: Option<String>,
+        profile: &str,
+    ) {
+        let entry = ManifestEntry {
+            target: target.to_owned(),
+            source: source.to_owned(),
+            kind,
+            hash,
+            profile: profile.to_string(),
+            deployed_at: Utc::now(),
+            block: block.map(str::to_string),
+        };
+        let key = (entry.target.clone(), entry.block.clone());
+        match self.position(target, block) {
+            Some(i) => self.entries[i] = entry,
+            None => {
+                self.entries.push(entry);
+                let i = self.entries.len() - 1;
+                if let Some(index) = self.index.get_mut() {
+                    index.insert(key, i);
+                }
+            }
+        }
+    }
+
+    fn position(&self, target: &Path, block: Option<&str>) -> Option<usize> {
+        let index = self.index.get_or_init(|| {
+            self.entries
+                .iter()
+                .enumerate()
+                .map(|(i, e)| ((e.target.clone(), e.block.clone()), i))
+                .collect()
+        });
+        index
+            .get(&(target.to_owned(), block.map(str::to_string)))
+            .copied()
+    }
+
+    pub fn get(&self, target: &Path) -> Option<&ManifestEntry> {
+        self.position(target, None).map(|i| &self.entries[i])
+    }
+
+    pub fn get_block(&self, target: &Path, id: &str) -> Option<&ManifestEntry> {
+        self.position(target, Some(id)).map(|i| &self.entries[i])
+    }
+
+    pub fn remove(&mut self, target: &Path) {
+        self.entries
+            .retain(|e| !(e.target == target && e.block.is_none()));
+        self.index = OnceCell::new();
+    }
+
+    pub fn remove_block(&mut self, target: &Path, id: &str) {
+        self.entries
+            .retain(|e| !(e.target == target && e.block.as_deref() == Some(id)));
+        self.index = OnceCell::new();
+    }
+
+    pub fn for_profile<'a>(&'a self, profile: &'a str) -> impl Iterator<Item = &'a ManifestEntry> {
+        self.entries.iter().filter(move |e| e.profile == profile)
+    }
+
+    /// Entries whose target is not part of the current deployment.
+    pub fn orphans(&self, current: &HashSet<PathBuf>) -> Vec<ManifestEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.block.is_none() && !current.contains(&e.target))
+            .cloned()
+            .collect()
+    }
+
+    /// Block entries whose (target, id) is not part of the current deployment.
+    pub fn orphan_blocks(&self, current: &HashSet<(PathBuf, String)>) -> Vec<ManifestEntry> {
+        self.entries
+            .iter()
+            .filter(|e| {
+                e.block
+                    .as_ref()
+                    .is_some_and(|id| !current.contains(&(e.target.clone(), id.clone())))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// blake3 of a file, or of every file under a directory (paths included so
+/// renames change the hash). Returns `None` if the path cannot be read.
+pub fn hash_path(path: &Path) -> Option<String> {
+    if !path.is_dir() {
+        return std::fs::read(path)
+            .ok()
+            .map(|bytes| blake3::hash(&bytes).to_hex().to_string());
+    }
+    let mut hasher = blake3::Hasher::new();
+    for entry in WalkDir::new(path).sort_by_file_name() {
+        let entry = entry.ok()?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(path).ok()?;
+        hasher.update(rel.to_string_lossy().as_bytes());
+        hasher.update(&std::fs::read(entry.path()).ok()?);
+    }
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn record_replaces_existing_target() {
+        let mut m = Manifest::default();
+        let target = Path::new("/home/u/.vimrc");
+        m.record(target, Path::new("/d/a"), DeployKind::Symlink, None, "p");
+        m.record(target, Path::new("/d/b"), DeployKind::Symlink, None, "p");
+        assert_eq!(m.entries.len(), 1);
+        assert_eq!(m.entries[0].source, Path::new("/d/b"));
+    }
+
+    #[test]
+    fn orphans_are_entries_missing_from_current_set() {
+        let mut m = Manifest::default();
+        m.record(
+            Path::new("/h/a"),
+            Path::new("/d/a"),
+            DeployKind::Symlink,
+            None,
+            "p",
+        );
+        m.record(
+            Path::new("/h/b"),
+            Path::new("/d/b"),
+            DeployKind::Symlink,
+            None,
+            "p",
+        );
+        let current: HashSet<PathBuf> = [PathBuf::from("/h/a")].into_iter().collect();
+        let orphans = m.orphans(&current);
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].target, Path::new("/h/b"));
+    }
+
+    #[test]
+    fn hash_path_changes_with_directory_contents() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(tmp.path().join("a"), "1").unwrap();
+        let before = hash_path(tmp.path()).unwrap();
+        std::fs::write(tmp.path().join("a"), "2").unwrap();
+        assert_ne!(before, hash_path(tmp.path()).unwrap());
+        assert_eq!(
+            hash_path(&tmp.path().join("a")).unwrap(),
+            blake3::hash(b"2").to_hex().to_string()
+        );
+    }
+}
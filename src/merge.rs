@@ -0,0 +1,458 @@
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+use crate::config::{ArrayStrategy, MergeEntry};
+use crate::journal::Journal;
+use crate::manifest::ManifestEntry;
+use crate::utils::{expand_path, rewrite_file};
+
+/// File formats a `merge:` entry can read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl MergeFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" | "jsonc" | "code-workspace" => Some(MergeFormat::Json),
+            "yaml" | "yml" => Some(MergeFormat::Yaml),
+            "toml" => Some(MergeFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// Parse `text` into the JSON data model all merging happens in. An empty
+    /// file is an empty document. JSON may carry comments and trailing commas
+    /// as editors allow, but they are not kept when the file is rewritten.
+    pub fn parse(self, text: &str) -> Result<Value> {
+        if text.trim().is_empty() {
+            return Ok(Value::Object(Map::new()));
+        }
+        Ok(match self {
+            MergeFormat::Json => serde_json::from_str(&strip_jsonc(text))?,
+            MergeFormat::Yaml => serde_yaml_ng::from_str(text)?,
+            MergeFormat::Toml => serde_json::to_value(toml::from_str::<toml::Value>(text)?)?,
+        })
+    }
+
+    /// Serialize `value`, indenting JSON the way `previous` was indented.
+    pub fn render(self, value: &Value, previous: &str) -> Result<String> {
+        Ok(match self {
+            MergeFormat::Json => {
+                let indent = json_indent(previous);
+                let mut out = Vec::new();
+                let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+                let mut ser = serde_json::Serializer::with_formatter(&mut out, formatter);
+                value.serialize(&mut ser)?;
+                out.push(b'\n');
+                String::from_utf8(out)?
+            }
+            MergeFormat::Yaml => serde_yaml_ng::to_string(value)?,
+            MergeFormat::Toml => {
+                toml::to_string_pretty(&serde_json::from_value::<toml::Value>(value.clone())?)?
+            }
+        })
+    }
+}
+
+/// One heimdal-owned key whose value a merge sets or would set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyChange {
+    /// Dotted path from the document root.
+    pub key: String,
+    pub old: Option<Value>,
+    pub new: Value,
+}
+
+impl KeyChange {
+    pub fn describe(&self) -> String {
+        match &self.old {
+            None => format!("+ {} = {}", self.key, self.new),
+            Some(old) => format!("~ {}: {} → {}", self.key, old, self.new),
+        }
+    }
+}
+
+/// Deep-merge `fragment` into `base`. Objects merge key by key, scalars from
+/// the fragment win, and arrays follow `arrays`. Keys only `base` has are the
+/// app's and are never touched. Returns the keys whose value changed.
+pub fn merge(base: &mut Value, fragment: &Value, arrays: ArrayStrategy) -> Vec<KeyChange> {
+    let mut changes = Vec::new();
+    merge_at(base, fragment, arrays, &mut Vec::new(), &mut changes);
+    changes
+}
+
+fn merge_at(
+    base: &mut Value,
+    fragment: &Value,
+    arrays: ArrayStrategy,
+    path: &mut Vec<String>,
+    changes: &mut Vec<KeyChange>,
+) {
+    if let (Value::Object(base_map), Value::Object(frag_map)) = (&mut *base, fragment) {
+        for (key, frag_value) in frag_map {
+            path.push(key.clone());
+            match base_map.get_mut(key) {
+                Some(existing) => merge_at(existing, frag_value, arrays, path, changes),
+                None => {
+                    base_map.insert(key.clone(), frag_value.clone());
+                    changes.push(KeyChange {
+                        key: path.join("."),
+                        old: None,
+                        new: frag_value.clone(),
+                    });
+                }
+            }
+            path.pop();
+        }
+        return;
+    }
+
+    let merged = match (&*base, fragment) {
+        (Value::Array(existing), Value::Array(ours)) => {
+            Value::Array(merge_array(existing, ours, arrays))
+        }
+        _ => fragment.clone(),
+    };
+    if *base != merged {
+        let old = std::mem::replace(base, merged.clone());
+        changes.push(KeyChange {
+            key: path.join("."),
+            old: Some(old),
+            new: merged,
+        });
+    }
+}
+
+fn merge_array(existing: &[Value], ours: &[Value], arrays: ArrayStrategy) -> Vec<Value> {
+    match arrays {
+        ArrayStrategy::Replace => ours.to_vec(),
+        // Ours go last, moved there if already present, so reapplying is a no-op
+        ArrayStrategy::Append => existing
+            .iter()
+            .filter(|v| !ours.contains(v))
+            .chain(ours)
+            .cloned()
+            .collect(),
+        ArrayStrategy::Unique => {
+            let mut out: Vec<Value> = Vec::with_capacity(existing.len() + ours.len());
+            for v in existing.iter().chain(ours) {
+                if !out.contains(v) {
+                    out.push(v.clone());
+                }
+            }
+            out
+        }
+    }
+}
+
+/// Take out of `base` what merging `fragment` put there: keys still holding
+/// our value, and our items in appended arrays. Keys changed since are kept.
+/// Returns the dotted paths removed.
+pub fn unmerge(base: &mut Value, fragment: &Value, arrays: ArrayStrategy) -> Vec<String> {
+    let mut removed = Vec::new();
+    unmerge_at(base, fragment, arrays, &mut Vec::new(), &mut removed);
+    removed
+}
+
+fn unmerge_at(
+    base: &mut Value,
+    fragment: &Value,
+    arrays: ArrayStrategy,
+    path: &mut Vec<String>,
+    removed: &mut Vec<String>,
+) {
+    let (Value::Object(base_map), Value::Object(frag_map)) = (base, fragment) else {
+        return;
+    };
+    for (key, frag_value) in frag_map {
+        path.push(key.clone());
+        let drop_key = match (base_map.get_mut(key), frag_value) {
+            (Some(existing @ Value::Object(_)), Value::Object(_)) => {
+                unmerge_at(existing, frag_value, arrays, path, removed);
+                existing.as_object().is_some_and(|m| m.is_empty())
+            }
+            (Some(Value::Array(items)), Value::Array(ours)) if arrays != ArrayStrategy::Replace => {
+                let before = items.len();
+                items.retain(|v| !ours.contains(v));
+                if items.len() != before {
+                    removed.push(path.join("."));
+                }
+                items.is_empty()
+            }
+            (Some(existing), _) if existing == frag_value => {
+                removed.push(path.join("."));
+                true
+            }
+            _ => false,
+        };
+        if drop_key {
+            base_map.remove(key);
+        }
+        path.pop();
+    }
+}
+
+/// A fragment and the file it is merged into.
+pub struct MergeTarget {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    pub format: Option<MergeFormat>,
+    pub arrays: ArrayStrategy,
+}
+
+impl MergeTarget {
+    pub fn new(entry: &MergeEntry, dotfiles_dir: &Path) -> Self {
+        Self {
+            source: dotfiles_dir.join(&entry.source),
+            dest: expand_path(&entry.target),
+            format: entry.format,
+            arrays: entry.arrays,
+        }
+    }
+
+    /// The merge a manifest entry records, with its settings taken from the
+    /// profile's entry for the same target. Once that entry is gone from the
+    /// config the defaults apply.
+    pub fn recorded(entry: &ManifestEntry, merges: &[MergeEntry]) -> Self {
+        let config = merges
+            .iter()
+            .find(|m| expand_path(&m.target) == entry.target);
+        Self {
+            source: entry.source.clone(),
+            dest: entry.target.clone(),
+            format: config.and_then(|m| m.format),
+            arrays: config.map(|m| m.arrays).unwrap_or_default(),
+        }
+    }
+
+    fn format(&self) -> Result<MergeFormat> {
+        self.format
+            .or_else(|| MergeFormat::from_path(&self.dest))
+            .or_else(|| MergeFormat::from_path(&self.source))
+            .ok_or_else(|| {
+                crate::error::HeimdallError::Config(format!(
+                    "cannot tell the format of {}; set format: json, yaml or toml",
+                    self.dest.display()
+                ))
+                .into()
+            })
+    }
+
+    fn fragment(&self, target_format: MergeFormat) -> Result<Value> {
+        let text = std::fs::read_to_string(&self.source).map_err(|e| {
+            anyhow::anyhow!("Cannot read fragment {}: {}", self.source.display(), e)
+        })?;
+        let format = MergeFormat::from_path(&self.source).unwrap_or(target_format);
+        let fragment = format
+            .parse(&text)
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.source.display(), e))?;
+        if !fragment.is_object() {
+            anyhow::bail!(
+                "{}: a merge fragment must be a mapping of keys",
+                self.source.display()
+            );
+        }
+        Ok(fragment)
+    }
+
+    fn read(&self, format: MergeFormat) -> Result<(String, Value)> {
+        let text = match std::fs::read_to_string(&self.dest) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => anyhow::bail!("Cannot read {}: {}", self.dest.display(), e),
+        };
+        let value = format
+            .parse(&text)
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.dest.display(), e))?;
+        Ok((text, value))
+    }
+
+    /// Merge the fragment into the target file, writing it only if a key
+    /// changed. Returns the changes, for apply to report.
+    pub fn deploy(&self, dry_run: bool, journal: Option<&Journal>) -> Result<Vec<KeyChange>> {
+        let format = self.format()?;
+        let fragment = self.fragment(format)?;
+        let (text, mut value) = self.read(format)?;
+        let changes = merge(&mut value, &fragment, self.arrays);
+        if !changes.is_empty() && !dry_run {
+            rewrite_file(&self.dest, &format.render(&value, &text)?, journal)?;
+        }
+        Ok(changes)
+    }
+
+    /// Heimdal-owned keys whose value in the target differs from what the
+    /// fragment sets. Keys the app owns are not compared.
+    pub fn drift(&self) -> Result<Vec<KeyChange>> {
+        let format = self.format()?;
+        let fragment = self.fragment(format)?;
+        let (_, mut value) = self.read(format)?;
+        Ok(merge(&mut value, &fragment, self.arrays))
+    }
+
+    /// Remove our keys from the target. Returns the keys removed.
+    pub fn remove(&self, dry_run: bool, journal: Option<&Journal>) -> Result<Vec<String>> {
+        let format = self.format()?;
+        let fragment = self.fragment(format)?;
+        let (text, mut value) = self.read(format)?;
+        let removed = unmerge(&mut value, &fragment, self.arrays);
+        if !removed.is_empty() && !dry_run {
+            rewrite_file(&self.dest, &format.render(&value, &text)?, journal)?;
+        }
+        Ok(removed)
+    }
+}
+
+/// Indent unit of a pretty-printed JSON document: the leading whitespace of
+/// its first indented line, or two spaces.
+fn json_indent(text: &str) -> String {
+    text.lines()
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .find(|ws| !ws.is_empty())
+        .unwrap_or("  ")
+        .to_string()
+}
+
+/// Drop `//` and `/* */` comments and trailing commas, leaving strings alone.
+fn strip_jsonc(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' => {
+                out.push(c);
+                i += 1;
+                while i < chars.len() {
+                    out.push(chars[i]);
+                    if chars[i] == '\\' && i + 1 < chars.len() {
+                        out.push(chars[i + 1]);
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    if chars[i - 1] == '"' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 2;
+                continue;
+            }
+            ',' => {
+                let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+                if !matches!(next, Some('}') | Some(']')) {
+                    out.push(c);
+                }
+            }
+            _ => out.push(c),
+        }
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_keeps_app_keys_and_reports_changes() {
+        let mut base = json!({"editor": {"fontSize": 12, "wordWrap": "on"}, "telemetry": false});
+        let frag = json!({"editor": {"fontSize": 14, "tabSize": 2}});
+        let changes = merge(&mut base, &frag, ArrayStrategy::Replace);
+        assert_eq!(
+            base,
+            json!({"editor": {"fontSize": 14, "wordWrap": "on", "tabSize": 2}, "telemetry": false})
+        );
+        let keys: Vec<_> = changes.iter().map(|c| c.describe()).collect();
+        assert_eq!(
+            keys,
+            vec!["~ editor.fontSize: 12 → 14", "+ editor.tabSize = 2"]
+        );
+        assert!(merge(&mut base, &frag, ArrayStrategy::Replace).is_empty());
+    }
+
+    #[test]
+    fn array_strategies_are_idempotent() {
+        let frag = json!({"list": [2, 3]});
+        for (arrays, list, expected) in [
+            (ArrayStrategy::Replace, json!([1, 3, 1, 2]), json!([2, 3])),
+            (ArrayStrategy::Append, json!([1, 2, 1]), json!([1, 1, 2, 3])),
+            (ArrayStrategy::Unique, json!([1, 3, 1]), json!([1, 3, 2])),
+        ] {
+            let mut base = json!({ "list": list });
+            merge(&mut base, &frag, arrays);
+            assert_eq!(base["list"], expected, "{:?}", arrays);
+            assert!(merge(&mut base, &frag, arrays).is_empty(), "{:?}", arrays);
+        }
+    }
+
+    #[test]
+    fn unmerge_removes_only_unchanged_keys() {
+        let frag = json!({"a": 1, "b": 2, "nested": {"c": 3}, "list": ["x"]});
+        let mut base = json!({"keep": true, "list": ["mine"]});
+        merge(&mut base, &frag, ArrayStrategy::Unique);
+        base["b"] = json!(20);
+        let removed = unmerge(&mut base, &frag, ArrayStrategy::Unique);
+        assert_eq!(removed, vec!["a", "nested.c", "list"]);
+        assert_eq!(base, json!({"keep": true, "list": ["mine"], "b": 20}));
+    }
+
+    #[test]
+    fn jsonc_comments_and_trailing_commas() {
+        let text = "{\n  // comment\n  \"url\": \"http://x\", /* block */\n  \"a\": [1, 2,],\n}\n";
+        let v = MergeFormat::Json.parse(text).unwrap();
+        assert_eq!(v, json!({"url": "http://x", "a": [1, 2]}));
+    }
+
+    #[test]
+    fn toml_and_yaml_round_trip() {
+        let toml_text = "[server]\nport = 8080\n";
+        let mut v = MergeFormat::Toml.parse(toml_text).unwrap();
+        merge(
+            &mut v,
+            &json!({"server": {"host": "x"}}),
+            ArrayStrategy::Replace,
+        );
+        let out = MergeFormat::Toml.render(&v, toml_text).unwrap();
+        assert!(out.contains("port = 8080") && out.contains("host = \"x\""));
+
+        let mut y = MergeFormat::Yaml.parse("b: 1\na: 2\n").unwrap();
+        merge(&mut y, &json!({"c": 3}), ArrayStrategy::Replace);
+        assert_eq!(
+            MergeFormat::Yaml.render(&y, "").unwrap(),
+            "b: 1\na: 2\nc: 3\n"
+        );
+    }
+
+    #[test]
+    fn json_render_keeps_indent() {
+        let v = json!({"a": 1});
+        assert_eq!(
+            MergeFormat::Json.render(&v, "{\n    \"a\": 0\n}").unwrap(),
+            "{\n    \"a\": 1\n}\n"
+        );
+    }
+}
@@ -277,41 +277,30 @@ pub fn detect_manager() -> Option<Box<dyn PackageManager>> {
     managers.into_iter().find(|m| m.is_available())
 }
 
-/// Install packages for the active profile (called from apply command).
-pub fn install_for_profile(profile: &crate::config::Profile, dry_run: bool) -> Result<()> {
-    let managers: Vec<Box<dyn PackageManager>> = vec![
+fn all_managers() -> Vec<Box<dyn PackageManager>> {
+    vec![
         Box::new(Homebrew),
         Box::new(HomebrewCask),
         Box::new(Apt),
         Box::new(Dnf),
         Box::new(Pacman),
         Box::new(Apk),
-    ];
+    ]
+}
 
-    let pkgs = &profile.packages;
+/// What `install_for_profile` hands to each package manager, in order.
+/// Managers missing from this system are warned about and left out.
+pub fn install_batches(
+    profile: &crate::config::Profile,
+    active: &str,
+) -> Vec<(Box<dyn PackageManager>, Vec<String>)> {
+    let pkgs = &profile.packages.for_host(active);
+    let mut batches = Vec::new();
 
     // Install common packages via the first available package manager
     if !pkgs.common.is_empty() {
-        match managers.iter().find(|m| m.is_available()) {
-            Some(manager) => {
-                let results = manager.install_many(&pkgs.common, dry_run)?;
-                for r in &results {
-                    if r.success {
-                        if let Some(msg) = &r.message {
-                            crate::utils::info(msg);
-                        } else {
-                            crate::utils::step(&format!("Installed: {}", r.package));
-                        }
-                    } else {
-                        crate::utils::warning(&format!(
-                            "Failed to install '{}' via {}: {}",
-                            r.package,
-                            manager.name(),
-                            r.message.as_deref().unwrap_or("unknown error")
-                        ));
-                    }
-                }
-            }
+        match all_managers().into_iter().find(|m| m.is_available()) {
+            Some(manager) => batches.push((manager, pkgs.common.clone())),
             None => {
                 crate::utils::warning(&format!(
                     "No package manager available. Skipping {} common package(s).",
@@ -321,7 +310,7 @@ pub fn install_for_profile(profile: &crate::config::Profile, dry_run: bool) -> R
         }
     }
 
-    for manager in &managers {
+    for manager in all_managers() {
         let to_install = match manager.field_name() {
             "homebrew" => pkgs.homebrew.clone(),
             "homebrew_casks" => pkgs.homebrew_casks.clone(),
@@ -344,25 +333,54 @@ pub fn install_for_profile(profile: &crate::config::Profile, dry_run: bool) -> R
             ));
             continue;
         }
+        batches.push((manager, to_install));
+    }
+    batches
+}
+
+/// Install packages for the active profile (called from apply command).
+pub fn install_for_profile(
+    profile: &crate::config::Profile,
+    active: &str,
+    dry_run: bool,
+) -> Result<()> {
+    for (manager, pkgs) in install_batches(profile, active) {
+        let results = manager.install_many(&pkgs, dry_run)?;
+        report(manager.as_ref(), &results);
+    }
+    Ok(())
+}
+
+/// Install one package with the manager called `manager` (see
+/// `PackageManager::name`), as recorded in a saved plan.
+pub fn install_one(manager: &str, pkg: &str) -> Result<()> {
+    let Some(m) = all_managers().into_iter().find(|m| m.name() == manager) else {
+        return Err(crate::error::HeimdallError::Package {
+            manager: manager.to_string(),
+            reason: "unknown package manager".to_string(),
+        }
+        .into());
+    };
+    let results = m.install_many(&[pkg.to_string()], false)?;
+    report(m.as_ref(), &results);
+    Ok(())
+}
 
-        let results = manager.install_many(&to_install, dry_run)?;
-        for r in &results {
-            if r.success {
-                if let Some(msg) = &r.message {
-                    crate::utils::info(msg);
-                } else {
-                    crate::utils::step(&format!("Installed: {}", r.package));
-                }
+fn report(manager: &dyn PackageManager, results: &[InstallResult]) {
+    for r in results {
+        if r.success {
+            if let Some(msg) = &r.message {
+                crate::utils::info(msg);
             } else {
-                crate::utils::warning(&format!(
-                    "Failed to install '{}' via {}: {}",
-                    r.package,
-                    manager.name(),
-                    r.message.as_deref().unwrap_or("unknown error")
-                ));
+                crate::utils::step(&format!("Installed: {}", r.package));
             }
+        } else {
+            crate::utils::warning(&format!(
+                "Failed to install '{}' via {}: {}",
+                r.package,
+                manager.name(),
+                r.message.as_deref().unwrap_or("unknown error")
+            ));
         }
     }
-
-    Ok(())
 }
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Worker count used when `--jobs` is not given: one per core, capped so a
+/// large apply does not drown a slow disk in concurrent metadata calls.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(8)
+}
+
+/// Run `f` over `items` on at most `jobs` threads and return the results in
+/// the order of `items`, however the work was scheduled.
+pub fn map_ordered<T, R, F>(items: Vec<T>, jobs: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let workers = jobs.clamp(1, items.len().max(1));
+    if workers == 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let count = items.len();
+    let queue: Vec<Mutex<Option<T>>> = items.into_iter().map(|i| Mutex::new(Some(i))).collect();
+    let results: Vec<Mutex<Option<R>>> = (0..count).map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= count {
+                    break;
+                }
+                let item = queue[i]
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .expect("each item is taken once");
+                let result = f(item);
+                *results[i].lock().unwrap() = Some(result);
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|r| r.into_inner().unwrap().expect("every item was processed"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_keep_input_order() {
+        let items: Vec<u64> = (0..200).collect();
+        let out = map_ordered(items, 8, |n| {
+            // Later items finish first
+            std::thread::sleep(std::time::Duration::from_micros(200 - n));
+            n * 2
+        });
+        assert_eq!(out, (0..200).map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn single_job_and_empty_input() {
+        assert_eq!(map_ordered(vec![1, 2, 3], 1, |n| n + 1), vec![2, 3, 4]);
+        assert!(map_ordered(Vec::<u8>::new(), 4, |n| n).is_empty());
+    }
+}
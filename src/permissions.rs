@@ -0,0 +1,336 @@
+use anyhow::Result;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use walkdir::WalkDir;
+
+use crate::config::{DotfileEntry, HeimdalConfig};
+use crate::utils::expand_path;
+
+/// Directories whose contents only their owner should read, whatever the
+/// individual files are called.
+const PRIVATE_DIRS: &[&str] = &[".ssh", ".gnupg", ".aws", ".kube", ".docker"];
+
+/// Unix permission bits, written in config as an octal string (`"0600"`).
+/// A bare YAML number is read as octal digits too, so `600` means `0600`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode(pub u32);
+
+impl FileMode {
+    pub fn world_readable(self) -> bool {
+        self.0 & 0o004 != 0
+    }
+}
+
+impl FromStr for FileMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.trim().trim_start_matches("0o");
+        match u32::from_str_radix(digits, 8) {
+            Ok(bits) if !digits.is_empty() && bits <= 0o7777 => Ok(FileMode(bits)),
+            _ => Err(format!(
+                "invalid file mode '{}': expected octal like \"0600\"",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+impl Serialize for FileMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for FileMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Text(String),
+            Number(u64),
+        }
+        let text = match Raw::deserialize(deserializer)? {
+            Raw::Text(s) => s,
+            Raw::Number(n) => n.to_string(),
+        };
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for FileMode {
+    fn schema_name() -> String {
+        "FileMode".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject {
+            instance_type: Some(vec![InstanceType::String, InstanceType::Integer].into()),
+            ..Default::default()
+        };
+        schema.metadata().description = Some("Octal, like \"0600\".".to_string());
+        schema.into()
+    }
+}
+
+/// `file_mode` / `dir_mode` from one dotfile or template entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permissions {
+    /// Applied to the deployed file, or to every file in a deployed directory.
+    pub file: Option<FileMode>,
+    /// Applied to deployed directories and to parent directories heimdal creates.
+    pub dir: Option<FileMode>,
+}
+
+impl Permissions {
+    pub fn is_empty(&self) -> bool {
+        self.file.is_none() && self.dir.is_none()
+    }
+
+    /// Set the configured modes on `path`, recursing into directories.
+    /// Symlinks inside a directory are left alone.
+    pub fn enforce(&self, path: &Path) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+            let mode = if entry.file_type().is_dir() {
+                self.dir
+            } else if entry.file_type().is_file() {
+                self.file
+            } else {
+                None
+            };
+            if let Some(mode) = mode {
+                set_mode(entry.path(), mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Give the directories in `created` (from [`missing_ancestors`]) the
+    /// configured directory mode, now that they exist.
+    pub fn enforce_created(&self, created: &[PathBuf]) -> Result<()> {
+        if let Some(mode) = self.dir {
+            for dir in created.iter().filter(|d| d.is_dir()) {
+                set_mode(dir, mode)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parent directories of `path` that do not exist yet, outermost first.
+/// Taken before deploying so only directories heimdal creates get `dir_mode`.
+pub fn missing_ancestors(path: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|p| !p.as_os_str().is_empty() && std::fs::symlink_metadata(p).is_err())
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+    missing
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: FileMode) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode.0)).map_err(|e| {
+        crate::error::HeimdallError::Symlink {
+            path: path.display().to_string(),
+            reason: format!("cannot set mode {}: {}", mode, e),
+        }
+    })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: FileMode) -> Result<()> {
+    // Windows has no mode bits; access is governed by ACLs instead
+    Ok(())
+}
+
+/// Create `path`, which must not exist yet, holding `content`. The file is
+/// created with `mode` rather than tightened afterwards, so what it holds is
+/// never readable more widely than configured, even for a moment.
+pub fn create_file(path: &Path, content: &str, mode: Option<FileMode>) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode.0);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(path)?.write_all(content.as_bytes())
+}
+
+/// Create the directory `path`, whose parent exists, with `mode` from the start.
+pub fn create_dir(path: &Path, mode: Option<FileMode>) -> std::io::Result<()> {
+    dir_builder(mode).create(path)
+}
+
+/// `create_dir_all`, giving every directory it creates `mode` from the start.
+pub fn create_dir_all(path: &Path, mode: Option<FileMode>) -> std::io::Result<()> {
+    dir_builder(mode).recursive(true).create(path)
+}
+
+fn dir_builder(mode: Option<FileMode>) -> std::fs::DirBuilder {
+    #[allow(unused_mut)]
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(mode.0);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    builder
+}
+
+/// Why `path` should be readable by its owner only, if it should: it lives
+/// in a private directory like `~/.ssh`, or looks like it holds secrets.
+pub fn needs_private_mode(path: &Path) -> Option<String> {
+    let private_dir = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .find(|c| PRIVATE_DIRS.contains(&c.as_ref()));
+    if let Some(dir) = private_dir {
+        return Some(format!("it is inside '{}'", dir));
+    }
+    crate::secrets::looks_sensitive(path)
+}
+
+/// True if anyone on the machine can read `path` (following symlinks).
+#[cfg(unix)]
+pub fn is_world_readable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o004 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_world_readable(_path: &Path) -> bool {
+    false
+}
+
+/// A warning for a sensitive `path` that every user can read, if it is one.
+pub fn exposure_warning(path: &Path) -> Option<String> {
+    if !is_world_readable(path) {
+        return None;
+    }
+    let reason = needs_private_mode(path)?;
+    Some(format!(
+        "{} is world-readable but should be private ({}). Set file_mode: \"0600\" on its entry and run 'heimdal apply'",
+        path.display(),
+        reason
+    ))
+}
+
+/// Entries in `config` whose target should be private but would not be:
+/// their `file_mode` lets everyone read, or they set none and the repo file
+/// is world-readable. For `heimdal validate`; nothing here is fatal.
+pub fn config_warnings(config: &HeimdalConfig, dotfiles_dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = config.profiles.keys().collect();
+    names.sort();
+    let mut warnings = Vec::new();
+    for name in names {
+        let profile = &config.profiles[name];
+        let dotfiles = profile.dotfiles.iter().map(|entry| match entry {
+            DotfileEntry::Simple(s) => (s.clone(), format!("~/{}", s), None),
+            DotfileEntry::Mapped(m) => (m.source.clone(), m.target.clone(), m.file_mode),
+        });
+        let templates = profile
+            .templates
+            .iter()
+            .map(|t| (t.src.clone(), t.dest.clone(), t.file_mode));
+        for (src, dest, file_mode) in dotfiles.chain(templates) {
+            let source = dotfiles_dir.join(&src);
+            let Some(reason) =
+                needs_private_mode(&expand_path(&dest)).or_else(|| needs_private_mode(&source))
+            else {
+                continue;
+            };
+            match file_mode {
+                Some(mode) if mode.world_readable() => warnings.push(format!(
+                    "Profile '{}': file_mode {} makes {} world-readable, but it should be private ({})",
+                    name, mode, dest, reason
+                )),
+                None if is_world_readable(&source) => warnings.push(format!(
+                    "Profile '{}': {} should be private ({}), but {} is world-readable. \
+                     Set file_mode: \"0600\" on the entry",
+                    name, dest, reason, src
+                )),
+                _ => {}
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn parses_octal_strings_and_numbers() {
+        assert_eq!("0600".parse::<FileMode>().unwrap(), FileMode(0o600));
+        assert_eq!("0o755".parse::<FileMode>().unwrap(), FileMode(0o755));
+        assert!("0800".parse::<FileMode>().is_err());
+        assert!("77777".parse::<FileMode>().is_err());
+        let m: FileMode = serde_yaml_ng::from_str("600").unwrap();
+        assert_eq!(m, FileMode(0o600));
+        assert_eq!(serde_yaml_ng::to_string(&m).unwrap().trim(), "'0600'");
+    }
+
+    #[test]
+    fn private_paths() {
+        assert!(needs_private_mode(Path::new("/home/u/.ssh/config")).is_some());
+        assert!(needs_private_mode(Path::new("/home/u/.gnupg/gpg.conf")).is_some());
+        assert!(needs_private_mode(Path::new("/home/u/.netrc")).is_some());
+        assert!(needs_private_mode(Path::new("/home/u/.vimrc")).is_none());
+    }
+
+    #[test]
+    fn missing_ancestors_stop_at_existing_dir() {
+        let tmp = TempDir::new().unwrap();
+        let dest = tmp.path().join("a").join("b").join("file");
+        assert_eq!(
+            missing_ancestors(&dest),
+            vec![tmp.path().join("a"), tmp.path().join("a").join("b")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn enforce_sets_file_and_dir_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("gnupg");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("gpg.conf"), "").unwrap();
+        let perms = Permissions {
+            file: Some(FileMode(0o600)),
+            dir: Some(FileMode(0o700)),
+        };
+        perms.enforce(&dir).unwrap();
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&dir.join("gpg.conf")), 0o600);
+        assert!(!is_world_readable(&dir.join("gpg.conf")));
+    }
+}
@@ -0,0 +1,836 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::blocks::BlockOutcome;
+use crate::config::{BlockEntry, DeployMode, DotfileEntry, MergeEntry, Profile, TemplateEntry};
+use crate::manifest::{hash_path, DeployKind, Manifest};
+use crate::merge::MergeTarget;
+use crate::permissions::Permissions;
+use crate::state::State;
+use crate::symlink::UnlinkResult;
+use crate::symlink::{apply_mappings, apply_stow_walk, unlink_one, ApplyContext, LinkResult};
+use crate::utils::{expand_path, info, warning};
+
+pub const PLAN_VERSION: u32 = 1;
+
+/// What was at a path when the plan was made. `apply --plan` compares it
+/// with what is there now and refuses to run on a mismatch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Fingerprint {
+    Absent,
+    Symlink { target: PathBuf },
+    File { hash: Option<String> },
+    Dir { hash: Option<String> },
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> Self {
+        match std::fs::symlink_metadata(path) {
+            Err(_) => Fingerprint::Absent,
+            Ok(meta) if meta.file_type().is_symlink() => Fingerprint::Symlink {
+                target: std::fs::read_link(path).unwrap_or_default(),
+            },
+            Ok(meta) if meta.is_dir() => Fingerprint::Dir {
+                hash: hash_path(path),
+            },
+            Ok(_) => Fingerprint::File {
+                hash: hash_path(path),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookPhase {
+    PreApply,
+    PostApply,
+}
+
+/// One change `apply` would make.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Deploy `src` at a `dest` that does not exist yet.
+    CreateLink {
+        src: PathBuf,
+        dest: PathBuf,
+        mode: DeployMode,
+        relative: bool,
+        perms: Permissions,
+    },
+    /// Deploy `src` over what is at `dest`: our own outdated deploy, or a
+    /// conflicting file when planned with `--force`.
+    ReplaceFile {
+        src: PathBuf,
+        dest: PathBuf,
+        mode: DeployMode,
+        relative: bool,
+        perms: Permissions,
+    },
+    /// Move the conflicting `dest` to the backup dir, then deploy `src`.
+    Backup {
+        src: PathBuf,
+        dest: PathBuf,
+        mode: DeployMode,
+        relative: bool,
+        perms: Permissions,
+    },
+    /// Split a foreign directory link into per-entry links (stow walk).
+    Unfold {
+        dest: PathBuf,
+        from: PathBuf,
+    },
+    /// Collapse a directory of our own links into one link (stow walk).
+    Fold {
+        src: PathBuf,
+        dest: PathBuf,
+    },
+    /// Replace our own directory link with an empty directory, for the
+    /// steps after it to link its entries one by one (stow walk).
+    Expand {
+        src: PathBuf,
+        dest: PathBuf,
+    },
+    /// `output` is the blake3 of the rendered text; the text itself is not
+    /// stored, since it may hold secrets.
+    RenderTemplate {
+        src: PathBuf,
+        dest: PathBuf,
+        output: String,
+    },
+    WriteBlock {
+        src: PathBuf,
+        dest: PathBuf,
+        id: String,
+        output: String,
+    },
+    MergeKeys {
+        src: PathBuf,
+        dest: PathBuf,
+        changes: Vec<String>,
+    },
+    /// Remove something an earlier apply deployed that the profile dropped.
+    Prune {
+        dest: PathBuf,
+        kind: DeployKind,
+    },
+    RemoveBlock {
+        dest: PathBuf,
+        id: String,
+    },
+    InstallPackage {
+        manager: String,
+        package: String,
+    },
+    RunHook {
+        phase: HookPhase,
+        command: String,
+        fail_on_error: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Step {
+    #[serde(flatten)]
+    pub action: Action,
+    /// The target as it was when planned.
+    #[serde(default)]
+    pub before: Option<Fingerprint>,
+    /// blake3 of the repo source as it was when planned.
+    #[serde(default)]
+    pub source_hash: Option<String>,
+}
+
+impl Step {
+    fn new(action: Action) -> Self {
+        let before = action.target().map(Fingerprint::of);
+        let source_hash = action.source().and_then(hash_path);
+        Self {
+            action,
+            before,
+            source_hash,
+        }
+    }
+}
+
+impl Action {
+    /// The path this action changes, if it changes one.
+    pub fn target(&self) -> Option<&Path> {
+        match self {
+            Action::CreateLink { dest, .. }
+            | Action::ReplaceFile { dest, .. }
+            | Action::Backup { dest, .. }
+            | Action::Unfold { dest, .. }
+            | Action::Fold { dest, .. }
+            | Action::Expand { dest, .. }
+            | Action::RenderTemplate { dest, .. }
+            | Action::WriteBlock { dest, .. }
+            | Action::MergeKeys { dest, .. }
+            | Action::Prune { dest, .. }
+            | Action::RemoveBlock { dest, .. } => Some(dest),
+            Action::InstallPackage { .. } | Action::RunHook { .. } => None,
+        }
+    }
+
+    /// The repo file this action deploys, if any.
+    pub fn source(&self) -> Option<&Path> {
+        match self {
+            Action::CreateLink { src, .. }
+            | Action::ReplaceFile { src, .. }
+            | Action::Backup { src, .. }
+            | Action::Fold { src, .. }
+            | Action::RenderTemplate { src, .. }
+            | Action::WriteBlock { src, .. }
+            | Action::MergeKeys { src, .. } => Some(src),
+            _ => None,
+        }
+    }
+
+    /// One line for the human-readable plan, without the leading marker.
+    pub fn describe(&self) -> String {
+        let verb = |mode: &DeployMode| match mode {
+            DeployMode::Symlink => "link",
+            DeployMode::Copy => "copy",
+            DeployMode::Hardlink => "hardlink",
+        };
+        match self {
+            Action::CreateLink {
+                src, dest, mode, ..
+            } => format!("{} {} → {}", verb(mode), dest.display(), src.display()),
+            Action::ReplaceFile {
+                src, dest, mode, ..
+            } => format!(
+                "replace {} with {} → {}",
+                dest.display(),
+                verb(mode),
+                src.display()
+            ),
+            Action::Backup {
+                src, dest, mode, ..
+            } => format!(
+                "back up {}, then {} → {}",
+                dest.display(),
+                verb(mode),
+                src.display()
+            ),
+            Action::Unfold { dest, from } => {
+                format!("unfold {} (was → {})", dest.display(), from.display())
+            }
+            Action::Fold { src, dest } => format!("fold {} → {}", dest.display(), src.display()),
+            Action::Expand { src, dest } => {
+                format!("expand {} (was → {})", dest.display(), src.display())
+            }
+            Action::RenderTemplate { src, dest, .. } => {
+                format!("render {} from {}", dest.display(), src.display())
+            }
+            Action::WriteBlock { dest, id, .. } => {
+                format!("write block heimdal:{} in {}", id, dest.display())
+            }
+            Action::MergeKeys { dest, changes, .. } => {
+                let mut line = format!("merge {} key(s) into {}", changes.len(), dest.display());
+                for change in changes {
+                    line.push_str(&format!("\n      {}", change));
+                }
+                line
+            }
+            Action::Prune { dest, .. } => format!("prune orphaned {}", dest.display()),
+            Action::RemoveBlock { dest, id } => {
+                format!("remove block heimdal:{} from {}", id, dest.display())
+            }
+            Action::InstallPackage { manager, package } => {
+                format!("install {} ({})", package, manager)
+            }
+            Action::RunHook { phase, command, .. } => {
+                let phase = match phase {
+                    HookPhase::PreApply => "pre_apply",
+                    HookPhase::PostApply => "post_apply",
+                };
+                format!("run {} hook: {}", phase, command)
+            }
+        }
+    }
+
+    fn marker(&self) -> colored::ColoredString {
+        match self {
+            Action::CreateLink { .. } | Action::InstallPackage { .. } => "+".green(),
+            Action::RenderTemplate { .. } => "+".green(),
+            Action::Prune { .. } | Action::RemoveBlock { .. } => "-".red(),
+            Action::RunHook { .. } => "!".cyan(),
+            _ => "~".yellow(),
+        }
+    }
+}
+
+/// Everything `apply` would do, in the order it would do it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub profile: String,
+    pub dotfiles: PathBuf,
+    /// blake3 of heimdal.yaml and the files it includes when the plan was made.
+    pub config_hash: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read plan {}: {}", path.display(), e))?;
+        let plan: Plan = serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Invalid plan {}: {}", path.display(), e))?;
+        if plan.version != PLAN_VERSION {
+            anyhow::bail!(
+                "Plan {} has version {}, this heimdal reads version {}",
+                path.display(),
+                plan.version,
+                PLAN_VERSION
+            );
+        }
+        Ok(plan)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        if self.steps.is_empty() {
+            info(&format!(
+                "Plan for profile '{}': no changes, everything is up to date",
+                self.profile
+            ));
+            return;
+        }
+        info(&format!(
+            "Plan for profile '{}': {} change(s)",
+            self.profile,
+            self.steps.len()
+        ));
+        for step in &self.steps {
+            println!("  {} {}", step.action.marker(), step.action.describe());
+        }
+    }
+
+    /// Everything that differs from when the plan was made: the profile,
+    /// the config files, any target or repo source, or what a template or block
+    /// would now render to. Empty means the plan can run as it is.
+    pub fn changes_since(
+        &self,
+        state: &State,
+        config_files: &[&Path],
+        profile: &Profile,
+    ) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.profile != state.active_profile {
+            changes.push(format!(
+                "the plan is for profile '{}', the active profile is '{}'",
+                self.profile, state.active_profile
+            ));
+        }
+        if self.dotfiles != state.dotfiles_path {
+            changes.push(format!(
+                "the plan is for dotfiles in {}",
+                self.dotfiles.display()
+            ));
+        }
+        if config_hash(config_files) != self.config_hash {
+            changes.push(match config_files {
+                [main] => format!("{} was edited", main.display()),
+                [main, ..] => format!("{} or a file it includes was edited", main.display()),
+                [] => "the config was edited".to_string(),
+            });
+        }
+        for step in &self.steps {
+            if let (Some(target), Some(before)) = (step.action.target(), &step.before) {
+                if Fingerprint::of(target) != *before {
+                    changes.push(format!("{} changed", target.display()));
+                    continue;
+                }
+            }
+            if let Some(src) = step.action.source() {
+                if hash_path(src) != step.source_hash {
+                    changes.push(format!("{} changed in the repo", src.display()));
+                    continue;
+                }
+            }
+            // Variables, environment and secrets feed these, not just files
+            let rendered = match &step.action {
+                Action::RenderTemplate { src, dest, output } => Some((
+                    src,
+                    output,
+                    find_template(profile, &state.dotfiles_path, src, dest).and_then(|t| {
+                        render_template(t, &profile.variables, &state.dotfiles_path).ok()
+                    }),
+                )),
+                Action::WriteBlock {
+                    src,
+                    dest,
+                    id,
+                    output,
+                } => Some((
+                    src,
+                    output,
+                    find_block(profile, dest, id)
+                        .and_then(|b| {
+                            crate::blocks::render_body(b, &profile.variables, &state.dotfiles_path)
+                                .ok()
+                        })
+                        .map(|body| crate::blocks::hash(&body)),
+                )),
+                _ => None,
+            };
+            if let Some((src, output, now)) = rendered {
+                if now.as_ref() != Some(output) {
+                    changes.push(format!("{} would now render differently", src.display()));
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Work out what `apply` would do without touching anything. `ctx` must be a
+/// dry-run context; its `force`/`backup` decide how conflicts are planned.
+pub fn build(
+    state: &State,
+    config_files: &[&Path],
+    profile: &Profile,
+    ctx: &ApplyContext,
+    manifest: &Manifest,
+    dotfiles_only: bool,
+    packages_only: bool,
+) -> Result<Plan> {
+    let mut steps = Vec::new();
+
+    if !packages_only {
+        steps.extend(hook_steps(
+            &profile.hooks.pre_apply,
+            HookPhase::PreApply,
+            &state.active_profile,
+        ));
+    }
+
+    if !dotfiles_only {
+        for (manager, pkgs) in crate::packages::install_batches(profile, &state.active_profile) {
+            for package in pkgs {
+                if !manager.is_installed(&package) {
+                    steps.push(Step::new(Action::InstallPackage {
+                        manager: manager.name().to_string(),
+                        package,
+                    }));
+                }
+            }
+        }
+    }
+
+    if !packages_only {
+        let mut current: HashSet<PathBuf> = HashSet::new();
+        plan_links(state, profile, ctx, manifest, &mut steps, &mut current)?;
+        plan_templates(state, profile, &mut steps, &mut current);
+        let current_blocks = plan_blocks(state, profile, ctx, manifest, &mut steps)?;
+        plan_merges(state, profile, &mut steps, &mut current);
+        plan_prunes(ctx, manifest, &current, &current_blocks, &mut steps)?;
+        steps.extend(hook_steps(
+            &profile.hooks.post_apply,
+            HookPhase::PostApply,
+            &state.active_profile,
+        ));
+    }
+
+    Ok(Plan {
+        version: PLAN_VERSION,
+        created_at: Utc::now(),
+        profile: state.active_profile.clone(),
+        dotfiles: state.dotfiles_path.clone(),
+        config_hash: config_hash(config_files),
+        steps,
+    })
+}
+
+/// One file hashes as itself, so plans of a config without includes keep
+/// their hash; several hash their hashes together.
+fn config_hash(files: &[&Path]) -> Option<String> {
+    if let [file] = files {
+        return hash_path(file);
+    }
+    let mut hasher = blake3::Hasher::new();
+    for file in files {
+        hasher.update(hash_path(file).unwrap_or_default().as_bytes());
+    }
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+fn hook_steps(hooks: &[crate::config::HookEntry], phase: HookPhase, profile: &str) -> Vec<Step> {
+    crate::hooks::applicable(hooks, profile)
+        .into_iter()
+        .map(|(command, fail_on_error)| {
+            Step::new(Action::RunHook {
+                phase,
+                command,
+                fail_on_error,
+            })
+        })
+        .collect()
+}
+
+fn plan_links(
+    state: &State,
+    profile: &Profile,
+    ctx: &ApplyContext,
+    manifest: &Manifest,
+    steps: &mut Vec<Step>,
+    current: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    let results = if profile.dotfiles.is_empty() {
+        apply_stow_walk(ctx)?
+    } else {
+        apply_mappings(ctx, &profile.dotfiles, &state.active_profile, manifest)?
+    };
+
+    let conflicts: Vec<_> = results
+        .iter()
+        .filter_map(|r| match r {
+            LinkResult::Conflict { dest, reason } => {
+                Some(format!("{}: {}", dest.display(), reason))
+            }
+            _ => None,
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        for conflict in &conflicts {
+            warning(&format!("Conflict at {}", conflict));
+        }
+        anyhow::bail!(
+            "{} conflict(s) found. Plan with --force to overwrite or --backup to save originals.",
+            conflicts.len()
+        );
+    }
+
+    // Link form and modes come from the mapping; the walk uses the defaults
+    let settings: HashMap<PathBuf, (bool, Permissions)> = profile
+        .dotfiles
+        .iter()
+        .filter_map(|entry| match entry {
+            DotfileEntry::Mapped(m) => Some((
+                expand_path(&m.target),
+                (
+                    m.relative_links.unwrap_or(ctx.relative_links),
+                    m.permissions(),
+                ),
+            )),
+            DotfileEntry::Simple(_) => None,
+        })
+        .collect();
+    let settings_for = |dest: &Path| {
+        settings
+            .get(dest)
+            .copied()
+            .unwrap_or((ctx.relative_links, Permissions::default()))
+    };
+
+    // Directories an earlier step turns from a link into an empty directory
+    let mut expanded: Vec<PathBuf> = Vec::new();
+    for result in results {
+        if let Some(dest) = result.managed() {
+            current.insert(dest.to_owned());
+        }
+        let action = match result {
+            LinkResult::Created { src, dest, mode } => {
+                let (relative, perms) = settings_for(&dest);
+                let exists = (dest.exists() || dest.is_symlink())
+                    && !expanded.iter().any(|dir| dest.starts_with(dir));
+                if exists {
+                    Action::ReplaceFile {
+                        src,
+                        dest,
+                        mode,
+                        relative,
+                        perms,
+                    }
+                } else {
+                    Action::CreateLink {
+                        src,
+                        dest,
+                        mode,
+                        relative,
+                        perms,
+                    }
+                }
+            }
+            LinkResult::Backed {
+                src, dest, mode, ..
+            } => {
+                let (relative, perms) = settings_for(&dest);
+                Action::Backup {
+                    src,
+                    dest,
+                    mode,
+                    relative,
+                    perms,
+                }
+            }
+            LinkResult::Unfolded { dest, from } => Action::Unfold { dest, from },
+            LinkResult::Folded { src, dest } => Action::Fold { src, dest },
+            LinkResult::Expanded { src, dest } => {
+                expanded.push(dest.clone());
+                Action::Expand { src, dest }
+            }
+            LinkResult::AlreadyLinked { .. }
+            | LinkResult::Skipped { .. }
+            | LinkResult::Unmet { .. }
+            | LinkResult::Conflict { .. }
+            | LinkResult::Adopted { .. } => continue,
+        };
+        steps.push(Step::new(action));
+    }
+    Ok(())
+}
+
+fn plan_templates(
+    state: &State,
+    profile: &Profile,
+    steps: &mut Vec<Step>,
+    current: &mut HashSet<PathBuf>,
+) {
+    for tmpl in &profile.templates {
+        if !crate::condition::holds(tmpl.when.as_deref(), &state.active_profile) {
+            continue;
+        }
+        let src = state.dotfiles_path.join(&tmpl.src);
+        let dest = expand_path(&tmpl.dest);
+        current.insert(dest.clone());
+        match render_template(tmpl, &profile.variables, &state.dotfiles_path) {
+            Ok(output) if hash_path(&dest).as_ref() == Some(&output) => {}
+            Ok(output) => steps.push(Step::new(Action::RenderTemplate { src, dest, output })),
+            Err(e) => warning(&format!("Template '{}' failed: {}", tmpl.src, e)),
+        }
+    }
+}
+
+fn plan_blocks(
+    state: &State,
+    profile: &Profile,
+    ctx: &ApplyContext,
+    manifest: &Manifest,
+    steps: &mut Vec<Step>,
+) -> Result<HashSet<(PathBuf, String)>> {
+    let mut current = HashSet::new();
+    let mut edited = 0;
+    for entry in &profile.blocks {
+        let src = state.dotfiles_path.join(&entry.source);
+        let dest = expand_path(&entry.target);
+        current.insert((dest.clone(), entry.id.clone()));
+        let body = crate::blocks::render_body(entry, &profile.variables, &state.dotfiles_path)?;
+        let outcome = crate::blocks::deploy(
+            &dest,
+            &entry.id,
+            entry
+                .comment
+                .as_deref()
+                .unwrap_or(crate::blocks::DEFAULT_COMMENT),
+            &body,
+            manifest.get_block(&dest, &entry.id),
+            ctx.force,
+            true,
+            None,
+        )?;
+        match outcome {
+            BlockOutcome::Inserted | BlockOutcome::Updated => {
+                steps.push(Step::new(Action::WriteBlock {
+                    src,
+                    dest,
+                    id: entry.id.clone(),
+                    output: crate::blocks::hash(&body),
+                }))
+            }
+            BlockOutcome::Unchanged => {}
+            BlockOutcome::HandEdited => {
+                warning(&format!(
+                    "Conflict: block heimdal:{} in {} was edited by hand since last apply",
+                    entry.id,
+                    dest.display()
+                ));
+                edited += 1;
+            }
+        }
+    }
+    if edited > 0 {
+        anyhow::bail!(
+            "{} managed block(s) were edited by hand. Copy the edits into the repo snippet, \
+             or plan with --force to overwrite them.",
+            edited
+        );
+    }
+    Ok(current)
+}
+
+fn plan_merges(
+    state: &State,
+    profile: &Profile,
+    steps: &mut Vec<Step>,
+    current: &mut HashSet<PathBuf>,
+) {
+    for entry in &profile.merges {
+        let target = MergeTarget::new(entry, &state.dotfiles_path);
+        current.insert(target.dest.clone());
+        match target.deploy(true, None) {
+            Ok(changes) if changes.is_empty() => {}
+            Ok(changes) => steps.push(Step::new(Action::MergeKeys {
+                src: target.source.clone(),
+                dest: target.dest.clone(),
+                changes: changes.iter().map(|c| c.describe()).collect(),
+            })),
+            Err(e) => warning(&format!(
+                "Merge into {} failed: {}",
+                target.dest.display(),
+                e
+            )),
+        }
+    }
+}
+
+/// Orphans `apply` would actually remove: links still into the repo, files
+/// unchanged since deployed, merged keys still present, intact blocks.
+fn plan_prunes(
+    ctx: &ApplyContext,
+    manifest: &Manifest,
+    current: &HashSet<PathBuf>,
+    current_blocks: &HashSet<(PathBuf, String)>,
+    steps: &mut Vec<Step>,
+) -> Result<()> {
+    for orphan in manifest.orphans(current) {
+        let target = &orphan.target;
+        let prunable = match orphan.kind {
+            DeployKind::Symlink => {
+                matches!(
+                    unlink_one(target, ctx, false)?,
+                    UnlinkResult::Removed { .. }
+                )
+            }
+            DeployKind::Merge => MergeTarget::recorded(&orphan, &[])
+                .remove(true, None)
+                .is_ok_and(|removed| !removed.is_empty()),
+            DeployKind::Block => false,
+            DeployKind::Copy | DeployKind::Hardlink | DeployKind::Template => {
+                target.exists() && hash_path(target) == orphan.hash
+            }
+        };
+        if prunable {
+            steps.push(Step::new(Action::Prune {
+                dest: target.clone(),
+                kind: orphan.kind,
+            }));
+        }
+    }
+    for orphan in manifest.orphan_blocks(current_blocks) {
+        if crate::blocks::check(&orphan)? == crate::blocks::BlockState::Intact {
+            steps.push(Step::new(Action::RemoveBlock {
+                dest: orphan.target.clone(),
+                id: orphan.block.clone().unwrap_or_default(),
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// blake3 of what `tmpl` renders to now.
+fn render_template(
+    tmpl: &TemplateEntry,
+    variables: &HashMap<String, String>,
+    dotfiles_dir: &Path,
+) -> Result<String> {
+    let vars = crate::templates::build_vars(variables, &tmpl.vars, "env")?;
+    let rendered = crate::templates::render_file(
+        &dotfiles_dir.join(&tmpl.src),
+        &expand_path(&tmpl.dest),
+        &vars,
+        true,
+        None,
+        &Permissions::default(),
+    )?;
+    Ok(blake3::hash(rendered.as_bytes()).to_hex().to_string())
+}
+
+/// The profile's template entry behind a planned render.
+pub fn find_template<'a>(
+    profile: &'a Profile,
+    dotfiles_dir: &Path,
+    src: &Path,
+    dest: &Path,
+) -> Option<&'a TemplateEntry> {
+    profile
+        .templates
+        .iter()
+        .find(|t| dotfiles_dir.join(&t.src) == src && expand_path(&t.dest) == dest)
+}
+
+pub fn find_block<'a>(profile: &'a Profile, dest: &Path, id: &str) -> Option<&'a BlockEntry> {
+    profile
+        .blocks
+        .iter()
+        .find(|b| b.id == id && expand_path(&b.target) == dest)
+}
+
+pub fn find_merge<'a>(profile: &'a Profile, dest: &Path) -> Option<&'a MergeEntry> {
+    profile
+        .merges
+        .iter()
+        .find(|m| expand_path(&m.target) == dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn fingerprint_tells_links_files_and_absence_apart() {
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join("f");
+        assert_eq!(Fingerprint::of(&file), Fingerprint::Absent);
+        std::fs::write(&file, "a").unwrap();
+        let before = Fingerprint::of(&file);
+        assert!(matches!(before, Fingerprint::File { hash: Some(_) }));
+        std::fs::write(&file, "b").unwrap();
+        assert_ne!(Fingerprint::of(&file), before);
+
+        #[cfg(unix)]
+        {
+            let link = tmp.path().join("l");
+            std::os::unix::fs::symlink(&file, &link).unwrap();
+            assert_eq!(
+                Fingerprint::of(&link),
+                Fingerprint::Symlink {
+                    target: file.clone()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn steps_round_trip_through_json() {
+        let step = Step {
+            action: Action::CreateLink {
+                src: PathBuf::from("/repo/.vimrc"),
+                dest: PathBuf::from("/home/.vimrc"),
+                mode: DeployMode::Symlink,
+                relative: false,
+                perms: Permissions::default(),
+            },
+            before: Some(Fingerprint::Absent),
+            source_hash: Some("abc".to_string()),
+        };
+        let json = serde_json::to_value(&step).unwrap();
+        assert_eq!(json["action"], "create_link");
+        assert_eq!(json["before"]["state"], "absent");
+        assert_eq!(serde_json::from_value::<Step>(json).unwrap(), step);
+    }
+}
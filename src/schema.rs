@@ -0,0 +1,333 @@
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde_json::Value;
+use serde_yaml_ng::Value as Yaml;
+
+use crate::config::{Fragment, HeimdalConfig};
+use crate::yaml_edit::{Document, Seg};
+
+/// The JSON Schema for heimdal.yaml, for editors to complete and check
+/// the file against. It is derived from the config types, doc comments
+/// included, so it always follows their serde attributes.
+pub fn config_schema() -> Value {
+    schema_for::<HeimdalConfig>("heimdal.yaml")
+}
+
+/// What an included file may hold: the lists heimdal.yaml has, without
+/// its settings.
+fn fragment_schema() -> Value {
+    schema_for::<Fragment>("Included config file")
+}
+
+fn schema_for<T: JsonSchema>(title: &str) -> Value {
+    let mut root = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<T>();
+    root.schema.metadata().title = Some(title.to_string());
+    serde_json::to_value(root).expect("a JSON Schema serializes")
+}
+
+/// The schema `schema` stands for, following `$ref`s into the root's
+/// `definitions` and the `allOf` wrapper a documented reference gets.
+fn resolve<'a>(root: &'a Value, mut schema: &'a Value) -> &'a Value {
+    loop {
+        if let Some(r) = schema.get("$ref").and_then(Value::as_str) {
+            match r
+                .strip_prefix("#/definitions/")
+                .and_then(|name| root["definitions"].get(name))
+            {
+                Some(target) => schema = target,
+                None => return schema,
+            }
+        } else if let Some([only]) = schema
+            .get("allOf")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+        {
+            schema = only;
+        } else {
+            return schema;
+        }
+    }
+}
+
+/// A key the schema has no place for.
+struct Unknown {
+    path: Vec<Seg>,
+    key: String,
+    suggestion: Option<String>,
+}
+
+/// Keys in the files `config` was read from that heimdal does not know,
+/// each as `file:line:column: message`. Serde skips them silently, so a
+/// misspelt key would otherwise just do nothing.
+pub fn unknown_keys(config: &HeimdalConfig) -> Vec<String> {
+    let main = config_schema();
+    let fragment = fragment_schema();
+    let mut errors = Vec::new();
+    for (path, text) in config.sources.texts() {
+        let Ok(value) = serde_yaml_ng::from_str::<Yaml>(text) else {
+            continue;
+        };
+        let schema = match config.sources.is_included(path) {
+            true => &fragment,
+            false => &main,
+        };
+        let mut found = Vec::new();
+        walk(schema, schema, &value, &mut Vec::new(), &mut found);
+        let document = Document::parse(text);
+        for unknown in found {
+            let mut key_path = unknown.path.clone();
+            key_path.push(Seg::Key(Yaml::String(unknown.key.clone())));
+            let at = document
+                .as_ref()
+                .and_then(|d| d.position(&key_path))
+                .map(|(line, col)| format!(":{}:{}", line, col))
+                .unwrap_or_default();
+            let place = match unknown.path.is_empty() {
+                true => "at the top level".to_string(),
+                false => format!("in {}", dotted(&unknown.path)),
+            };
+            let hint = unknown
+                .suggestion
+                .map(|s| format!(", did you mean '{}'?", s))
+                .unwrap_or_default();
+            errors.push(format!(
+                "{}{}: unknown key '{}' {}{}",
+                path.display(),
+                at,
+                unknown.key,
+                place,
+                hint
+            ));
+        }
+    }
+    errors
+}
+
+/// `profiles.work.dotfiles[2]`
+fn dotted(path: &[Seg]) -> String {
+    let mut out = String::new();
+    for seg in path {
+        match seg {
+            Seg::Key(key) => {
+                if !out.is_empty() {
+                    out.push('.');
+                }
+                out.push_str(&key_text(key));
+            }
+            Seg::Index(i) => out.push_str(&format!("[{}]", i)),
+        }
+    }
+    out
+}
+
+fn key_text(key: &Yaml) -> String {
+    match key {
+        Yaml::String(s) => s.clone(),
+        other => serde_yaml_ng::to_string(other)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+/// Collect the keys in `value` that `schema` has no place for. Only where
+/// keys are allowed is checked; wrong types are left to deserialization,
+/// which already reports them.
+fn walk(root: &Value, schema: &Value, value: &Yaml, path: &mut Vec<Seg>, out: &mut Vec<Unknown>) {
+    let schema = resolve(root, schema);
+    let alternatives = schema
+        .get("anyOf")
+        .or_else(|| schema.get("oneOf"))
+        .and_then(Value::as_array);
+    if let Some(alternatives) = alternatives {
+        // The alternative the value fits best
+        let mut best: Option<Vec<Unknown>> = None;
+        for alternative in alternatives {
+            let alternative = resolve(root, alternative);
+            if !fits_kind(alternative, value) {
+                continue;
+            }
+            let mut found = Vec::new();
+            walk(root, alternative, value, path, &mut found);
+            if best.as_ref().map_or(true, |b| found.len() < b.len()) {
+                best = Some(found);
+            }
+        }
+        out.extend(best.unwrap_or_default());
+        return;
+    }
+    match value {
+        Yaml::Mapping(map) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let additional = schema.get("additionalProperties");
+            for (key, child) in map {
+                path.push(Seg::Key(key.clone()));
+                let name = key_text(key);
+                match properties.and_then(|p| p.get(&name)) {
+                    Some(child_schema) => walk(root, child_schema, child, path, out),
+                    None => match additional {
+                        Some(Value::Bool(false)) => {
+                            path.pop();
+                            out.push(Unknown {
+                                path: path.clone(),
+                                suggestion: properties.and_then(|p| suggest(&name, p.keys())),
+                                key: name,
+                            });
+                            continue;
+                        }
+                        Some(child_schema) if child_schema.is_object() => {
+                            walk(root, child_schema, child, path, out)
+                        }
+                        _ => {}
+                    },
+                }
+                path.pop();
+            }
+        }
+        Yaml::Sequence(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    path.push(Seg::Index(i));
+                    walk(root, item_schema, item, path, out);
+                    path.pop();
+                }
+            }
+        }
+        Yaml::Tagged(tagged) => walk(root, schema, &tagged.value, path, out),
+        _ => {}
+    }
+}
+
+/// Whether `schema` could describe `value` at all: a mapping needs an
+/// object, a list an array. A schema without a `type` could be anything.
+fn fits_kind(schema: &Value, value: &Yaml) -> bool {
+    let kinds: Vec<&str> = match schema.get("type") {
+        Some(Value::String(kind)) => vec![kind],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => return true,
+    };
+    match value {
+        Yaml::Mapping(_) => kinds.contains(&"object"),
+        Yaml::Sequence(_) => kinds.contains(&"array"),
+        _ => kinds.iter().any(|k| !matches!(*k, "object" | "array")),
+    }
+}
+
+/// The known key closest to `key`, if one is close enough to be a typo.
+fn suggest<'a>(key: &str, known: impl Iterator<Item = &'a String>) -> Option<String> {
+    known
+        .map(|k| (strsim::jaro_winkler(key, k), k))
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, k)| k.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::condition::Condition;
+    use crate::config::{Exclude, HistoryConfig, PackageMap, Profile, ProfileHooks, TemplateEntry};
+    use std::collections::HashMap;
+
+    fn properties(root: &Value, name: &str) -> Vec<String> {
+        root["definitions"][name]["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Every key serde writes for these types has a place in the schema.
+    #[test]
+    fn schema_covers_serialized_keys() {
+        let root = config_schema();
+        let cases = [
+            (
+                "Profile",
+                serde_json::to_value(Profile {
+                    machine: Some(Condition::default()),
+                    variables: HashMap::from([("a".into(), "b".into())]),
+                    ..Default::default()
+                }),
+            ),
+            ("Condition", serde_json::to_value(Condition::default())),
+            ("PackageMap", serde_json::to_value(PackageMap::default())),
+            (
+                "ProfileHooks",
+                serde_json::to_value(ProfileHooks::default()),
+            ),
+            (
+                "TemplateEntry",
+                serde_json::to_value(TemplateEntry::default()),
+            ),
+            (
+                "HistoryConfig",
+                serde_json::to_value(HistoryConfig::default()),
+            ),
+            ("Exclude", serde_json::to_value(Exclude::default())),
+        ];
+        for (name, value) in cases {
+            let known = properties(&root, name);
+            for key in value.unwrap().as_object().unwrap().keys() {
+                assert!(known.contains(key), "'{}' missing from schema", key);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_unknown_keys_through_untagged_entries() {
+        let schema = config_schema();
+        let value: Yaml = serde_yaml_ng::from_str(
+            r#"
+heimdal: { version: "1", stow_compat: true }
+profiles:
+  work:
+    dotfile: []
+    dotfiles:
+      - .vimrc
+      - { source: a, target: b, whne: { os: [linux] } }
+    hooks:
+      post_apply:
+        - { command: make, fail_on_eror: false }
+        - { ref: build }
+    packages:
+      conditional:
+        - when: { os: [linux], any: [{ distroo: [arch] }] }
+          apt: [git]
+"#,
+        )
+        .unwrap();
+        let mut found = Vec::new();
+        walk(&schema, &schema, &value, &mut Vec::new(), &mut found);
+        let found: Vec<_> = found
+            .iter()
+            .map(|u| (dotted(&u.path), u.key.as_str(), u.suggestion.as_deref()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("heimdal".to_string(), "stow_compat", None),
+                ("profiles.work".to_string(), "dotfile", Some("dotfiles")),
+                (
+                    "profiles.work.dotfiles[1]".to_string(),
+                    "whne",
+                    Some("when")
+                ),
+                (
+                    "profiles.work.hooks.post_apply[0]".to_string(),
+                    "fail_on_eror",
+                    Some("fail_on_error")
+                ),
+                (
+                    "profiles.work.packages.conditional[0].when.any[0]".to_string(),
+                    "distroo",
+                    Some("distro")
+                ),
+            ]
+        );
+    }
+}
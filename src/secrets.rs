@@ -79,6 +79,63 @@ fn save_manifest(dotfiles_path: &Path, manifest: &SecretsManifest) -> Result<()>
     Ok(())
 }
 
+/// File names that almost always hold credentials.
+const SENSITIVE_NAMES: &[&str] = &[
+    "id_rsa",
+    "id_dsa",
+    "id_ecdsa",
+    "id_ed25519",
+    ".netrc",
+    ".pgpass",
+    ".git-credentials",
+    "credentials",
+    ".env",
+    ".npmrc",
+    ".pypirc",
+];
+
+const SENSITIVE_EXTENSIONS: &[&str] = &["pem", "key", "p12", "pfx", "keystore", "gpg", "asc"];
+
+/// Content markers of private keys and tokens.
+const SENSITIVE_MARKERS: &[&str] = &[
+    "PRIVATE KEY-----",
+    "aws_secret_access_key",
+    "_authToken=",
+    "ghp_",
+    "xoxb-",
+];
+
+/// Why `path` looks like it holds secrets, if it does. Checks the name
+/// first, then scans files (or every file in a directory) for key material.
+pub fn looks_sensitive(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy().to_string();
+    if SENSITIVE_NAMES.contains(&name.as_str()) || name.starts_with(".env.") {
+        return Some(format!("'{}' is a well-known credentials file", name));
+    }
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        if SENSITIVE_EXTENSIONS.contains(&ext) {
+            return Some(format!(
+                "'.{}' files usually hold keys or certificates",
+                ext
+            ));
+        }
+    }
+    if path.is_dir() {
+        return walkdir::WalkDir::new(path)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .find_map(|e| looks_sensitive(e.path()));
+    }
+    let content = std::fs::read(path).ok()?;
+    let text = String::from_utf8_lossy(&content);
+    SENSITIVE_MARKERS
+        .iter()
+        .find(|m| text.contains(**m))
+        .map(|m| format!("'{}' contains '{}'", name, m))
+}
+
 pub fn set_secret(dotfiles_path: &Path, name: &str, value: &str) -> Result<()> {
     let key = format!("{}:{}", whoami::username(), name);
     let entry = keyring::Entry::new(SERVICE_NAME, &key)
@@ -1,9 +1,16 @@
 use anyhow::Result;
 use chrono::Utc;
-use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
-use crate::config::{DotfileCondition, DotfileEntry};
+use crate::backups;
+use crate::condition::{Condition, Host};
+use crate::config::{DeployMode, DotfileEntry};
+use crate::ignore_rules::IgnoreRules;
+use crate::journal::Journal;
+use crate::manifest::{hash_path, DeployKind, Manifest, ManifestEntry};
+use crate::permissions::{missing_ancestors, Permissions};
 use crate::utils::{expand_path, info, step, warning};
 
 pub struct ApplyContext {
@@ -12,36 +19,260 @@ pub struct ApplyContext {
     pub dry_run: bool,
     pub force: bool,
     pub backup: bool,
+    /// Default for writing relative link targets (`heimdal.relative_links`).
+    pub relative_links: bool,
+    /// Paths under `dotfiles_dir` the stow walk never deploys.
+    pub ignore: IgnoreRules,
+    /// Set by `apply --interactive` on a TTY: conflicts are settled by asking.
+    pub prompt: Option<ConflictPrompt>,
+    /// When set, every mutation is journaled so a failed apply can be undone.
+    pub journal: Option<Journal>,
+    /// Upper bound on targets deployed concurrently.
+    pub jobs: usize,
+}
+
+impl ApplyContext {
+    /// Delete `path`, or stash it in the journal when apply is transactional.
+    fn remove_path(&self, path: &Path) -> Result<()> {
+        match &self.journal {
+            Some(journal) => journal.remove(path),
+            None if path.is_dir() && !path.is_symlink() => Ok(std::fs::remove_dir_all(path)?),
+            None => Ok(std::fs::remove_file(path)?),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        match &self.journal {
+            Some(journal) => journal.create_dir_all(path, None),
+            None => Ok(std::fs::create_dir_all(path)?),
+        }
+    }
+
+    fn symlink(&self, src: &Path, dest: &Path, relative: bool) -> Result<()> {
+        if let Some(journal) = &self.journal {
+            journal.creating_link(dest, src)?;
+        }
+        create_symlink(src, dest, relative)
+    }
+
+    /// Put `src` at `dest` using the given deploy mode.
+    fn place(&self, src: &Path, dest: &Path, mode: DeployMode, relative: bool) -> Result<()> {
+        if mode == DeployMode::Symlink {
+            return self.symlink(src, dest, relative);
+        }
+        if let Some(journal) = &self.journal {
+            journal.creating_copy(dest)?;
+        }
+        let placed = match mode {
+            DeployMode::Hardlink => std::fs::hard_link(src, dest),
+            _ => crate::utils::copy_recursive(src, dest),
+        };
+        placed.map_err(|e| crate::error::HeimdallError::Symlink {
+            path: dest.display().to_string(),
+            reason: e.to_string(),
+        })?;
+        Ok(())
+    }
+
+    fn move_to_backup(&self, path: &Path, backup: &Path) -> Result<()> {
+        match &self.journal {
+            Some(journal) => journal.backup(path, backup),
+            None => Ok(std::fs::rename(path, backup)?),
+        }
+    }
+
+    /// Replace the repo copy at `src` with whatever is at `dest`.
+    fn adopt(&self, src: &Path, dest: &Path) -> Result<()> {
+        if src.exists() || src.is_symlink() {
+            self.remove_path(src)?;
+        }
+        match &self.journal {
+            // Undoing a backup moves the file back, which is exactly the undo here
+            Some(journal) => journal.backup(dest, src),
+            None => Ok(crate::utils::move_path(dest, src)?),
+        }
+    }
+}
+
+/// How to settle one conflicting target during `apply --interactive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Overwrite,
+    Backup,
+    Adopt,
+    Skip,
+}
+
+impl Resolution {
+    const ALL: [Resolution; 4] = [
+        Resolution::Overwrite,
+        Resolution::Backup,
+        Resolution::Adopt,
+        Resolution::Skip,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Resolution::Overwrite => "Overwrite — replace it with the repo version",
+            Resolution::Backup => "Backup — move it to the backup dir, then link",
+            Resolution::Adopt => "Adopt — replace the repo copy with this file",
+            Resolution::Skip => "Skip — leave it as it is",
+        }
+    }
+}
+
+/// Asks the user about each conflict, remembering an "apply to all" answer
+/// for the rest of the run.
+#[derive(Default)]
+pub struct ConflictPrompt {
+    remembered: Mutex<Option<Resolution>>,
+}
+
+impl ConflictPrompt {
+    fn resolve(&self, src: &Path, dest: &Path) -> Result<Resolution> {
+        // Held across the prompt so only one question is on screen at a time
+        let mut remembered = self.remembered.lock().unwrap();
+        // A symlink is not content worth moving into the repo
+        let adoptable = !dest.is_symlink();
+        if let Some(choice) = *remembered {
+            return Ok(if choice == Resolution::Adopt && !adoptable {
+                Resolution::Skip
+            } else {
+                choice
+            });
+        }
+
+        println!();
+        warning(&format!("Conflict at {}", dest.display()));
+        match std::fs::read_link(dest) {
+            Ok(target) => println!("  existing symlink points to {}", target.display()),
+            Err(_) => crate::utils::print_diff(dest, src),
+        }
+
+        let choices: Vec<Resolution> = Resolution::ALL
+            .into_iter()
+            .filter(|r| adoptable || *r != Resolution::Adopt)
+            .collect();
+        let mut labels: Vec<&str> = choices.iter().map(|r| r.label()).collect();
+        labels.push("Apply one choice to all remaining conflicts…");
+        let picked = dialoguer::Select::new()
+            .with_prompt("What should happen to the existing file?")
+            .items(&labels)
+            .default(0)
+            .interact()?;
+        if let Some(choice) = choices.get(picked) {
+            return Ok(*choice);
+        }
+
+        let labels: Vec<&str> = Resolution::ALL.iter().map(|r| r.label()).collect();
+        let picked = dialoguer::Select::new()
+            .with_prompt("Apply to this and every remaining conflict")
+            .items(&labels)
+            .default(0)
+            .interact()?;
+        let choice = Resolution::ALL[picked];
+        *remembered = Some(choice);
+        Ok(if choice == Resolution::Adopt && !adoptable {
+            Resolution::Skip
+        } else {
+            choice
+        })
+    }
 }
 
 #[derive(Debug)]
 pub enum LinkResult {
-    #[allow(dead_code)]
     Created {
         src: PathBuf,
         dest: PathBuf,
+        mode: DeployMode,
     },
     AlreadyLinked {
+        src: PathBuf,
         dest: PathBuf,
+        mode: DeployMode,
     },
     Skipped {
         dest: PathBuf,
         reason: String,
     },
+    /// The entry's `when:` does not hold on this machine.
+    Unmet {
+        dest: PathBuf,
+    },
     Backed {
+        src: PathBuf,
         dest: PathBuf,
         backup: PathBuf,
+        mode: DeployMode,
     },
     Conflict {
         dest: PathBuf,
         reason: String,
     },
+    /// The existing file replaced the repo copy, then was linked back.
+    Adopted {
+        src: PathBuf,
+        dest: PathBuf,
+        mode: DeployMode,
+    },
+    /// A directory symlink owned by another source was split into per-entry links.
+    Unfolded {
+        dest: PathBuf,
+        from: PathBuf,
+    },
+    /// A directory of our own links was collapsed back into a single symlink.
+    Folded {
+        src: PathBuf,
+        dest: PathBuf,
+    },
+    /// Our own folded link to `src` was replaced by a real directory, so its
+    /// entries can be linked one by one.
+    Expanded {
+        src: PathBuf,
+        dest: PathBuf,
+    },
+}
+
+impl LinkResult {
+    /// The (source, target, mode) this result leaves deployed, if any.
+    pub fn linked(&self) -> Option<(&Path, &Path, DeployMode)> {
+        match self {
+            LinkResult::Created { src, dest, mode }
+            | LinkResult::AlreadyLinked { src, dest, mode }
+            | LinkResult::Adopted { src, dest, mode }
+            | LinkResult::Backed {
+                src, dest, mode, ..
+            } => Some((src, dest, *mode)),
+            LinkResult::Folded { src, dest } => Some((src, dest, DeployMode::Symlink)),
+            _ => None,
+        }
+    }
+
+    /// The target this result leaves under heimdal's management: deployed,
+    /// already in place, or skipped but still configured. Only targets whose
+    /// condition does not hold here are left out.
+    pub fn managed(&self) -> Option<&Path> {
+        match self {
+            LinkResult::Unmet { .. } => None,
+            LinkResult::Created { dest, .. }
+            | LinkResult::AlreadyLinked { dest, .. }
+            | LinkResult::Skipped { dest, .. }
+            | LinkResult::Backed { dest, .. }
+            | LinkResult::Conflict { dest, .. }
+            | LinkResult::Adopted { dest, .. }
+            | LinkResult::Unfolded { dest, .. }
+            | LinkResult::Folded { dest, .. }
+            | LinkResult::Expanded { dest, .. } => Some(dest),
+        }
+    }
 }
 
 static STOW_SKIP: &[&str] = &[
     ".git",
     ".heimdal",
     "heimdal.yaml",
+    crate::ignore_rules::IGNORE_FILE,
     ".stowrc",
     "README.md",
     "README",
@@ -50,171 +281,930 @@ static STOW_SKIP: &[&str] = &[
     "Makefile",
 ];
 
-pub fn apply_mappings(
+/// One mapping that passed its condition and path checks, ready to deploy.
+struct PlannedLink {
+    src: PathBuf,
+    dest: PathBuf,
+    mode: DeployMode,
+    relative: bool,
+    perms: Permissions,
+}
+
+/// Resolve every entry against the config first: conditions, the traversal
+/// guard and target paths. Entries that are skipped already carry their result.
+fn plan_mappings(
     ctx: &ApplyContext,
     entries: &[DotfileEntry],
     active_profile: &str,
-) -> Result<Vec<LinkResult>> {
-    let os = crate::utils::os_name();
-    let hostname = hostname::get()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-    let mut results = Vec::new();
+) -> Vec<std::result::Result<PlannedLink, LinkResult>> {
+    let host = Host::current();
+    let canonical_dir = ctx.dotfiles_dir.canonicalize();
 
-    for entry in entries {
-        let (src_rel, dest_str, condition) = match entry {
-            DotfileEntry::Simple(s) => (s.as_str(), format!("~/{}", s), None),
-            DotfileEntry::Mapped(m) => (m.source.as_str(), m.target.clone(), m.when.clone()),
-        };
+    entries
+        .iter()
+        .map(|entry| {
+            let (src_rel, dest_str, condition, mode, relative, perms) = match entry {
+                DotfileEntry::Simple(s) => (
+                    s.as_str(),
+                    format!("~/{}", s),
+                    None,
+                    DeployMode::Symlink,
+                    ctx.relative_links,
+                    Permissions::default(),
+                ),
+                DotfileEntry::Mapped(m) => (
+                    m.source.as_str(),
+                    m.target.clone(),
+                    m.when.as_deref(),
+                    m.mode,
+                    m.relative_links.unwrap_or(ctx.relative_links),
+                    m.permissions(),
+                ),
+            };
+            let dest = expand_path(&dest_str);
 
-        if !should_link(&condition, active_profile, os, &hostname) {
-            results.push(LinkResult::Skipped {
-                dest: expand_path(&dest_str),
-                reason: "condition not met".to_string(),
-            });
-            continue;
+            if !should_link(condition, active_profile, &host) {
+                return Err(LinkResult::Unmet { dest });
+            }
+
+            let src = ctx.dotfiles_dir.join(src_rel);
+
+            // Guard against path traversal (e.g., source: "../../etc/passwd")
+            if let Ok(canonical_dir) = &canonical_dir {
+                let canonical_src = src.canonicalize().unwrap_or_else(|_| src.clone());
+                if !canonical_src.starts_with(canonical_dir) {
+                    return Err(LinkResult::Skipped {
+                        dest,
+                        reason: format!(
+                            "source '{}' escapes dotfiles directory — skipped for safety",
+                            src_rel
+                        ),
+                    });
+                }
+            }
+
+            Ok(PlannedLink {
+                src,
+                dest,
+                mode,
+                relative,
+                perms,
+            })
+        })
+        .collect()
+}
+
+/// Split planned links into groups that can run concurrently. Targets that
+/// are equal or nested (`~/.config/nvim` and `~/.config/nvim/init.lua`) land
+/// in one group and keep their config order; everything else is independent.
+fn independent_groups(planned: &[(usize, &PlannedLink)]) -> Vec<Vec<usize>> {
+    let mut by_dest: Vec<(usize, &Path)> = planned
+        .iter()
+        .map(|(i, link)| (*i, link.dest.as_path()))
+        .collect();
+    by_dest.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(&b.0)));
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut root: Option<&Path> = None;
+    for (i, dest) in by_dest {
+        match (root, groups.last_mut()) {
+            (Some(r), Some(group)) if dest.starts_with(r) => group.push(i),
+            _ => {
+                root = Some(dest);
+                groups.push(vec![i]);
+            }
         }
+    }
+    for group in &mut groups {
+        group.sort_unstable();
+    }
+    groups
+}
+
+/// Deploy one planned link and set its permissions.
+fn execute_link(link: &PlannedLink, ctx: &ApplyContext, manifest: &Manifest) -> Result<LinkResult> {
+    let created = missing_ancestors(&link.dest);
+    let result = deploy_one(
+        &link.src,
+        &link.dest,
+        link.mode,
+        link.relative,
+        manifest.get(&link.dest),
+        ctx,
+    )?;
+    finish_link(&result, &link.perms, &created, ctx)?;
+    Ok(result)
+}
 
-        let src = ctx.dotfiles_dir.join(src_rel);
-
-        // Guard against path traversal (e.g., source: "../../etc/passwd")
-        if let (Ok(canonical_src), Ok(canonical_dir)) = (
-            src.canonicalize()
-                .or_else(|_| Ok::<_, std::io::Error>(src.clone())),
-            ctx.dotfiles_dir.canonicalize(),
-        ) {
-            if !canonical_src.starts_with(&canonical_dir) {
-                results.push(LinkResult::Skipped {
-                    dest: expand_path(&dest_str),
-                    reason: format!(
-                        "source '{}' escapes dotfiles directory — skipped for safety",
-                        src_rel
-                    ),
-                });
-                continue;
+/// Set the configured modes on what a deploy left in place. `created` are
+/// the parent directories that did not exist before it.
+pub fn finish_link(
+    result: &LinkResult,
+    perms: &Permissions,
+    created: &[PathBuf],
+    ctx: &ApplyContext,
+) -> Result<()> {
+    if ctx.dry_run || perms.is_empty() {
+        return Ok(());
+    }
+    if let Some((src, dest, mode)) = result.linked() {
+        // A symlink has no mode of its own; readers get the repo file's
+        perms.enforce(if mode == DeployMode::Symlink {
+            src
+        } else {
+            dest
+        })?;
+        perms.enforce_created(created)?;
+    }
+    Ok(())
+}
+
+/// Deploy every mapping: plan first, then run independent targets on up to
+/// `ctx.jobs` workers. Results come back in config order whatever the
+/// scheduling, and the first error in that order is the one returned.
+pub fn apply_mappings(
+    ctx: &ApplyContext,
+    entries: &[DotfileEntry],
+    active_profile: &str,
+    manifest: &Manifest,
+) -> Result<Vec<LinkResult>> {
+    let plan = plan_mappings(ctx, entries, active_profile);
+    let planned: Vec<(usize, &PlannedLink)> = plan
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| p.as_ref().ok().map(|link| (i, link)))
+        .collect();
+    let groups = independent_groups(&planned);
+
+    let failed = AtomicBool::new(false);
+    let executed = crate::parallel::map_ordered(groups, ctx.jobs, |group| {
+        let mut out = Vec::with_capacity(group.len());
+        for i in group {
+            // Once a deploy failed the run is rolled back; stop adding to it
+            if failed.load(Ordering::Relaxed) {
+                break;
+            }
+            let Ok(link) = &plan[i] else { continue };
+            let result = execute_link(link, ctx, manifest);
+            if result.is_err() {
+                failed.store(true, Ordering::Relaxed);
             }
+            out.push((i, result));
         }
+        out
+    });
 
-        let dest = expand_path(&dest_str);
-        results.push(link_one(&src, &dest, ctx)?);
+    let mut done: Vec<(usize, Result<LinkResult>)> = executed.into_iter().flatten().collect();
+    done.sort_by_key(|(i, _)| *i);
+    let mut done = done.into_iter().peekable();
+    let mut results = Vec::with_capacity(plan.len());
+    for (i, planned) in plan.into_iter().enumerate() {
+        match planned {
+            Err(skipped) => results.push(skipped),
+            Ok(_) => match done.next_if(|(j, _)| *j == i) {
+                Some((_, result)) => results.push(result?),
+                // Not reached because an earlier target failed
+                None => continue,
+            },
+        }
     }
     Ok(results)
 }
 
-/// GNU Stow-style walk: symlink top-level entries from dotfiles_dir into home_dir.
+/// GNU Stow-style walk: mirror the dotfiles tree into home_dir.
 ///
-/// Only top-level entries are processed (depth 1). Each entry becomes a single
-/// symlink in home_dir at the same relative path. This means:
-///   - `.vimrc` in dotfiles → `~/.vimrc` symlink
-///   - `.config/` directory → `~/.config` symlink (the whole dir, not its contents)
+/// Follows Stow's tree folding rules:
+///   - A directory whose target does not exist is *folded* into one symlink
+///     (`.config/nvim/` → `~/.config/nvim` when `~/.config/nvim` is absent).
+///   - A directory whose target is a real directory is descended into, so
+///     `.config/nvim` links next to other apps in an existing `~/.config`.
+///   - A directory whose target is a symlink to some other directory in the
+///     dotfiles repo is *unfolded*: the link is replaced by a real directory
+///     holding per-entry links to the other directory's contents, then our
+///     entries are added. A link that points outside the repo is the user's
+///     own and is a conflict like any other.
+///   - A real directory that contains nothing but links into the matching
+///     dotfiles directory is *re-folded* back into a single symlink.
+///   - A directory holding ignored entries is never folded, so ignored files
+///     stay out of home even when their parent directory is linked.
 ///
-/// If you need file-level control within subdirectories, use explicit `dotfiles:`
-/// mappings in heimdal.yaml instead.
+/// Files are always handed to `link_one`, so --force/--backup apply as usual.
+/// Top-level entries touch disjoint parts of home, so each is walked on its
+/// own worker; results keep the sorted order of a sequential walk.
 pub fn apply_stow_walk(ctx: &ApplyContext) -> Result<Vec<LinkResult>> {
+    let entries = stow_sources(&ctx.dotfiles_dir, ctx, true)?;
+    let walked = crate::parallel::map_ordered(entries, ctx.jobs, |entry| {
+        let mut results = Vec::new();
+        stow_entry(
+            &entry.path(),
+            &ctx.home_dir.join(entry.file_name()),
+            ctx,
+            &mut results,
+        )
+        .map(|()| results)
+    });
     let mut results = Vec::new();
-    for entry in WalkDir::new(&ctx.dotfiles_dir)
-        .min_depth(1)
-        .max_depth(1)
-        .into_iter()
+    for walked in walked {
+        results.extend(walked?);
+    }
+    Ok(results)
+}
+
+/// The entries of `src_dir` the walk deploys, sorted by name.
+fn stow_sources(
+    src_dir: &Path,
+    ctx: &ApplyContext,
+    top_level: bool,
+) -> Result<Vec<std::fs::DirEntry>> {
+    let mut entries: Vec<_> = std::fs::read_dir(src_dir)?
         .filter_map(|e| e.ok())
-    {
-        let name = entry.file_name().to_string_lossy().to_string();
-        if STOW_SKIP.contains(&name.as_str()) {
-            continue;
-        }
-        let rel = entry.path().strip_prefix(&ctx.dotfiles_dir).unwrap();
+        .filter(|entry| {
+            // Repo metadata only lives at the top of the dotfiles dir
+            let metadata =
+                top_level && STOW_SKIP.contains(&entry.file_name().to_string_lossy().as_ref());
+            !metadata && !ctx.ignore.is_ignored(&entry.path())
+        })
+        .collect();
+    entries.sort_by_key(|e| e.file_name());
+    Ok(entries)
+}
+
+fn stow_children(
+    src_dir: &Path,
+    dest_dir: &Path,
+    ctx: &ApplyContext,
+    results: &mut Vec<LinkResult>,
+) -> Result<()> {
+    for entry in stow_sources(src_dir, ctx, false)? {
         // home_dir is already a resolved absolute path from dirs::home_dir(),
         // so no shellexpand needed here unlike apply_mappings which takes strings from config.
-        let dest = ctx.home_dir.join(rel);
-        results.push(link_one(entry.path(), &dest, ctx)?);
+        stow_entry(
+            &entry.path(),
+            &dest_dir.join(entry.file_name()),
+            ctx,
+            results,
+        )?;
+    }
+    Ok(())
+}
+
+fn stow_entry(
+    src: &Path,
+    dest: &Path,
+    ctx: &ApplyContext,
+    results: &mut Vec<LinkResult>,
+) -> Result<()> {
+    if !src.is_dir() {
+        results.push(link_one(src, dest, ctx)?);
+        return Ok(());
+    }
+
+    if dest.is_symlink() {
+        let target = std::fs::read_link(dest)?;
+        if points_to(dest, src) {
+            if ctx.ignore.any_within(src) {
+                // Folded before its contents were ignored
+                return expand(src, dest, ctx, results);
+            }
+            // Already ours; link_one rewrites it if only the form differs
+            results.push(link_one(src, dest, ctx)?);
+            return Ok(());
+        }
+        let other = absolute_link_target(dest, &target);
+        if !other.is_dir() || !is_within(&other, &ctx.dotfiles_dir) {
+            // Dangling, a file, or not ours — regular conflict handling
+            results.push(link_one(src, dest, ctx)?);
+            return Ok(());
+        }
+        unfold(dest, &other, ctx, results)?;
+        return stow_children(src, dest, ctx, results);
+    }
+
+    if dest.is_dir() {
+        if is_foldable(src, dest) && !ctx.ignore.any_within(src) {
+            fold(src, dest, ctx, results)?;
+        } else {
+            stow_children(src, dest, ctx, results)?;
+        }
+        return Ok(());
+    }
+
+    if !dest.exists() && ctx.ignore.any_within(src) {
+        if !ctx.dry_run {
+            ctx.create_dir_all(dest)?;
+        }
+        return stow_children(src, dest, ctx, results);
+    }
+
+    results.push(link_one(src, dest, ctx)?);
+    Ok(())
+}
+
+/// Replace our own folded directory link with a real directory and link its
+/// entries one by one, skipping whatever is ignored.
+fn expand(
+    src: &Path,
+    dest: &Path,
+    ctx: &ApplyContext,
+    results: &mut Vec<LinkResult>,
+) -> Result<()> {
+    expand_link(src, dest, ctx, results)?;
+    if ctx.dry_run {
+        // dest is still the folded link, so look at it as the empty
+        // directory it would be by now
+        return stow_fresh(src, dest, ctx, results);
+    }
+    stow_children(src, dest, ctx, results)
+}
+
+/// Replace the folded link at `dest` with an empty directory.
+pub fn expand_link(
+    src: &Path,
+    dest: &Path,
+    ctx: &ApplyContext,
+    results: &mut Vec<LinkResult>,
+) -> Result<()> {
+    if !ctx.dry_run {
+        ctx.remove_path(dest)?;
+        ctx.create_dir_all(dest)?;
+    }
+    results.push(LinkResult::Expanded {
+        src: src.to_owned(),
+        dest: dest.to_owned(),
+    });
+    Ok(())
+}
+
+/// What `stow_children` would do if `dest_dir` were an empty directory.
+fn stow_fresh(
+    src_dir: &Path,
+    dest_dir: &Path,
+    ctx: &ApplyContext,
+    results: &mut Vec<LinkResult>,
+) -> Result<()> {
+    for entry in stow_sources(src_dir, ctx, false)? {
+        let (src, dest) = (entry.path(), dest_dir.join(entry.file_name()));
+        if src.is_dir() && ctx.ignore.any_within(&src) {
+            stow_fresh(&src, &dest, ctx, results)?;
+        } else {
+            results.push(LinkResult::Created {
+                src,
+                dest,
+                mode: DeployMode::Symlink,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Resolve a (possibly relative) symlink target against the link's parent dir.
+fn absolute_link_target(link: &Path, target: &Path) -> PathBuf {
+    if target.is_absolute() {
+        normalize(target)
+    } else {
+        normalize(&link.parent().unwrap_or(Path::new("/")).join(target))
+    }
+}
+
+/// True if the symlink at `link` resolves to `src`, whether its target was
+/// written in absolute or relative form.
+pub fn points_to(link: &Path, src: &Path) -> bool {
+    std::fs::read_link(link)
+        .is_ok_and(|target| absolute_link_target(link, &target) == normalize(src))
+}
+
+/// True if `link` is a symlink to somewhere inside `dir`, whether its
+/// target was written in absolute or relative form.
+pub fn links_into(link: &Path, dir: &Path) -> bool {
+    std::fs::read_link(link)
+        .is_ok_and(|target| is_within(&absolute_link_target(link, &target), dir))
+}
+
+/// Lexically resolve `.` and `..` without touching the filesystem, so a link
+/// target can be compared before (or without) the source existing.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push(component);
+                }
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// The path of `to` as seen from directory `from`. Both must be absolute;
+/// paths with no common root (e.g. different Windows drives) stay absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = normalize(from);
+    let to = normalize(to);
+    let mut from_parts = from.components().peekable();
+    let mut to_parts = to.components().peekable();
+    if from_parts.peek() != to_parts.peek() {
+        return to;
+    }
+    while from_parts.peek().is_some() && from_parts.peek() == to_parts.peek() {
+        from_parts.next();
+        to_parts.next();
+    }
+    let mut rel: PathBuf = from_parts.map(|_| Component::ParentDir).collect();
+    rel.extend(to_parts);
+    rel
+}
+
+/// Replace a directory symlink with a real directory of per-entry links
+/// pointing at the same places, so other sources can be linked alongside.
+pub fn unfold(
+    dest: &Path,
+    other: &Path,
+    ctx: &ApplyContext,
+    results: &mut Vec<LinkResult>,
+) -> Result<()> {
+    if !ctx.dry_run {
+        ctx.remove_path(dest)?;
+        ctx.create_dir_all(dest)?;
+        for entry in std::fs::read_dir(other)?.filter_map(|e| e.ok()) {
+            ctx.symlink(
+                &entry.path(),
+                &dest.join(entry.file_name()),
+                ctx.relative_links,
+            )?;
+        }
+    }
+    results.push(LinkResult::Unfolded {
+        dest: dest.to_owned(),
+        from: other.to_owned(),
+    });
+    Ok(())
+}
+
+/// A real directory can be folded when it is non-empty and every entry in it
+/// is a symlink to the corresponding entry of `src` — i.e. it only exists
+/// because of us and nothing would be lost by replacing it with one link.
+fn is_foldable(src: &Path, dest: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dest) else {
+        return false;
+    };
+    let mut any = false;
+    for entry in entries {
+        let Ok(entry) = entry else { return false };
+        if !points_to(&entry.path(), &src.join(entry.file_name())) {
+            return false;
+        }
+        any = true;
+    }
+    any
+}
+
+/// Collapse a directory of our own links into one link to `src`.
+pub fn fold(
+    src: &Path,
+    dest: &Path,
+    ctx: &ApplyContext,
+    results: &mut Vec<LinkResult>,
+) -> Result<()> {
+    if !ctx.dry_run {
+        for entry in std::fs::read_dir(dest)?.filter_map(|e| e.ok()) {
+            ctx.remove_path(&entry.path())?;
+        }
+        ctx.remove_path(dest)?;
+        ctx.symlink(src, dest, ctx.relative_links)?;
+    }
+    results.push(LinkResult::Folded {
+        src: src.to_owned(),
+        dest: dest.to_owned(),
+    });
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum UnlinkResult {
+    Removed { dest: PathBuf },
+    Restored { dest: PathBuf, backup: PathBuf },
+    NotLinked { dest: PathBuf },
+    Skipped { dest: PathBuf, reason: String },
+}
+
+impl UnlinkResult {
+    pub fn dest(&self) -> &Path {
+        match self {
+            UnlinkResult::Removed { dest }
+            | UnlinkResult::Restored { dest, .. }
+            | UnlinkResult::NotLinked { dest }
+            | UnlinkResult::Skipped { dest, .. } => dest,
+        }
+    }
+}
+
+/// Remove the symlinks created for explicit `dotfiles:` mappings.
+///
+/// Conditions are not evaluated: a link left behind by an earlier apply is
+/// still ours to remove even if its `when:` no longer matches this machine.
+pub fn unlink_mappings(
+    ctx: &ApplyContext,
+    entries: &[DotfileEntry],
+    restore: bool,
+) -> Result<Vec<UnlinkResult>> {
+    let mut results = Vec::new();
+    for entry in entries {
+        results.push(unlink_one(&expand_path(&entry.target()), ctx, restore)?);
     }
     Ok(results)
 }
 
+/// Reverse of `apply_stow_walk`: remove every link under home_dir that points
+/// into the dotfiles tree, descending into unfolded (real) directories.
+pub fn unlink_stow_walk(ctx: &ApplyContext, restore: bool) -> Result<Vec<UnlinkResult>> {
+    let mut results = Vec::new();
+    unstow_children(
+        &ctx.dotfiles_dir,
+        &ctx.home_dir,
+        ctx,
+        restore,
+        &mut results,
+        true,
+    )?;
+    Ok(results)
+}
+
+fn unstow_children(
+    src_dir: &Path,
+    dest_dir: &Path,
+    ctx: &ApplyContext,
+    restore: bool,
+    results: &mut Vec<UnlinkResult>,
+    top_level: bool,
+) -> Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(src_dir)?.filter_map(|e| e.ok()).collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        if top_level && STOW_SKIP.contains(&name.to_string_lossy().as_ref()) {
+            continue;
+        }
+        let src = entry.path();
+        let dest = dest_dir.join(&name);
+        if src.is_dir() && dest.is_dir() && !dest.is_symlink() {
+            unstow_children(&src, &dest, ctx, restore, results, false)?;
+        } else {
+            results.push(unlink_one(&dest, ctx, restore)?);
+        }
+    }
+    Ok(())
+}
+
+/// Remove `dest` if it is a symlink into the dotfiles directory, optionally
+/// moving the most recent backup of it back into place.
+pub fn unlink_one(dest: &Path, ctx: &ApplyContext, restore: bool) -> Result<UnlinkResult> {
+    if !dest.is_symlink() {
+        return Ok(UnlinkResult::NotLinked {
+            dest: dest.to_owned(),
+        });
+    }
+    let target = absolute_link_target(dest, &std::fs::read_link(dest)?);
+    if !is_within(&target, &ctx.dotfiles_dir) {
+        return Ok(UnlinkResult::Skipped {
+            dest: dest.to_owned(),
+            reason: format!(
+                "points to {} (outside dotfiles directory)",
+                target.display()
+            ),
+        });
+    }
+
+    let backup = if restore {
+        latest_backup(ctx, dest)
+    } else {
+        None
+    };
+
+    if !ctx.dry_run {
+        ctx.remove_path(dest)?;
+        if let Some(backup) = &backup {
+            restore_backup(ctx, backup, dest)?;
+        }
+    }
+
+    Ok(match backup {
+        Some(backup) => UnlinkResult::Restored {
+            dest: dest.to_owned(),
+            backup,
+        },
+        None => UnlinkResult::Removed {
+            dest: dest.to_owned(),
+        },
+    })
+}
+
+/// Remove a copy or hardlink recorded in the manifest, unless it was edited
+/// after heimdal deployed it.
+pub fn unlink_copy(
+    entry: &ManifestEntry,
+    ctx: &ApplyContext,
+    restore: bool,
+) -> Result<UnlinkResult> {
+    let dest = &entry.target;
+    if dest.is_symlink() || !dest.exists() {
+        return Ok(UnlinkResult::NotLinked { dest: dest.clone() });
+    }
+    if hash_path(dest) != entry.hash {
+        return Ok(UnlinkResult::Skipped {
+            dest: dest.clone(),
+            reason: "modified since last apply — left in place".to_string(),
+        });
+    }
+
+    let backup = if restore {
+        latest_backup(ctx, dest)
+    } else {
+        None
+    };
+
+    if !ctx.dry_run {
+        ctx.remove_path(dest)?;
+        if let Some(backup) = &backup {
+            restore_backup(ctx, backup, dest)?;
+        }
+    }
+
+    Ok(match backup {
+        Some(backup) => UnlinkResult::Restored {
+            dest: dest.clone(),
+            backup,
+        },
+        None => UnlinkResult::Removed { dest: dest.clone() },
+    })
+}
+
+/// True if `path` lies inside `dir`, comparing canonical forms when they exist
+/// so that symlinked dotfiles directories (e.g. /tmp → /private/tmp) still match.
+fn is_within(path: &Path, dir: &Path) -> bool {
+    if path.starts_with(dir) {
+        return true;
+    }
+    match (path.canonicalize(), dir.canonicalize()) {
+        (Ok(p), Ok(d)) => p.starts_with(d),
+        _ => false,
+    }
+}
+
+/// Find the newest backup taken from `dest`.
+fn latest_backup(ctx: &ApplyContext, dest: &Path) -> Option<PathBuf> {
+    let dir = backups::backup_dir(&ctx.dotfiles_dir);
+    let index = backups::BackupIndex::load(&dir).ok()?;
+    Some(dir.join(&index.latest_for(dest)?.id))
+}
+
+/// Move a backup back to `dest` and drop it from the backup index.
+fn restore_backup(ctx: &ApplyContext, backup: &Path, dest: &Path) -> Result<()> {
+    crate::utils::move_path(backup, dest)?;
+    if let Some(id) = backup.file_name() {
+        backups::forget(
+            &backups::backup_dir(&ctx.dotfiles_dir),
+            &id.to_string_lossy(),
+        )?;
+    }
+    Ok(())
+}
+
 pub fn link_one(src: &Path, dest: &Path, ctx: &ApplyContext) -> Result<LinkResult> {
+    deploy_one(
+        src,
+        dest,
+        DeployMode::Symlink,
+        ctx.relative_links,
+        None,
+        ctx,
+    )
+}
+
+/// Place `src` at `dest` by symlink, copy or hardlink.
+///
+/// `relative` only affects symlinks. `last` is the manifest entry from the
+/// previous apply, if any. It lets a copy we deployed be refreshed when the
+/// repo changes, while a copy that was edited in place is reported as a
+/// conflict instead of silently overwritten.
+pub fn deploy_one(
+    src: &Path,
+    dest: &Path,
+    mode: DeployMode,
+    relative: bool,
+    last: Option<&ManifestEntry>,
+    ctx: &ApplyContext,
+) -> Result<LinkResult> {
+    crate::journal::check_interrupted()?;
     if !src.exists() {
         return Ok(LinkResult::Skipped {
             dest: dest.to_owned(),
             reason: format!("source not found: {}", src.display()),
         });
     }
+    if mode == DeployMode::Hardlink && src.is_dir() {
+        return Ok(LinkResult::Skipped {
+            dest: dest.to_owned(),
+            reason: "hardlink mode needs a file source, not a directory".to_string(),
+        });
+    }
 
-    // Already correctly linked?
-    if dest.is_symlink() {
-        if let Ok(target) = std::fs::read_link(dest) {
-            if target == src {
-                return Ok(LinkResult::AlreadyLinked {
-                    dest: dest.to_owned(),
-                });
-            }
-        }
+    // Already correctly deployed?
+    if is_deployed(src, dest, mode, relative) {
+        return Ok(LinkResult::AlreadyLinked {
+            src: src.to_owned(),
+            dest: dest.to_owned(),
+            mode,
+        });
     }
 
     // Conflict: dest exists (as a real file/dir or wrong symlink)
     if dest.exists() || dest.is_symlink() {
-        if ctx.force {
+        if is_replaceable(src, dest, last) {
+            // Ours and untouched (e.g. repo changed, or the mode changed)
             if !ctx.dry_run {
-                if dest.is_dir() && !dest.is_symlink() {
-                    std::fs::remove_dir_all(dest)?;
-                } else {
-                    std::fs::remove_file(dest)?;
-                }
-            }
-            // fall through to create symlink
-        } else if ctx.backup {
-            let backup_dir = ctx.dotfiles_dir.join(".heimdal").join("backups");
-            let ts = Utc::now().format("%Y%m%dT%H%M%SZ");
-            let base_name = dest
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("backup");
-            let backup_name = format!("{}.{}", base_name, ts);
-            let backup = backup_dir.join(&backup_name);
-
-            if ctx.dry_run {
-                // In dry-run, show what would happen but don't actually do it
-                return Ok(LinkResult::Skipped {
-                    dest: dest.to_owned(),
-                    reason: format!("[preview] would back up to {}", backup.display()),
-                });
+                ctx.remove_path(dest)?;
             }
-
-            std::fs::create_dir_all(&backup_dir)?;
-            std::fs::rename(dest, &backup)?;
-            if let Some(parent) = dest.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            create_symlink(src, dest)?;
-            return Ok(LinkResult::Backed {
-                dest: dest.to_owned(),
-                backup,
-            });
         } else {
-            return Ok(LinkResult::Conflict {
-                dest: dest.to_owned(),
-                reason: "file exists. Use --force to overwrite or --backup to save original"
-                    .to_string(),
-            });
+            let resolution = if ctx.force {
+                Some(Resolution::Overwrite)
+            } else if ctx.backup {
+                Some(Resolution::Backup)
+            } else if let Some(prompt) = &ctx.prompt {
+                Some(prompt.resolve(src, dest)?)
+            } else {
+                None
+            };
+            match resolution {
+                Some(Resolution::Overwrite) => {
+                    if !ctx.dry_run {
+                        ctx.remove_path(dest)?;
+                    }
+                    // fall through to create symlink
+                }
+                Some(Resolution::Backup) => {
+                    return backup_and_place(src, dest, mode, relative, ctx)
+                }
+                Some(Resolution::Adopt) => {
+                    if !ctx.dry_run {
+                        ctx.adopt(src, dest)?;
+                        ctx.place(src, dest, mode, relative)?;
+                    }
+                    return Ok(LinkResult::Adopted {
+                        src: src.to_owned(),
+                        dest: dest.to_owned(),
+                        mode,
+                    });
+                }
+                Some(Resolution::Skip) => {
+                    return Ok(LinkResult::Skipped {
+                        dest: dest.to_owned(),
+                        reason: "kept existing file".to_string(),
+                    })
+                }
+                None if is_locally_edited(dest, last) => {
+                    return Ok(LinkResult::Conflict {
+                        dest: dest.to_owned(),
+                        reason: "edited locally since last apply. Run 'heimdal state check-drift \
+                                 --pull' to keep the edit, or --force to discard it"
+                            .to_string(),
+                    });
+                }
+                None => {
+                    return Ok(LinkResult::Conflict {
+                        dest: dest.to_owned(),
+                        reason:
+                            "file exists. Use --force to overwrite or --backup to save original"
+                                .to_string(),
+                    });
+                }
+            }
         }
     }
 
     if !ctx.dry_run {
         if let Some(parent) = dest.parent() {
-            std::fs::create_dir_all(parent)?;
+            ctx.create_dir_all(parent)?;
         }
-        create_symlink(src, dest)?;
+        ctx.place(src, dest, mode, relative)?;
     }
 
     Ok(LinkResult::Created {
         src: src.to_owned(),
         dest: dest.to_owned(),
+        mode,
     })
 }
 
+/// Remove whatever is at `dest` and deploy `src` there, as `--force` would.
+pub fn replace_one(
+    src: &Path,
+    dest: &Path,
+    mode: DeployMode,
+    relative: bool,
+    ctx: &ApplyContext,
+) -> Result<LinkResult> {
+    if !ctx.dry_run && (dest.exists() || dest.is_symlink()) {
+        ctx.remove_path(dest)?;
+    }
+    deploy_one(src, dest, mode, relative, None, ctx)
+}
+
+/// Move the existing `dest` into the timestamped backup dir, then deploy.
+pub fn backup_and_place(
+    src: &Path,
+    dest: &Path,
+    mode: DeployMode,
+    relative: bool,
+    ctx: &ApplyContext,
+) -> Result<LinkResult> {
+    let backup_dir = backups::backup_dir(&ctx.dotfiles_dir);
+    let backup_name = backups::new_backup_name(&backup_dir, dest, Utc::now());
+    let backup = backup_dir.join(&backup_name);
+
+    if !ctx.dry_run {
+        ctx.create_dir_all(&backup_dir)?;
+        ctx.move_to_backup(dest, &backup)?;
+        backups::record(&backup_dir, &backup_name, dest)?;
+        if let Some(parent) = dest.parent() {
+            ctx.create_dir_all(parent)?;
+        }
+        ctx.place(src, dest, mode, relative)?;
+    }
+    Ok(LinkResult::Backed {
+        src: src.to_owned(),
+        dest: dest.to_owned(),
+        backup,
+        mode,
+    })
+}
+
+/// Symlinks also have to be in the requested absolute/relative form, so
+/// flipping `relative_links` rewrites existing links in place.
+fn is_deployed(src: &Path, dest: &Path, mode: DeployMode, relative: bool) -> bool {
+    match mode {
+        DeployMode::Symlink => {
+            points_to(dest, src)
+                && std::fs::read_link(dest).is_ok_and(|target| target.is_absolute() != relative)
+        }
+        DeployMode::Copy => {
+            !dest.is_symlink() && dest.exists() && hash_path(dest) == hash_path(src)
+        }
+        DeployMode::Hardlink => !dest.is_symlink() && same_file(src, dest),
+    }
+}
+
+/// A target we may replace without --force: a symlink to this very source
+/// (left over from symlink mode), or a copy whose content still matches what
+/// the last apply wrote.
+fn is_replaceable(src: &Path, dest: &Path, last: Option<&ManifestEntry>) -> bool {
+    if points_to(dest, src) {
+        return true;
+    }
+    last.is_some_and(|e| {
+        e.kind != DeployKind::Symlink && !dest.is_symlink() && hash_path(dest) == e.hash
+    })
+}
+
+/// A copy or hardlink we deployed whose content no longer matches the last apply.
+fn is_locally_edited(dest: &Path, last: Option<&ManifestEntry>) -> bool {
+    last.is_some_and(|e| {
+        matches!(e.kind, DeployKind::Copy | DeployKind::Hardlink)
+            && !dest.is_symlink()
+            && hash_path(dest) != e.hash
+    })
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(windows)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    // No stable file-index API on Windows; fall back to identical content
+    b.exists() && hash_path(a) == hash_path(b)
+}
+
+/// Link `dest` to `src`, writing the target relative to `dest`'s directory
+/// when `relative` is set.
+fn create_symlink(src: &Path, dest: &Path, relative: bool) -> Result<()> {
+    let target = match dest.parent() {
+        Some(parent) if relative => relative_path(parent, src),
+        _ => src.to_owned(),
+    };
+    symlink_to(src, &target, dest)
+}
+
 #[cfg(unix)]
-fn create_symlink(src: &Path, dest: &Path) -> Result<()> {
-    std::os::unix::fs::symlink(src, dest).map_err(|e| {
+fn symlink_to(src: &Path, target: &Path, dest: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, dest).map_err(|e| {
         crate::error::HeimdallError::Symlink {
             path: src.display().to_string(),
             reason: e.to_string(),
@@ -224,11 +1214,11 @@ fn create_symlink(src: &Path, dest: &Path) -> Result<()> {
 }
 
 #[cfg(windows)]
-fn create_symlink(src: &Path, dest: &Path) -> Result<()> {
+fn symlink_to(src: &Path, target: &Path, dest: &Path) -> Result<()> {
     if src.is_dir() {
-        std::os::windows::fs::symlink_dir(src, dest)
+        std::os::windows::fs::symlink_dir(target, dest)
     } else {
-        std::os::windows::fs::symlink_file(src, dest)
+        std::os::windows::fs::symlink_file(target, dest)
     }
     .map_err(|e| {
         crate::error::HeimdallError::Symlink {
@@ -239,59 +1229,81 @@ fn create_symlink(src: &Path, dest: &Path) -> Result<()> {
     })
 }
 
-pub fn should_link(
-    condition: &Option<DotfileCondition>,
-    active_profile: &str,
-    os: &str,
-    hostname: &str,
-) -> bool {
-    let Some(cond) = condition else { return true };
-    if !cond.os.is_empty() && !cond.os.iter().any(|o| o == os) {
-        return false;
-    }
-    if !cond.profile.is_empty() && !cond.profile.iter().any(|p| p == active_profile) {
-        return false;
-    }
-    if let Some(pattern) = &cond.hostname {
-        match glob::Pattern::new(pattern) {
-            Ok(pat) => {
-                if !pat.matches(hostname) {
-                    return false;
-                }
-            }
-            Err(_) => {
-                crate::utils::warning(&format!(
-                    "Invalid hostname glob pattern '{}' — skipping dotfile for safety",
-                    pattern
-                ));
-                return false;
-            }
-        }
-    }
-    true
+pub fn should_link(condition: Option<&Condition>, active_profile: &str, host: &Host) -> bool {
+    condition.map_or(true, |c| c.matches(host, active_profile))
 }
 
 pub fn print_results(results: &[LinkResult], dry_run: bool) {
     let prefix = if dry_run { "[preview] " } else { "" };
     for r in results {
         match r {
-            LinkResult::Created { dest, .. } => {
-                step(&format!("{}Linked: {}", prefix, dest.display()))
-            }
-            LinkResult::AlreadyLinked { dest } => {
-                info(&format!("Already linked: {}", dest.display()))
+            LinkResult::Created { dest, mode, .. } => {
+                step(&format!("{}{}: {}", prefix, mode.verb(), dest.display()))
             }
+            LinkResult::AlreadyLinked { dest, mode, .. } => info(&format!(
+                "Already {}: {}",
+                mode.verb().to_lowercase(),
+                dest.display()
+            )),
             LinkResult::Skipped { dest, reason } => {
                 info(&format!("Skipped {}: {}", dest.display(), reason))
             }
-            LinkResult::Backed { dest, backup } => step(&format!(
-                "{}Backed {} \u{2192} {}",
+            LinkResult::Unmet { dest } => {
+                info(&format!("Skipped {}: condition not met", dest.display()))
+            }
+            LinkResult::Backed { dest, backup, .. } => step(&format!(
+                "{}Backed {} \u{2192} {}",
+                prefix,
+                dest.display(),
+                backup.display()
+            )),
+            LinkResult::Conflict { dest, reason } => {
+                warning(&format!("Conflict at {}: {}", dest.display(), reason))
+            }
+            LinkResult::Adopted { src, dest, .. } => step(&format!(
+                "{}Adopted {} into {}",
+                prefix,
+                dest.display(),
+                src.display()
+            )),
+            LinkResult::Unfolded { dest, from } => step(&format!(
+                "{}Unfolded {} (was \u{2192} {})",
+                prefix,
+                dest.display(),
+                from.display()
+            )),
+            LinkResult::Expanded { src, dest } => step(&format!(
+                "{}Expanded {} (was \u{2192} {})",
+                prefix,
+                dest.display(),
+                src.display()
+            )),
+            LinkResult::Folded { src, dest } => step(&format!(
+                "{}Folded {} \u{2192} {}",
+                prefix,
+                dest.display(),
+                src.display()
+            )),
+        }
+    }
+}
+
+pub fn print_unlink_results(results: &[UnlinkResult], dry_run: bool) {
+    let prefix = if dry_run { "[preview] " } else { "" };
+    for r in results {
+        match r {
+            UnlinkResult::Removed { dest } => {
+                step(&format!("{}Unlinked: {}", prefix, dest.display()))
+            }
+            UnlinkResult::Restored { dest, backup } => step(&format!(
+                "{}Restored {} \u{2190} {}",
                 prefix,
                 dest.display(),
                 backup.display()
             )),
-            LinkResult::Conflict { dest, reason } => {
-                warning(&format!("Conflict at {}: {}", dest.display(), reason))
+            UnlinkResult::NotLinked { dest } => info(&format!("Not linked: {}", dest.display())),
+            UnlinkResult::Skipped { dest, reason } => {
+                info(&format!("Skipped {}: {}", dest.display(), reason))
             }
         }
     }
@@ -309,52 +1321,81 @@ mod tests {
             dry_run,
             force,
             backup,
+            relative_links: false,
+            ignore: IgnoreRules::none(),
+            prompt: None,
+            journal: None,
+            jobs: 1,
         }
     }
 
+    const HOST: Host = Host {
+        os: "linux",
+        distro: Some("debian"),
+        arch: "x86_64",
+        hostname: "host",
+        user: "user",
+    };
+
     #[test]
     fn should_link_no_condition() {
-        assert!(should_link(&None, "default", "linux", "host"));
+        assert!(should_link(None, "default", &HOST));
     }
 
     #[test]
     fn should_link_os_match() {
-        let c = Some(DotfileCondition {
+        let c = Condition {
             os: vec!["linux".into()],
             ..Default::default()
-        });
-        assert!(should_link(&c, "default", "linux", "host"));
-        assert!(!should_link(&c, "default", "macos", "host"));
+        };
+        assert!(should_link(Some(&c), "default", &HOST));
+        let mac = Host {
+            os: "macos",
+            ..HOST
+        };
+        assert!(!should_link(Some(&c), "default", &mac));
     }
 
     #[test]
     fn should_link_os_empty_allows_all() {
-        let c = Some(DotfileCondition {
+        let c = Condition {
             os: vec![],
             ..Default::default()
-        });
-        assert!(should_link(&c, "default", "linux", "host"));
-        assert!(should_link(&c, "default", "macos", "host"));
+        };
+        assert!(should_link(Some(&c), "default", &HOST));
+        let mac = Host {
+            os: "macos",
+            ..HOST
+        };
+        assert!(should_link(Some(&c), "default", &mac));
     }
 
     #[test]
     fn should_link_profile_filter() {
-        let c = Some(DotfileCondition {
+        let c = Condition {
             profile: vec!["work".into()],
             ..Default::default()
-        });
-        assert!(should_link(&c, "work", "linux", "host"));
-        assert!(!should_link(&c, "personal", "linux", "host"));
+        };
+        assert!(should_link(Some(&c), "work", &HOST));
+        assert!(!should_link(Some(&c), "personal", &HOST));
     }
 
     #[test]
     fn should_link_hostname_glob() {
-        let c = Some(DotfileCondition {
+        let c = Condition {
             hostname: Some("work-*".into()),
             ..Default::default()
-        });
-        assert!(should_link(&c, "default", "linux", "work-laptop"));
-        assert!(!should_link(&c, "default", "linux", "personal-mac"));
+        };
+        let laptop = Host {
+            hostname: "work-laptop",
+            ..HOST
+        };
+        let mac = Host {
+            hostname: "personal-mac",
+            ..HOST
+        };
+        assert!(should_link(Some(&c), "default", &laptop));
+        assert!(!should_link(Some(&c), "default", &mac));
     }
 
     #[test]
@@ -434,4 +1475,602 @@ mod tests {
         let r = link_one(&src, &dest, &ctx(&tmp, false, false, false)).unwrap();
         assert!(matches!(r, LinkResult::Skipped { .. }));
     }
+
+    fn copy_entry(dest: &Path, src: &Path) -> ManifestEntry {
+        let mut manifest = crate::manifest::Manifest::default();
+        manifest.record(dest, src, DeployKind::Copy, hash_path(src), "default");
+        manifest.get(dest).unwrap().clone()
+    }
+
+    #[test]
+    fn deploy_copy_creates_independent_file() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "data").unwrap();
+        let dest = tmp.path().join("copied");
+        let c = ctx(&tmp, false, false, false);
+
+        let r = deploy_one(&src, &dest, DeployMode::Copy, false, None, &c).unwrap();
+        assert!(matches!(r, LinkResult::Created { .. }));
+        assert!(!dest.is_symlink());
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "data");
+
+        let r = deploy_one(&src, &dest, DeployMode::Copy, false, None, &c).unwrap();
+        assert!(matches!(r, LinkResult::AlreadyLinked { .. }));
+    }
+
+    #[test]
+    fn deploy_copy_refreshes_unchanged_copy() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "v1").unwrap();
+        let dest = tmp.path().join("copied");
+        let c = ctx(&tmp, false, false, false);
+        deploy_one(&src, &dest, DeployMode::Copy, false, None, &c).unwrap();
+        let last = copy_entry(&dest, &src);
+
+        std::fs::write(&src, "v2").unwrap();
+        let r = deploy_one(&src, &dest, DeployMode::Copy, false, Some(&last), &c).unwrap();
+
+        assert!(matches!(r, LinkResult::Created { .. }));
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "v2");
+    }
+
+    #[test]
+    fn deploy_copy_edited_locally_is_conflict() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "v1").unwrap();
+        let dest = tmp.path().join("copied");
+        let c = ctx(&tmp, false, false, false);
+        deploy_one(&src, &dest, DeployMode::Copy, false, None, &c).unwrap();
+        let last = copy_entry(&dest, &src);
+
+        std::fs::write(&dest, "local edit").unwrap();
+        std::fs::write(&src, "v2").unwrap();
+        let r = deploy_one(&src, &dest, DeployMode::Copy, false, Some(&last), &c).unwrap();
+
+        match r {
+            LinkResult::Conflict { reason, .. } => assert!(reason.contains("--pull")),
+            other => panic!("expected conflict, got {:?}", other),
+        }
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "local edit");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deploy_hardlink_shares_inode() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "data").unwrap();
+        let dest = tmp.path().join("linked");
+        let c = ctx(&tmp, false, false, false);
+
+        let r = deploy_one(&src, &dest, DeployMode::Hardlink, false, None, &c).unwrap();
+        assert!(matches!(r, LinkResult::Created { .. }));
+        assert!(!dest.is_symlink());
+        assert!(same_file(&src, &dest));
+
+        let r = deploy_one(&src, &dest, DeployMode::Hardlink, false, None, &c).unwrap();
+        assert!(matches!(r, LinkResult::AlreadyLinked { .. }));
+    }
+
+    #[test]
+    fn deploy_switches_own_symlink_to_copy() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "data").unwrap();
+        let dest = tmp.path().join("linked");
+        let c = ctx(&tmp, false, false, false);
+        link_one(&src, &dest, &c).unwrap();
+
+        let r = deploy_one(&src, &dest, DeployMode::Copy, false, None, &c).unwrap();
+
+        assert!(matches!(r, LinkResult::Created { .. }));
+        assert!(!dest.is_symlink());
+    }
+
+    #[test]
+    fn relative_path_walks_up_to_common_ancestor() {
+        assert_eq!(
+            relative_path(Path::new("/home/u"), Path::new("/home/u/.dotfiles/.vimrc")),
+            PathBuf::from(".dotfiles/.vimrc")
+        );
+        assert_eq!(
+            relative_path(
+                Path::new("/home/u/.config/nvim"),
+                Path::new("/home/u/.dotfiles/nvim/init.lua")
+            ),
+            PathBuf::from("../../.dotfiles/nvim/init.lua")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deploy_relative_link_resolves_to_source() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("repo").join("dotfile");
+        std::fs::create_dir_all(src.parent().unwrap()).unwrap();
+        std::fs::write(&src, "data").unwrap();
+        let dest = tmp.path().join("home").join("linked");
+        let c = ctx(&tmp, false, false, false);
+
+        let r = deploy_one(&src, &dest, DeployMode::Symlink, true, None, &c).unwrap();
+
+        assert!(matches!(r, LinkResult::Created { .. }));
+        assert_eq!(
+            std::fs::read_link(&dest).unwrap(),
+            PathBuf::from("../repo/dotfile")
+        );
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "data");
+        let r = deploy_one(&src, &dest, DeployMode::Symlink, true, None, &c).unwrap();
+        assert!(matches!(r, LinkResult::AlreadyLinked { .. }));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deploy_migrates_absolute_link_to_relative() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "data").unwrap();
+        let dest = tmp.path().join("linked");
+        let c = ctx(&tmp, false, false, false);
+        link_one(&src, &dest, &c).unwrap();
+        assert!(std::fs::read_link(&dest).unwrap().is_absolute());
+
+        let r = deploy_one(&src, &dest, DeployMode::Symlink, true, None, &c).unwrap();
+
+        assert!(matches!(r, LinkResult::Created { .. }));
+        assert_eq!(std::fs::read_link(&dest).unwrap(), PathBuf::from("dotfile"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn relative_link_is_recognised_as_ours() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "data").unwrap();
+        let dest = tmp.path().join("linked");
+        std::os::unix::fs::symlink("./dotfile", &dest).unwrap();
+
+        let r = link_one(&src, &dest, &ctx(&tmp, false, false, false)).unwrap();
+        assert!(matches!(r, LinkResult::Created { .. }));
+        assert!(std::fs::read_link(&dest).unwrap().is_absolute());
+        let r = unlink_one(&dest, &ctx(&tmp, false, false, false), false).unwrap();
+        assert!(matches!(r, UnlinkResult::Removed { .. }));
+    }
+
+    fn stow_ctx(dotfiles: &Path, home: &Path, dry_run: bool) -> ApplyContext {
+        ApplyContext {
+            dotfiles_dir: dotfiles.to_owned(),
+            home_dir: home.to_owned(),
+            dry_run,
+            force: false,
+            backup: false,
+            relative_links: false,
+            ignore: IgnoreRules::none(),
+            prompt: None,
+            journal: None,
+            jobs: 1,
+        }
+    }
+
+    fn stow_dirs(tmp: &TempDir) -> (PathBuf, PathBuf) {
+        let dotfiles = tmp.path().join("dotfiles");
+        let home = tmp.path().join("home");
+        std::fs::create_dir_all(dotfiles.join(".config").join("nvim")).unwrap();
+        std::fs::write(
+            dotfiles.join(".config").join("nvim").join("init.vim"),
+            "set nu",
+        )
+        .unwrap();
+        std::fs::create_dir_all(&home).unwrap();
+        (dotfiles, home)
+    }
+
+    #[test]
+    fn stow_walk_folds_missing_directory() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        apply_stow_walk(&stow_ctx(&dotfiles, &home, false)).unwrap();
+        let link = home.join(".config");
+        assert!(link.is_symlink());
+        assert_eq!(std::fs::read_link(&link).unwrap(), dotfiles.join(".config"));
+    }
+
+    #[test]
+    fn stow_walk_descends_into_real_directory() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        std::fs::create_dir_all(home.join(".config").join("git")).unwrap();
+        std::fs::write(home.join(".config").join("git").join("config"), "x").unwrap();
+
+        apply_stow_walk(&stow_ctx(&dotfiles, &home, false)).unwrap();
+
+        assert!(!home.join(".config").is_symlink());
+        assert!(home.join(".config").join("git").join("config").is_file());
+        let nvim = home.join(".config").join("nvim");
+        assert!(nvim.is_symlink());
+        assert_eq!(
+            std::fs::read_link(&nvim).unwrap(),
+            dotfiles.join(".config").join("nvim")
+        );
+    }
+
+    #[test]
+    fn stow_walk_leaves_directory_link_outside_repo() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        let other = tmp.path().join("other").join(".config");
+        std::fs::create_dir_all(other.join("fish")).unwrap();
+        create_symlink(&other, &home.join(".config"), false).unwrap();
+
+        let results = apply_stow_walk(&stow_ctx(&dotfiles, &home, false)).unwrap();
+
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, LinkResult::Conflict { .. })));
+        assert_eq!(std::fs::read_link(home.join(".config")).unwrap(), other);
+        assert!(!other.join("nvim").exists());
+    }
+
+    #[test]
+    fn stow_walk_unfolds_directory_link_into_repo() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        let other = dotfiles.join("work").join(".config");
+        std::fs::create_dir_all(other.join("fish")).unwrap();
+        create_symlink(&other, &home.join(".config"), false).unwrap();
+
+        let results = apply_stow_walk(&stow_ctx(&dotfiles, &home, false)).unwrap();
+
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, LinkResult::Unfolded { .. })));
+        let config = home.join(".config");
+        assert!(config.is_dir() && !config.is_symlink());
+        assert_eq!(
+            std::fs::read_link(config.join("fish")).unwrap(),
+            other.join("fish")
+        );
+        assert_eq!(
+            std::fs::read_link(config.join("nvim")).unwrap(),
+            dotfiles.join(".config").join("nvim")
+        );
+    }
+
+    #[test]
+    fn stow_walk_unfold_dry_run_changes_nothing() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        let other = dotfiles.join("work").join(".config");
+        std::fs::create_dir_all(other.join("fish")).unwrap();
+        create_symlink(&other, &home.join(".config"), false).unwrap();
+
+        apply_stow_walk(&stow_ctx(&dotfiles, &home, true)).unwrap();
+
+        assert!(home.join(".config").is_symlink());
+        assert!(!other.join("nvim").exists());
+    }
+
+    #[test]
+    fn stow_walk_refolds_directory_of_own_links() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        std::fs::create_dir_all(home.join(".config")).unwrap();
+        create_symlink(
+            &dotfiles.join(".config").join("nvim"),
+            &home.join(".config").join("nvim"),
+            false,
+        )
+        .unwrap();
+
+        let results = apply_stow_walk(&stow_ctx(&dotfiles, &home, false)).unwrap();
+
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, LinkResult::Folded { .. })));
+        assert_eq!(
+            std::fs::read_link(home.join(".config")).unwrap(),
+            dotfiles.join(".config")
+        );
+    }
+
+    #[test]
+    fn stow_walk_reports_conflict_inside_mixed_tree() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        std::fs::create_dir_all(home.join(".config").join("nvim")).unwrap();
+        std::fs::write(home.join(".config").join("nvim").join("init.vim"), "local").unwrap();
+
+        let results = apply_stow_walk(&stow_ctx(&dotfiles, &home, false)).unwrap();
+
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, LinkResult::Conflict { .. })));
+        assert!(!home
+            .join(".config")
+            .join("nvim")
+            .join("init.vim")
+            .is_symlink());
+    }
+
+    #[test]
+    fn unlink_one_removes_own_link() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "data").unwrap();
+        let dest = tmp.path().join("linked");
+        create_symlink(&src, &dest, false).unwrap();
+        let r = unlink_one(&dest, &ctx(&tmp, false, false, false), false).unwrap();
+        assert!(matches!(r, UnlinkResult::Removed { .. }));
+        assert!(!dest.is_symlink());
+        assert!(src.exists());
+    }
+
+    #[test]
+    fn unlink_one_leaves_foreign_link() {
+        let tmp = TempDir::new().unwrap();
+        let dotfiles = tmp.path().join("dotfiles");
+        std::fs::create_dir_all(&dotfiles).unwrap();
+        let foreign = tmp.path().join("elsewhere");
+        std::fs::write(&foreign, "data").unwrap();
+        let dest = tmp.path().join("linked");
+        create_symlink(&foreign, &dest, false).unwrap();
+        let r = unlink_one(&dest, &stow_ctx(&dotfiles, tmp.path(), false), false).unwrap();
+        assert!(matches!(r, UnlinkResult::Skipped { .. }));
+        assert!(dest.is_symlink());
+    }
+
+    #[test]
+    fn unlink_one_restores_latest_backup() {
+        let tmp = TempDir::new().unwrap();
+        let backups = tmp.path().join(".heimdal").join("backups");
+        std::fs::create_dir_all(&backups).unwrap();
+        std::fs::write(backups.join("linked.20240101T000000Z"), "old").unwrap();
+        std::fs::write(backups.join("linked.20250101T000000Z"), "newer").unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "data").unwrap();
+        let dest = tmp.path().join("linked");
+        create_symlink(&src, &dest, false).unwrap();
+
+        let r = unlink_one(&dest, &ctx(&tmp, false, false, false), true).unwrap();
+
+        assert!(matches!(r, UnlinkResult::Restored { .. }));
+        assert!(!dest.is_symlink());
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "newer");
+    }
+
+    #[test]
+    fn unlink_stow_walk_reverses_unfolded_tree() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        std::fs::create_dir_all(home.join(".config").join("git")).unwrap();
+        apply_stow_walk(&stow_ctx(&dotfiles, &home, false)).unwrap();
+
+        let results = unlink_stow_walk(&stow_ctx(&dotfiles, &home, false), false).unwrap();
+
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, UnlinkResult::Removed { .. })));
+        assert!(!home.join(".config").join("nvim").exists());
+        assert!(home.join(".config").join("git").is_dir());
+    }
+
+    fn ignoring(mut ctx: ApplyContext, patterns: &[&str]) -> ApplyContext {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        ctx.ignore = IgnoreRules::load(&ctx.dotfiles_dir, &patterns).unwrap();
+        ctx
+    }
+
+    #[test]
+    fn stow_walk_skips_ignored_entries() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        std::fs::write(dotfiles.join("notes.md"), "").unwrap();
+        std::fs::write(dotfiles.join(".zshrc"), "").unwrap();
+
+        apply_stow_walk(&ignoring(stow_ctx(&dotfiles, &home, false), &["*.md"])).unwrap();
+
+        assert!(!home.join("notes.md").exists());
+        assert!(home.join(".zshrc").is_symlink());
+    }
+
+    #[test]
+    fn stow_walk_does_not_fold_directory_with_ignored_entries() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        let nvim = dotfiles.join(".config").join("nvim");
+        std::fs::write(nvim.join(".DS_Store"), "").unwrap();
+
+        apply_stow_walk(&ignoring(stow_ctx(&dotfiles, &home, false), &[".DS_Store"])).unwrap();
+
+        let dest = home.join(".config").join("nvim");
+        assert!(!home.join(".config").is_symlink());
+        assert!(dest.is_dir() && !dest.is_symlink());
+        assert!(dest.join("init.vim").is_symlink());
+        assert!(!dest.join(".DS_Store").exists());
+    }
+
+    #[test]
+    fn stow_walk_expands_folded_link_once_contents_are_ignored() {
+        let tmp = TempDir::new().unwrap();
+        let (dotfiles, home) = stow_dirs(&tmp);
+        apply_stow_walk(&stow_ctx(&dotfiles, &home, false)).unwrap();
+        assert!(home.join(".config").is_symlink());
+        std::fs::write(dotfiles.join(".config").join("secret.local"), "").unwrap();
+
+        let results =
+            apply_stow_walk(&ignoring(stow_ctx(&dotfiles, &home, false), &["*.local"])).unwrap();
+
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, LinkResult::Expanded { .. })));
+        assert!(!home.join(".config").is_symlink());
+        assert!(home.join(".config").join("nvim").is_symlink());
+        assert!(!home.join(".config").join("secret.local").exists());
+    }
+
+    fn remembering(mut ctx: ApplyContext, choice: Resolution) -> ApplyContext {
+        let prompt = ConflictPrompt::default();
+        *prompt.remembered.lock().unwrap() = Some(choice);
+        ctx.prompt = Some(prompt);
+        ctx
+    }
+
+    fn conflicting(tmp: &TempDir) -> (PathBuf, PathBuf) {
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "repo").unwrap();
+        let dest = tmp.path().join("linked");
+        std::fs::write(&dest, "local").unwrap();
+        (src, dest)
+    }
+
+    #[test]
+    fn prompt_adopt_replaces_repo_copy() {
+        let tmp = TempDir::new().unwrap();
+        let (src, dest) = conflicting(&tmp);
+        let c = remembering(ctx(&tmp, false, false, false), Resolution::Adopt);
+
+        let r = link_one(&src, &dest, &c).unwrap();
+
+        assert!(matches!(r, LinkResult::Adopted { .. }));
+        assert!(dest.is_symlink());
+        assert_eq!(std::fs::read_to_string(&src).unwrap(), "local");
+    }
+
+    #[test]
+    fn prompt_skip_keeps_existing_file() {
+        let tmp = TempDir::new().unwrap();
+        let (src, dest) = conflicting(&tmp);
+        let c = remembering(ctx(&tmp, false, false, false), Resolution::Skip);
+
+        let r = link_one(&src, &dest, &c).unwrap();
+
+        assert!(matches!(r, LinkResult::Skipped { .. }));
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "local");
+        assert_eq!(std::fs::read_to_string(&src).unwrap(), "repo");
+    }
+
+    #[test]
+    fn skipped_target_stays_in_manifest() {
+        let tmp = TempDir::new().unwrap();
+        let (src, dest) = conflicting(&tmp);
+        let mut manifest = Manifest::default();
+        manifest.record(&dest, &src, DeployKind::Symlink, hash_path(&src), "p");
+        let c = remembering(ctx(&tmp, false, false, false), Resolution::Skip);
+
+        let results = apply_mappings(&c, &[mapped("dotfile", &dest)], "p", &manifest).unwrap();
+
+        assert!(matches!(results[0], LinkResult::Skipped { .. }));
+        let current = results
+            .iter()
+            .filter_map(LinkResult::managed)
+            .map(Path::to_owned)
+            .collect();
+        assert!(manifest.orphans(&current).is_empty());
+    }
+
+    #[test]
+    fn prompt_overwrite_and_backup() {
+        let tmp = TempDir::new().unwrap();
+        let (src, dest) = conflicting(&tmp);
+        let c = remembering(ctx(&tmp, false, false, false), Resolution::Overwrite);
+        assert!(matches!(
+            link_one(&src, &dest, &c).unwrap(),
+            LinkResult::Created { .. }
+        ));
+
+        std::fs::remove_file(&dest).unwrap();
+        std::fs::write(&dest, "local").unwrap();
+        let c = remembering(ctx(&tmp, false, false, false), Resolution::Backup);
+        assert!(matches!(
+            link_one(&src, &dest, &c).unwrap(),
+            LinkResult::Backed { .. }
+        ));
+        assert!(dest.is_symlink());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prompt_adopt_all_skips_foreign_symlinks() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("dotfile");
+        std::fs::write(&src, "repo").unwrap();
+        let dest = tmp.path().join("linked");
+        std::os::unix::fs::symlink("/nonexistent", &dest).unwrap();
+        let c = remembering(ctx(&tmp, false, false, false), Resolution::Adopt);
+
+        let r = link_one(&src, &dest, &c).unwrap();
+
+        assert!(matches!(r, LinkResult::Skipped { .. }));
+        assert_eq!(std::fs::read_to_string(&src).unwrap(), "repo");
+    }
+
+    fn mapped(source: &str, target: &Path) -> DotfileEntry {
+        DotfileEntry::Mapped(crate::config::DotfileMapping {
+            source: source.to_string(),
+            target: target.display().to_string(),
+            when: None,
+            mode: DeployMode::Symlink,
+            relative_links: None,
+            file_mode: None,
+            dir_mode: None,
+        })
+    }
+
+    #[test]
+    fn nested_targets_share_a_group_in_config_order() {
+        let link = |dest: &str| PlannedLink {
+            src: PathBuf::from("/repo/x"),
+            dest: PathBuf::from(dest),
+            mode: DeployMode::Symlink,
+            relative: false,
+            perms: Permissions::default(),
+        };
+        let links = [
+            link("/h/.config/nvim/init.lua"),
+            link("/h/.zshrc"),
+            link("/h/.config/nvim"),
+            link("/h/.config/nvim-old"),
+        ];
+        let planned: Vec<_> = links.iter().enumerate().collect();
+        assert_eq!(
+            independent_groups(&planned),
+            vec![vec![0, 2], vec![3], vec![1]]
+        );
+    }
+
+    #[test]
+    fn parallel_apply_matches_sequential_order() {
+        let tmp = TempDir::new().unwrap();
+        let home = tmp.path().join("home");
+        let mut entries = Vec::new();
+        for i in 0..64 {
+            std::fs::write(tmp.path().join(format!("f{}", i)), "x").unwrap();
+            entries.push(mapped(
+                &format!("f{}", i),
+                &home.join(format!("d{}/f", i % 5)),
+            ));
+        }
+        entries.push(mapped("missing", &home.join("missing")));
+
+        let mut c = ctx(&tmp, false, false, false);
+        c.jobs = 8;
+        let results = apply_mappings(&c, &entries, "p", &Manifest::default()).unwrap();
+        assert_eq!(results.len(), entries.len());
+        for (i, r) in results.iter().enumerate().take(64) {
+            let expected = home.join(format!("d{}/f", i % 5));
+            // Only the first entry for each target creates it
+            match r {
+                LinkResult::Created { src, dest, .. } if i < 5 => {
+                    assert_eq!(dest, &expected);
+                    assert_eq!(src, &tmp.path().join(format!("f{}", i)));
+                }
+                LinkResult::Conflict { dest, .. } if i >= 5 => assert_eq!(dest, &expected),
+                other => panic!("entry {}: {:?}", i, other),
+            }
+        }
+        assert!(matches!(results[64], LinkResult::Skipped { .. }));
+    }
 }
@@ -37,16 +37,19 @@ pub fn render_string(content: &str, vars: &HashMap<String, String>) -> String {
         .to_string()
 }
 
+/// The names of the `{{ ... }}` placeholders in `content`, in order.
+pub fn placeholders(content: &str) -> Vec<&str> {
+    VAR_RE
+        .captures_iter(content)
+        .filter_map(|caps| caps.get(1))
+        .map(|m| m.as_str())
+        .collect()
+}
+
 /// System variables: hostname, username, os, home
 pub fn system_vars() -> HashMap<String, String> {
     let mut vars = HashMap::new();
-    vars.insert(
-        "hostname".to_string(),
-        hostname::get()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string(),
-    );
+    vars.insert("hostname".to_string(), crate::utils::hostname().to_string());
     vars.insert("username".to_string(), whoami::username());
     vars.insert("os".to_string(), crate::utils::os_name().to_string());
     vars.insert(
@@ -59,14 +62,35 @@ pub fn system_vars() -> HashMap<String, String> {
     vars
 }
 
+/// This machine's overrides from `~/.heimdal/variables.yaml`, if it exists.
+pub fn local_vars() -> Result<HashMap<String, String>> {
+    let path = crate::utils::local_variables_path()?;
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let text = std::fs::read_to_string(&path)?;
+    if text.trim().is_empty() {
+        return Ok(HashMap::new());
+    }
+    serde_yaml_ng::from_str(&text).map_err(|e| {
+        crate::error::HeimdallError::Config(format!("{}: {}", path.display(), e)).into()
+    })
+}
+
 /// Build combined variable map. env_prefix is "env" so env vars are {{ env.HOME }}.
-pub fn build_vars(explicit: &HashMap<String, String>, env_prefix: &str) -> HashMap<String, String> {
+/// Later sources win: system variables, then `variables` (the config's and
+/// the profile's, as resolved), then `explicit` (an entry's own `vars`),
+/// then this machine's local overrides.
+pub fn build_vars(
+    variables: &HashMap<String, String>,
+    explicit: &HashMap<String, String>,
+    env_prefix: &str,
+) -> Result<HashMap<String, String>> {
     let mut vars = system_vars();
     for (k, v) in std::env::vars() {
         vars.insert(format!("{}.{}", env_prefix, k), v);
     }
-    // Explicit vars override system/env
-    for (k, v) in explicit {
+    for (k, v) in variables.iter().chain(explicit).chain(&local_vars()?) {
         vars.insert(k.clone(), v.clone());
     }
     // Resolve {{ secret:name }} values
@@ -94,7 +118,7 @@ pub fn build_vars(explicit: &HashMap<String, String>, env_prefix: &str) -> HashM
             }
         }
     }
-    vars
+    Ok(vars)
 }
 
 #[cfg(test)]
@@ -121,26 +145,49 @@ mod tests {
     }
 }
 
-/// Render a template file to a destination.
+/// Render a template file to a destination. Returns the rendered text, which
+/// in dry-run mode is all that happens.
 pub fn render_file(
     src: &Path,
     dest: &Path,
     vars: &HashMap<String, String>,
     dry_run: bool,
-) -> Result<()> {
+    journal: Option<&crate::journal::Journal>,
+    perms: &crate::permissions::Permissions,
+) -> Result<String> {
     let content = std::fs::read_to_string(src)
         .map_err(|e| anyhow::anyhow!("Cannot read template '{}': {}", src.display(), e))?;
     let rendered = render_string(&content, vars);
 
     if dry_run {
-        println!("--- [dry-run] Would write: {} ---", dest.display());
-        print!("{}", rendered);
-        return Ok(());
+        return Ok(rendered);
     }
 
-    if let Some(parent) = dest.parent() {
-        std::fs::create_dir_all(parent)?;
+    // Secrets may be in the output, so files and directories are created
+    // with their configured modes rather than tightened afterwards
+    let created = crate::permissions::missing_ancestors(dest);
+    match journal {
+        Some(journal) => {
+            if let Some(parent) = dest.parent() {
+                journal.create_dir_all(parent, perms.dir)?;
+            }
+            journal.write(dest, &rendered, perms.file)?;
+        }
+        None => {
+            if let Some(parent) = dest.parent() {
+                crate::permissions::create_dir_all(parent, perms.dir)?;
+            }
+            let name = dest.file_name().unwrap_or_default().to_string_lossy();
+            let tmp = dest.with_file_name(format!(".{}.heimdal-tmp", name));
+            let _ = std::fs::remove_file(&tmp);
+            crate::permissions::create_file(&tmp, &rendered, perms.file)?;
+            if let Err(e) = std::fs::rename(&tmp, dest) {
+                let _ = std::fs::remove_file(&tmp);
+                return Err(e.into());
+            }
+        }
     }
-    std::fs::write(dest, rendered)?;
-    Ok(())
+    perms.enforce(dest)?;
+    perms.enforce_created(&created)?;
+    Ok(rendered)
 }
@@ -1,8 +1,9 @@
 #![allow(dead_code)]
 
 use colored::Colorize;
+use once_cell::sync::Lazy;
 use std::borrow::Cow;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 // Terminal output
 pub fn success(msg: &str) {
@@ -31,6 +32,22 @@ pub enum LinuxDistro {
     Other,
 }
 
+impl LinuxDistro {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinuxDistro::Debian => "debian",
+            LinuxDistro::Ubuntu => "ubuntu",
+            LinuxDistro::Fedora => "fedora",
+            LinuxDistro::Rhel => "rhel",
+            LinuxDistro::CentOs => "centos",
+            LinuxDistro::Arch => "arch",
+            LinuxDistro::Manjaro => "manjaro",
+            LinuxDistro::Alpine => "alpine",
+            LinuxDistro::Other => "other",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Os {
     MacOS,
@@ -101,18 +118,175 @@ pub fn detect_os() -> Os {
     Os::Unknown
 }
 
+/// Detected once per process; on Linux it means reading `/etc/os-release`.
+static OS: Lazy<Os> = Lazy::new(detect_os);
+
+static HOSTNAME: Lazy<String> = Lazy::new(|| {
+    hostname::get()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+});
+
 pub fn os_name() -> &'static str {
-    match detect_os() {
+    match *OS {
         Os::MacOS => "macos",
         Os::Linux(_) => "linux",
         Os::Unknown => "unknown",
     }
 }
 
+/// The Linux distribution, `None` on other systems.
+pub fn distro_name() -> Option<&'static str> {
+    match &*OS {
+        Os::Linux(distro) => Some(distro.name()),
+        _ => None,
+    }
+}
+
+pub fn hostname() -> &'static str {
+    &HOSTNAME
+}
+
+static USERNAME: Lazy<String> = Lazy::new(whoami::username);
+
+pub fn username() -> &'static str {
+    &USERNAME
+}
+
 pub fn expand_path(p: &str) -> PathBuf {
     PathBuf::from(shellexpand::full(p).unwrap_or(Cow::Borrowed(p)).as_ref())
 }
 
+/// Copy a file, symlink or directory tree, preserving symlinks as symlinks.
+pub fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    let meta = std::fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        let target = std::fs::read_link(from)?;
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(target, to);
+        #[cfg(windows)]
+        return std::os::windows::fs::symlink_file(target, to);
+    }
+    if meta.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        return Ok(());
+    }
+    std::fs::copy(from, to).map(|_| ())
+}
+
+/// `rename`, falling back to copy + delete across filesystems. Any other
+/// failure, like a non-empty directory at `to`, is returned as is.
+pub fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(e) if !crosses_devices(&e) => return Err(e),
+        Err(_) => {}
+    }
+    crate::utils::copy_recursive(from, to)?;
+    if from.is_dir() && !from.is_symlink() {
+        std::fs::remove_dir_all(from)
+    } else {
+        std::fs::remove_file(from)
+    }
+}
+
+/// Whether `rename` failed because `from` and `to` are on different
+/// filesystems (`EXDEV`). `ErrorKind::CrossesDevices` needs Rust 1.85.
+fn crosses_devices(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    const EXDEV: i32 = 18;
+    // ERROR_NOT_SAME_DEVICE
+    #[cfg(not(unix))]
+    const EXDEV: i32 = 17;
+    e.raw_os_error() == Some(EXDEV)
+}
+
+/// Print a coloured unified diff from `old` to `new`. Directories and
+/// non-UTF-8 files are summarised instead of diffed.
+pub fn print_diff(old: &Path, new: &Path) {
+    let read = |p: &Path| {
+        if p.is_dir() {
+            None
+        } else {
+            std::fs::read(p)
+                .ok()
+                .and_then(|b| String::from_utf8(b).ok())
+        }
+    };
+    let (Some(a), Some(b)) = (read(old), read(new)) else {
+        println!(
+            "  {} and {} differ (directory or binary)",
+            old.display(),
+            new.display()
+        );
+        return;
+    };
+    let a: Vec<&str> = a.lines().collect();
+    let b: Vec<&str> = b.lines().collect();
+    let from = old.display().to_string();
+    let to = new.display().to_string();
+    let diff = difflib::unified_diff(&a, &b, &from, &to, "", "", 3);
+    if diff.is_empty() {
+        println!("  (contents are identical)");
+    }
+    for line in diff {
+        let line = line.trim_end_matches('\n');
+        if line.starts_with("---") || line.starts_with("+++") {
+            println!("{}", line.bold());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Rewrite a file heimdal shares with its owner, keeping its permissions. A
+/// symlinked target is written through, since the file belongs to whoever
+/// made the link.
+pub fn rewrite_file(
+    dest: &Path,
+    content: &str,
+    journal: Option<&crate::journal::Journal>,
+) -> anyhow::Result<()> {
+    let path: PathBuf = if dest.is_symlink() {
+        std::fs::canonicalize(dest)?
+    } else {
+        dest.to_owned()
+    };
+    let permissions = std::fs::metadata(&path).ok().map(|m| m.permissions());
+    match journal {
+        Some(journal) => {
+            if let Some(parent) = path.parent() {
+                journal.create_dir_all(parent, None)?;
+            }
+            journal.write(&path, content, None)?;
+        }
+        None => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, content)?;
+        }
+    }
+    if let Some(permissions) = permissions {
+        std::fs::set_permissions(&path, permissions)?;
+    }
+    Ok(())
+}
+
 pub fn home_dir() -> anyhow::Result<PathBuf> {
     dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Cannot determine home directory"))
 }
@@ -125,6 +299,19 @@ pub fn state_path() -> anyhow::Result<PathBuf> {
     Ok(home_dir()?.join(".heimdal").join("state.json"))
 }
 
+pub fn manifest_path() -> anyhow::Result<PathBuf> {
+    Ok(home_dir()?.join(".heimdal").join("manifest.json"))
+}
+
+/// Template variables for this machine only, kept out of the dotfiles repo.
+pub fn local_variables_path() -> anyhow::Result<PathBuf> {
+    Ok(home_dir()?.join(".heimdal").join("variables.yaml"))
+}
+
+pub fn journal_dir() -> anyhow::Result<PathBuf> {
+    Ok(home_dir()?.join(".heimdal").join("journal"))
+}
+
 pub fn confirm(prompt: &str) -> bool {
     dialoguer::Confirm::new()
         .with_prompt(prompt)
@@ -0,0 +1,364 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::condition::{Condition, OS_NAMES};
+use crate::config::{
+    resolve_profile, DotfileEntry, HeimdalConfig, HookEntry, PackageMap, Profile, ProfileHooks,
+};
+use crate::utils::expand_path;
+
+/// How bad a finding is. Errors fail `heimdal validate`; warnings only do
+/// with `--strict`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One problem `check` found, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// `Profile 'work'`, `Mixin 'k8s'` or `Top-level packages`.
+    pub scope: String,
+    pub message: String,
+}
+
+/// Checks that look past the shape of the config: files it names exist in
+/// the repo, nothing is deployed twice, conditions can hold, secrets and
+/// template variables are defined, and no package is listed twice. Each
+/// profile and mixin is checked as written; profiles are also checked as
+/// resolved, for what only shows once parents are merged in.
+pub fn check(config: &HeimdalConfig, dotfiles_dir: &Path) -> Vec<Finding> {
+    let secrets: HashSet<String> = crate::secrets::list_secrets(dotfiles_dir)
+        .into_iter()
+        .collect();
+    let mut findings = Vec::new();
+
+    let mut report = Report {
+        scope: "Top-level packages".to_string(),
+        findings: &mut findings,
+    };
+    report.duplicate_packages(&config.packages, "");
+
+    let mut layers: Vec<(String, &Profile)> = config
+        .profiles
+        .iter()
+        .map(|(name, p)| (format!("Profile '{}'", name), p))
+        .chain(
+            config
+                .mixins
+                .iter()
+                .map(|(name, p)| (format!("Mixin '{}'", name), p)),
+        )
+        .collect();
+    layers.sort_by(|a, b| a.0.cmp(&b.0));
+    for (scope, profile) in layers {
+        let mut report = Report {
+            scope,
+            findings: &mut findings,
+        };
+        report.as_written(profile, dotfiles_dir, &secrets);
+    }
+
+    let mut names: Vec<_> = config.profiles.keys().collect();
+    names.sort();
+    let local = crate::templates::local_vars().unwrap_or_default();
+    for name in names {
+        // Profiles that fail to resolve are reported by `validate_config`
+        let Ok(profile) = resolve_profile(config, name) else {
+            continue;
+        };
+        let mut report = Report {
+            scope: format!("Profile '{}'", name),
+            findings: &mut findings,
+        };
+        report.as_resolved(&profile, dotfiles_dir, &local);
+    }
+
+    // Several findings for one scope read best together
+    findings.sort_by(|a, b| a.scope.cmp(&b.scope));
+    findings
+}
+
+struct Report<'a> {
+    scope: String,
+    findings: &'a mut Vec<Finding>,
+}
+
+impl Report<'_> {
+    fn push(&mut self, severity: Severity, message: String) {
+        self.findings.push(Finding {
+            severity,
+            scope: self.scope.clone(),
+            message,
+        });
+    }
+
+    fn as_written(&mut self, profile: &Profile, dotfiles_dir: &Path, secrets: &HashSet<String>) {
+        let sources = profile
+            .dotfiles
+            .iter()
+            .map(|d| ("dotfile source", d.source()))
+            .chain(
+                profile
+                    .templates
+                    .iter()
+                    .map(|t| ("template", t.src.as_str())),
+            )
+            .chain(
+                profile
+                    .blocks
+                    .iter()
+                    .map(|b| ("block source", b.source.as_str())),
+            )
+            .chain(
+                profile
+                    .merges
+                    .iter()
+                    .map(|m| ("merge source", m.source.as_str())),
+            );
+        for (what, source) in sources {
+            if !dotfiles_dir.join(source).exists() {
+                self.push(
+                    Severity::Error,
+                    format!("{} '{}' does not exist in the repo", what, source),
+                );
+            }
+        }
+
+        // Entries for one target are alternatives, so each needs a condition
+        let targets = profile.dotfiles.iter().map(|d| {
+            let conditional = matches!(d, DotfileEntry::Mapped(m) if m.when.is_some());
+            ("dotfiles", d.target(), conditional)
+        });
+        let dests = profile
+            .templates
+            .iter()
+            .map(|t| ("templates", t.dest.clone(), t.when.is_some()));
+        for list in [targets.collect::<Vec<_>>(), dests.collect()] {
+            let mut seen: HashMap<PathBuf, bool> = HashMap::new();
+            let mut reported = HashSet::new();
+            for (what, target, conditional) in list {
+                let path = expand_path(&target);
+                let Some(all_conditional) = seen.get_mut(&path) else {
+                    seen.insert(path, conditional);
+                    continue;
+                };
+                *all_conditional &= conditional;
+                if !*all_conditional && reported.insert(path) {
+                    self.push(
+                        Severity::Error,
+                        format!(
+                            "{} lists {} more than once; give each entry a when: so only one applies",
+                            what, target
+                        ),
+                    );
+                }
+            }
+        }
+
+        let mut conditions: Vec<(String, &Condition)> = Vec::new();
+        if let Some(machine) = &profile.machine {
+            conditions.push(("match".to_string(), machine));
+        }
+        for d in &profile.dotfiles {
+            if let DotfileEntry::Mapped(m) = d {
+                if let Some(when) = &m.when {
+                    conditions.push((format!("dotfile '{}'", m.source), when));
+                }
+            }
+        }
+        for t in &profile.templates {
+            if let Some(when) = &t.when {
+                conditions.push((format!("template '{}'", t.src), when));
+            }
+        }
+        for phase in ProfileHooks::PHASES {
+            for hook in profile.hooks.phase(phase) {
+                let HookEntry::Full {
+                    command, os, when, ..
+                } = hook
+                else {
+                    continue;
+                };
+                let unknown = os.iter().filter(|o| !OS_NAMES.contains(&o.as_str()));
+                for o in unknown {
+                    self.push(
+                        Severity::Error,
+                        format!(
+                            "{} hook '{}': os '{}' is never matched (expected macos or linux)",
+                            phase, command, o
+                        ),
+                    );
+                }
+                if let Some(when) = when {
+                    conditions.push((format!("{} hook '{}'", phase, command), when));
+                }
+            }
+        }
+        conditional_groups(&profile.packages, &mut conditions);
+        for (what, condition) in conditions {
+            for problem in condition.problems() {
+                self.push(Severity::Error, format!("{}: {}", what, problem));
+            }
+        }
+
+        self.duplicate_packages(&profile.packages, "");
+
+        let mut texts: Vec<(String, String)> = Vec::new();
+        for t in &profile.templates {
+            if let Ok(content) = std::fs::read_to_string(dotfiles_dir.join(&t.src)) {
+                texts.push((format!("template '{}'", t.src), content));
+            }
+            for value in t.vars.values() {
+                texts.push((format!("vars of template '{}'", t.src), value.clone()));
+            }
+        }
+        for b in profile.blocks.iter().filter(|b| b.template) {
+            if let Ok(content) = std::fs::read_to_string(dotfiles_dir.join(&b.source)) {
+                texts.push((format!("block '{}'", b.id), content));
+            }
+            for value in b.vars.values() {
+                texts.push((format!("vars of block '{}'", b.id), value.clone()));
+            }
+        }
+        for value in profile.variables.values() {
+            texts.push(("variables".to_string(), value.clone()));
+        }
+        let mut reported = HashSet::new();
+        for (what, text) in &texts {
+            for name in crate::templates::placeholders(text) {
+                let Some(secret) = name.strip_prefix("secret:") else {
+                    continue;
+                };
+                if !secrets.contains(secret) && reported.insert((what.clone(), secret)) {
+                    self.push(
+                        Severity::Warning,
+                        format!(
+                            "{} uses secret '{}', which is not in the secrets manifest",
+                            what, secret
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn as_resolved(
+        &mut self,
+        profile: &Profile,
+        dotfiles_dir: &Path,
+        local: &HashMap<String, String>,
+    ) {
+        // A dotfile and a template deployed to the same place
+        let mut targets: HashMap<PathBuf, (String, bool)> = HashMap::new();
+        for d in &profile.dotfiles {
+            let conditional = matches!(d, DotfileEntry::Mapped(m) if m.when.is_some());
+            targets.insert(
+                expand_path(&d.target()),
+                (format!("dotfile '{}'", d.source()), conditional),
+            );
+        }
+        for t in &profile.templates {
+            let Some((other, other_conditional)) = targets.get(&expand_path(&t.dest)) else {
+                continue;
+            };
+            if t.when.is_some() && *other_conditional {
+                self.push(
+                    Severity::Warning,
+                    format!(
+                        "template '{}' and {} both deploy to {}; make sure their when: conditions never both hold",
+                        t.src, other, t.dest
+                    ),
+                );
+            } else {
+                self.push(
+                    Severity::Error,
+                    format!(
+                        "template '{}' and {} both deploy to {}",
+                        t.src, other, t.dest
+                    ),
+                );
+            }
+        }
+
+        let system = crate::templates::system_vars();
+        let defined = |name: &str, vars: &HashMap<String, String>| {
+            // What the environment holds differs from machine to machine
+            name.starts_with("env.")
+                || name.starts_with("secret:")
+                || system.contains_key(name)
+                || profile.variables.contains_key(name)
+                || vars.contains_key(name)
+                || local.contains_key(name)
+        };
+        let rendered = profile
+            .templates
+            .iter()
+            .map(|t| (format!("template '{}'", t.src), &t.src, &t.vars))
+            .chain(
+                profile
+                    .blocks
+                    .iter()
+                    .filter(|b| b.template)
+                    .map(|b| (format!("block '{}'", b.id), &b.source, &b.vars)),
+            );
+        for (what, src, vars) in rendered {
+            let Ok(content) = std::fs::read_to_string(dotfiles_dir.join(src)) else {
+                continue;
+            };
+            let mut reported = HashSet::new();
+            for name in crate::templates::placeholders(&content) {
+                if !defined(name, vars) && reported.insert(name) {
+                    self.push(
+                        Severity::Warning,
+                        format!(
+                            "{} uses {{{{ {} }}}}, which no variables: or vars: defines",
+                            what, name
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Names listed twice for one manager. `group` says which conditional
+    /// group, if any.
+    fn duplicate_packages(&mut self, packages: &PackageMap, group: &str) {
+        for (manager, names) in packages.lists() {
+            let mut seen = HashSet::new();
+            let mut reported = HashSet::new();
+            for name in names {
+                if !seen.insert(name) && reported.insert(name) {
+                    self.push(
+                        Severity::Warning,
+                        format!("{}{} lists '{}' more than once", group, manager, name),
+                    );
+                }
+            }
+        }
+        for (i, nested) in packages.conditional.iter().enumerate() {
+            let group = format!("{}conditional[{}].", group, i);
+            self.duplicate_packages(&nested.packages, &group);
+        }
+    }
+}
+
+/// The `when:` of every conditional package group, nested ones included.
+fn conditional_groups<'a>(packages: &'a PackageMap, out: &mut Vec<(String, &'a Condition)>) {
+    for group in &packages.conditional {
+        out.push(("conditional packages".to_string(), &group.when));
+        conditional_groups(&group.packages, out);
+    }
+}
@@ -0,0 +1,993 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_yaml_ng::{Mapping, Value};
+use std::collections::HashSet;
+
+/// One step from a YAML node to a child: a mapping key or a list index.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Seg {
+    Key(Value),
+    Index(usize),
+}
+
+/// Rewrite `text`, which deserialized to `old`, so that it deserializes to
+/// `new`, touching only the lines that hold what changed. Comments, key
+/// order and layout everywhere else stay as written. `None` means the edit
+/// can't be made in place and the file should be written out whole.
+pub fn update<T: Serialize + DeserializeOwned>(text: &str, old: &Value, new: &T) -> Option<String> {
+    let new = serde_yaml_ng::to_value(new).ok()?;
+    let raw: Value = serde_yaml_ng::from_str(text).ok()?;
+    let normalize = |v: &Value| {
+        serde_yaml_ng::from_value::<T>(v.clone())
+            .ok()
+            .and_then(|t| serde_yaml_ng::to_value(t).ok())
+    };
+
+    let mut added = Vec::new();
+    let mut target = carry(&raw, old, &new, &mut Vec::new(), &mut added);
+    for path in &added {
+        drop_defaults(&mut target, path, &new, &normalize);
+    }
+    if normalize(&target)? != new {
+        return None;
+    }
+
+    let mut doc = Document::parse(text)?;
+    doc.sync(&mut Vec::new(), &raw, &target)?;
+    let out = doc.text();
+    (serde_yaml_ng::from_str::<Value>(&out).ok()? == target).then_some(out)
+}
+
+/// What the file should hold: `raw` wherever `old` and `new` agree, and
+/// `new` where they differ. Paths of subtrees taken from `new` go in `added`.
+fn carry(
+    raw: &Value,
+    old: &Value,
+    new: &Value,
+    path: &mut Vec<Seg>,
+    added: &mut Vec<Vec<Seg>>,
+) -> Value {
+    if old == new {
+        return raw.clone();
+    }
+    match (raw, old, new) {
+        (Value::Mapping(r), Value::Mapping(o), Value::Mapping(n)) => {
+            let mut out = Mapping::new();
+            for (key, rv) in r {
+                match (o.get(key), n.get(key)) {
+                    (Some(ov), Some(nv)) => {
+                        path.push(Seg::Key(key.clone()));
+                        out.insert(key.clone(), carry(rv, ov, nv, path, added));
+                        path.pop();
+                    }
+                    (Some(_), None) => {}
+                    // Left out when loaded, as serde skips empty values
+                    (None, Some(nv)) => {
+                        path.push(Seg::Key(key.clone()));
+                        added.push(path.clone());
+                        path.pop();
+                        out.insert(key.clone(), prune(nv));
+                    }
+                    // A key the config doesn't know; it was ignored on load
+                    (None, None) => {
+                        out.insert(key.clone(), rv.clone());
+                    }
+                }
+            }
+            for (key, nv) in n {
+                // Unwritten keys held their default when loaded
+                if r.contains_key(key) || o.get(key) == Some(nv) {
+                    continue;
+                }
+                let value = prune(nv);
+                if is_empty(&value) {
+                    continue;
+                }
+                path.push(Seg::Key(key.clone()));
+                added.push(path.clone());
+                path.pop();
+                out.insert(key.clone(), value);
+            }
+            Value::Mapping(out)
+        }
+        (Value::Sequence(r), Value::Sequence(o), Value::Sequence(n)) if r.len() == o.len() => {
+            if o.len() == n.len() {
+                return Value::Sequence(
+                    (0..n.len())
+                        .map(|i| {
+                            path.push(Seg::Index(i));
+                            let item = carry(&r[i], &o[i], &n[i], path, added);
+                            path.pop();
+                            item
+                        })
+                        .collect(),
+                );
+            }
+            let mut from = vec![None; n.len()];
+            for (i, j) in lcs(o, n) {
+                from[j] = Some(i);
+            }
+            Value::Sequence(
+                n.iter()
+                    .zip(from)
+                    .enumerate()
+                    .map(|(j, (nv, from))| match from {
+                        Some(i) => r[i].clone(),
+                        None => {
+                            path.push(Seg::Index(j));
+                            added.push(path.clone());
+                            path.pop();
+                            prune(nv)
+                        }
+                    })
+                    .collect(),
+            )
+        }
+        _ => {
+            added.push(path.clone());
+            prune(new)
+        }
+    }
+}
+
+/// Drop null and empty values from mappings, which serde fills in for
+/// every field but nobody writes by hand.
+fn prune(value: &Value) -> Value {
+    match value {
+        Value::Mapping(m) => Value::Mapping(
+            m.iter()
+                .map(|(k, v)| (k.clone(), prune(v)))
+                .filter(|(_, v)| !is_empty(v))
+                .collect(),
+        ),
+        Value::Sequence(s) => Value::Sequence(s.iter().map(prune).collect()),
+        _ => value.clone(),
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Sequence(s) => s.is_empty(),
+        Value::Mapping(m) => m.is_empty(),
+        _ => false,
+    }
+}
+
+/// Leave out keys of a newly written subtree that only restate a default,
+/// checked by reading the config back without them.
+fn drop_defaults(
+    target: &mut Value,
+    path: &[Seg],
+    new: &Value,
+    normalize: &impl Fn(&Value) -> Option<Value>,
+) {
+    let mut keys = Vec::new();
+    if let Some(subtree) = get(target, path) {
+        collect_keys(subtree, &mut path.to_vec(), &mut keys);
+    }
+    for key in keys {
+        let mut trial = target.clone();
+        if remove_at(&mut trial, &key) && normalize(&trial).as_ref() == Some(new) {
+            *target = trial;
+        }
+    }
+}
+
+/// Paths of every mapping key under `value`, children before parents.
+fn collect_keys(value: &Value, path: &mut Vec<Seg>, out: &mut Vec<Vec<Seg>>) {
+    match value {
+        Value::Mapping(m) => {
+            for (k, v) in m {
+                path.push(Seg::Key(k.clone()));
+                collect_keys(v, path, out);
+                out.push(path.clone());
+                path.pop();
+            }
+        }
+        Value::Sequence(s) => {
+            for (i, v) in s.iter().enumerate() {
+                path.push(Seg::Index(i));
+                collect_keys(v, path, out);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn get<'a>(value: &'a Value, path: &[Seg]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |v, seg| match (v, seg) {
+        (Value::Mapping(m), Seg::Key(k)) => m.get(k),
+        (Value::Sequence(s), Seg::Index(i)) => s.get(*i),
+        _ => None,
+    })
+}
+
+fn remove_at(value: &mut Value, path: &[Seg]) -> bool {
+    let Some((Seg::Key(key), parent)) = path.split_last() else {
+        return false;
+    };
+    let mut node = value;
+    for seg in parent {
+        node = match (node, seg) {
+            (Value::Mapping(m), Seg::Key(k)) => match m.get_mut(k) {
+                Some(v) => v,
+                None => return false,
+            },
+            (Value::Sequence(s), Seg::Index(i)) => match s.get_mut(*i) {
+                Some(v) => v,
+                None => return false,
+            },
+            _ => return false,
+        };
+    }
+    match node {
+        Value::Mapping(m) => m.shift_remove(key).is_some(),
+        _ => false,
+    }
+}
+
+/// Index pairs of a longest common subsequence of `a` and `b`.
+fn lcs(a: &[Value], b: &[Value]) -> Vec<(usize, usize)> {
+    let mut len = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            len[i][j] = if a[i] == b[j] {
+                len[i + 1][j + 1] + 1
+            } else {
+                len[i + 1][j].max(len[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j, mut pairs) = (0, 0, Vec::new());
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if len[i + 1][j] >= len[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// The lines of a YAML file, edited in place. Block mappings and lists,
+/// one-line scalars and flow collections, and `|`/`>` scalars are
+/// understood; anything else makes an edit give up with `None`.
+pub struct Document {
+    lines: Vec<String>,
+    trailing_newline: bool,
+    /// Whether lists under a key are indented past it, as most people write.
+    indented_lists: bool,
+}
+
+/// A parsed node. Only block collections record where their children are.
+#[derive(Debug)]
+enum Node {
+    /// Nothing after `key:`, which reads as null.
+    Empty,
+    /// A scalar or flow collection on the owning line.
+    Inline,
+    /// A `|` or `>` scalar.
+    Literal,
+    Map(Vec<(Value, Slot)>),
+    List(Vec<Slot>),
+}
+
+/// Where a mapping entry or list item sits.
+#[derive(Debug)]
+struct Slot {
+    /// First line, taking in comments directly above.
+    start: usize,
+    /// The line with the key or dash.
+    line: usize,
+    /// Column of the key or dash.
+    col: usize,
+    /// Just past the `:` or `-`.
+    after: usize,
+    /// The value written on the same line, `vstart..vend`, empty if none.
+    vstart: usize,
+    vend: usize,
+    /// One past the last line of the value.
+    end: usize,
+    node: Node,
+}
+
+impl Document {
+    pub fn parse(text: &str) -> Option<Self> {
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        if lines.iter().any(|l| l.starts_with('\t')) {
+            return None;
+        }
+        let indented_lists = lines
+            .iter()
+            .zip(lines.iter().skip(1))
+            .find_map(|(key, next)| {
+                let dash = next.trim_start().starts_with("- ");
+                (dash && key.trim_end().ends_with(':')).then(|| indent(next) > indent(key))
+            })
+            .unwrap_or(true);
+        let doc = Document {
+            lines,
+            trailing_newline: text.ends_with('\n') || text.is_empty(),
+            indented_lists,
+        };
+        doc.root()?;
+        Some(doc)
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = self.lines.join("\n");
+        if self.trailing_newline {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Edit the node at `path`, which held `raw`, to hold `target`: key by
+    /// key and item by item where both are block collections, replacing the
+    /// node where that fails.
+    fn sync(&mut self, path: &mut Vec<Seg>, raw: &Value, target: &Value) -> Option<()> {
+        if raw == target {
+            return Some(());
+        }
+        let block = matches!(self.node(path), Some(Node::Map(_) | Node::List(_)));
+        let edited = match (raw, target) {
+            (Value::Mapping(r), Value::Mapping(t)) if block && !t.is_empty() => {
+                self.sync_map(path, r, t)
+            }
+            (Value::Sequence(r), Value::Sequence(t)) if block && !t.is_empty() => {
+                self.sync_list(path, r, t)
+            }
+            _ => None,
+        };
+        edited.or_else(|| self.replace(path, target))
+    }
+
+    fn sync_map(&mut self, path: &mut Vec<Seg>, raw: &Mapping, target: &Mapping) -> Option<()> {
+        for key in raw.keys().filter(|k| !target.contains_key(*k)) {
+            path.push(Seg::Key(key.clone()));
+            let removed = self.remove(path);
+            path.pop();
+            removed?;
+        }
+        for (key, value) in target {
+            match raw.get(key) {
+                Some(old) => {
+                    path.push(Seg::Key(key.clone()));
+                    let synced = self.sync(path, old, value);
+                    path.pop();
+                    synced?;
+                }
+                None => self.insert_key(path, key, value)?,
+            }
+        }
+        Some(())
+    }
+
+    fn sync_list(&mut self, path: &mut Vec<Seg>, raw: &[Value], target: &[Value]) -> Option<()> {
+        if raw.len() == target.len() {
+            for (i, (old, value)) in raw.iter().zip(target).enumerate() {
+                path.push(Seg::Index(i));
+                let synced = self.sync(path, old, value);
+                path.pop();
+                synced?;
+            }
+            return Some(());
+        }
+        let pairs = lcs(raw, target);
+        let kept: HashSet<usize> = pairs.iter().map(|&(i, _)| i).collect();
+        let placed: HashSet<usize> = pairs.iter().map(|&(_, j)| j).collect();
+        for i in (0..raw.len()).rev().filter(|i| !kept.contains(i)) {
+            path.push(Seg::Index(i));
+            let removed = self.remove(path);
+            path.pop();
+            removed?;
+        }
+        for (j, value) in target.iter().enumerate() {
+            if !placed.contains(&j) {
+                self.insert_item(path, j, value)?;
+            }
+        }
+        Some(())
+    }
+
+    /// Add `key: value` after the last entry of the block mapping at `path`.
+    pub fn insert_key(&mut self, path: &[Seg], key: &Value, value: &Value) -> Option<()> {
+        let root = self.root()?;
+        let Node::Map(entries) = find(&root, path)? else {
+            return None;
+        };
+        let (_, last) = entries.last()?;
+        let lines = self.render(Some(key), value, entries[0].1.col)?;
+        self.lines.splice(last.end..last.end, lines);
+        Some(())
+    }
+
+    /// Add `value` to the block list at `path` so it becomes item `index`.
+    pub fn insert_item(&mut self, path: &[Seg], index: usize, value: &Value) -> Option<()> {
+        let root = self.root()?;
+        let Node::List(items) = find(&root, path)? else {
+            return None;
+        };
+        let at = match items.get(index) {
+            Some(item) => item.start,
+            None => items.last()?.end,
+        };
+        let lines = self.render(None, value, items[0].col)?;
+        self.lines.splice(at..at, lines);
+        Some(())
+    }
+
+    /// Delete the entry or item at `path`, with the comments directly above it.
+    pub fn remove(&mut self, path: &[Seg]) -> Option<()> {
+        let root = self.root()?;
+        let slot = find_slot(&root, path)?;
+        if self.shares_line(slot) {
+            return None;
+        }
+        // Take a blank line that only separated it from what went before
+        let mut start = slot.start;
+        let blank = |l: Option<&String>| l.map_or(true, |l| l.trim().is_empty());
+        if start > 0 && blank(self.lines.get(start - 1)) && blank(self.lines.get(slot.end)) {
+            start -= 1;
+        }
+        self.lines.drain(start..slot.end);
+        Some(())
+    }
+
+    /// Write `value` in place of the value at `path`: on the same line when
+    /// it is a scalar, an empty collection or goes into a flow collection,
+    /// as a block otherwise.
+    pub fn replace(&mut self, path: &[Seg], value: &Value) -> Option<()> {
+        let root = self.root()?;
+        let slot = find_slot(&root, path)?;
+        if let Some(text) = self.inline(slot, value) {
+            let line = &self.lines[slot.line];
+            let edited = if slot.vstart < slot.vend {
+                format!("{}{}{}", &line[..slot.vstart], text, &line[slot.vend..])
+            } else {
+                format!("{} {}{}", &line[..slot.after], text, &line[slot.after..])
+            };
+            self.lines[slot.line] = edited;
+            self.lines.drain(slot.line + 1..slot.end);
+            return Some(());
+        }
+        if self.shares_line(slot) {
+            return None;
+        }
+        let key = match path.last()? {
+            Seg::Key(key) => Some(key),
+            Seg::Index(_) => None,
+        };
+        let lines = self.render(key, value, slot.col)?;
+        self.lines.splice(slot.line..slot.end, lines);
+        Some(())
+    }
+
+    /// Line and column, counted from 1, of the key or dash at `path`, or of
+    /// the nearest enclosing one when `path` is inside a flow collection.
+    pub fn position(&self, path: &[Seg]) -> Option<(usize, usize)> {
+        let root = self.root()?;
+        (1..=path.len()).rev().find_map(|len| {
+            let slot = find_slot(&root, &path[..len])?;
+            Some((slot.line + 1, slot.col + 1))
+        })
+    }
+
+    /// The first entry of a mapping written on its list item's dash line.
+    fn shares_line(&self, slot: &Slot) -> bool {
+        indent(&self.lines[slot.line]) != slot.col
+    }
+
+    fn inline(&self, slot: &Slot, value: &Value) -> Option<String> {
+        let written = &self.lines[slot.line][slot.vstart..slot.vend];
+        match value {
+            Value::Sequence(s) if s.is_empty() => Some("[]".to_string()),
+            Value::Mapping(m) if m.is_empty() => Some("{}".to_string()),
+            Value::Sequence(s) => {
+                let flow = matches!(slot.node, Node::Inline)
+                    && written.starts_with('[')
+                    && (written != "[]" || !s.iter().any(is_collection));
+                flow.then(|| flow_text(value)).flatten()
+            }
+            Value::Mapping(_) => {
+                let flow = matches!(slot.node, Node::Inline)
+                    && written.starts_with('{')
+                    && written != "{}";
+                flow.then(|| flow_text(value)).flatten()
+            }
+            _ => scalar_text(value),
+        }
+    }
+
+    /// `key: value`, or `- value` without a key, as lines indented `col`.
+    fn render(&self, key: Option<&Value>, value: &Value, col: usize) -> Option<Vec<String>> {
+        let wrapped = match key {
+            Some(key) => Value::Mapping(Mapping::from_iter([(key.clone(), value.clone())])),
+            None => Value::Sequence(vec![value.clone()]),
+        };
+        let mut text = serde_yaml_ng::to_string(&wrapped).ok()?;
+        if self.indented_lists {
+            text = indent_lists(&text);
+        }
+        Some(
+            text.lines()
+                .map(|l| match l.is_empty() {
+                    true => String::new(),
+                    false => format!("{}{}", " ".repeat(col), l),
+                })
+                .collect(),
+        )
+    }
+
+    fn node(&self, path: &[Seg]) -> Option<Node> {
+        let root = self.root()?;
+        let mut node = root;
+        for seg in path {
+            node = match (node, seg) {
+                (Node::Map(entries), Seg::Key(k)) => {
+                    entries.into_iter().find(|(key, _)| key == k)?.1.node
+                }
+                (Node::List(items), Seg::Index(i)) => items.into_iter().nth(*i)?.node,
+                _ => return None,
+            };
+        }
+        Some(node)
+    }
+
+    fn root(&self) -> Option<Node> {
+        let mut first = self.next_content(0);
+        if let Some(i) = first.filter(|&i| self.lines[i].starts_with("---")) {
+            if self.lines[i].trim_end() != "---" {
+                return None;
+            }
+            first = self.next_content(i + 1);
+        }
+        match first {
+            None => Some(Node::Empty),
+            Some(i) if indent(&self.lines[i]) == 0 => Some(self.block(i, 0)?.0),
+            Some(_) => None,
+        }
+    }
+
+    fn next_content(&self, from: usize) -> Option<usize> {
+        (from..self.lines.len()).find(|&i| {
+            let t = self.lines[i].trim();
+            !t.is_empty() && !t.starts_with('#')
+        })
+    }
+
+    /// The block collection starting at column `col` of line `at`, and the
+    /// line after it.
+    fn block(&self, at: usize, col: usize) -> Option<(Node, usize)> {
+        if is_dash(&self.lines[at][col..]) {
+            self.list(at, col)
+        } else {
+            self.map(at, col)
+        }
+    }
+
+    fn map(&self, at: usize, col: usize) -> Option<(Node, usize)> {
+        let mut entries = Vec::new();
+        let mut next = Some(at);
+        let mut end = at + 1;
+        while let Some(l) = next {
+            let line = &self.lines[l];
+            if l != at {
+                if indent(line) < col {
+                    break;
+                }
+                if indent(line) > col || is_dash(&line[col..]) {
+                    return None;
+                }
+            }
+            let colon = key_end(&line[col..])?;
+            let key: Value = serde_yaml_ng::from_str(line[col..col + colon].trim_end()).ok()?;
+            let after = col + colon + 1;
+            let (vstart, vend) = value_span(line, after);
+            let (node, slot_end) = self.value(l, col, vstart, vend, true)?;
+            let start = match indent(line) == col {
+                true => self.comments_above(l, col),
+                false => l,
+            };
+            entries.push((
+                key,
+                Slot {
+                    start,
+                    line: l,
+                    col,
+                    after,
+                    vstart,
+                    vend,
+                    end: slot_end,
+                    node,
+                },
+            ));
+            end = slot_end;
+            next = self.next_content(slot_end);
+        }
+        Some((Node::Map(entries), end))
+    }
+
+    fn list(&self, at: usize, col: usize) -> Option<(Node, usize)> {
+        let mut items = Vec::new();
+        let mut next = Some(at);
+        let mut end = at + 1;
+        while let Some(l) = next {
+            let line = &self.lines[l];
+            if indent(line) < col || !is_dash(&line[col..]) {
+                break;
+            }
+            if indent(line) > col {
+                return None;
+            }
+            let after = col + 1;
+            let (vstart, vend) = value_span(line, after);
+            let starts_map = vstart < vend
+                && !line[vstart..].starts_with(['[', '{'])
+                && key_end(&line[vstart..]).is_some();
+            let (node, slot_end) = if starts_map {
+                self.map(l, vstart)?
+            } else {
+                self.value(l, col, vstart, vend, false)?
+            };
+            items.push(Slot {
+                start: self.comments_above(l, col),
+                line: l,
+                col,
+                after,
+                vstart,
+                vend,
+                end: slot_end,
+                node,
+            });
+            end = slot_end;
+            next = self.next_content(slot_end);
+        }
+        Some((Node::List(items), end))
+    }
+
+    /// The value owned by the key or dash at column `col` of line `l`.
+    fn value(
+        &self,
+        l: usize,
+        col: usize,
+        vstart: usize,
+        vend: usize,
+        list_at_col: bool,
+    ) -> Option<(Node, usize)> {
+        let deeper = self
+            .next_content(l + 1)
+            .filter(|&n| indent(&self.lines[n]) > col);
+        if vstart == vend {
+            return match self.next_content(l + 1) {
+                Some(n) if deeper.is_some() => self.block(n, indent(&self.lines[n])),
+                Some(n)
+                    if list_at_col
+                        && indent(&self.lines[n]) == col
+                        && is_dash(&self.lines[n][col..]) =>
+                {
+                    self.list(n, col)
+                }
+                _ => Some((Node::Empty, l + 1)),
+            };
+        }
+        let written = &self.lines[l][vstart..vend];
+        if written.starts_with(['|', '>']) {
+            let last = (l + 1..self.lines.len())
+                .take_while(|&i| self.lines[i].trim().is_empty() || indent(&self.lines[i]) > col)
+                .filter(|&i| !self.lines[i].trim().is_empty())
+                .last();
+            return Some((Node::Literal, last.map_or(l + 1, |i| i + 1)));
+        }
+        // Multi-line scalars and flow collections, and anchors or tags on a
+        // block, are beyond what this edits
+        let anchored = written.starts_with(['&', '!']) && !written.contains(' ');
+        if deeper.is_some() || anchored || !closed(written) {
+            return None;
+        }
+        Some((Node::Inline, l + 1))
+    }
+
+    /// The first of the comment lines at column `col` directly above `l`.
+    fn comments_above(&self, l: usize, col: usize) -> usize {
+        let mut start = l;
+        while start > 0 {
+            let above = &self.lines[start - 1];
+            if indent(above) != col || !above.trim_start().starts_with('#') {
+                break;
+            }
+            start -= 1;
+        }
+        start
+    }
+}
+
+fn find<'a>(root: &'a Node, path: &[Seg]) -> Option<&'a Node> {
+    path.iter().try_fold(root, |node, seg| {
+        find_child(node, seg).map(|slot| &slot.node)
+    })
+}
+
+fn find_slot<'a>(root: &'a Node, path: &[Seg]) -> Option<&'a Slot> {
+    let (last, parent) = path.split_last()?;
+    find_child(find(root, parent)?, last)
+}
+
+fn find_child<'a>(node: &'a Node, seg: &Seg) -> Option<&'a Slot> {
+    match (node, seg) {
+        (Node::Map(entries), Seg::Key(k)) => {
+            entries.iter().find(|(key, _)| key == k).map(|(_, s)| s)
+        }
+        (Node::List(items), Seg::Index(i)) => items.get(*i),
+        _ => None,
+    }
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_dash(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// Where the key of a `key: value` line ends, at its colon.
+fn key_end(text: &str) -> Option<usize> {
+    if let Some(quote) = text.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let close = text[1..].find(quote)? + 1;
+        let rest = &text[close + 1..];
+        return (rest.starts_with(':') && (rest.len() == 1 || rest[1..].starts_with(' ')))
+            .then_some(close + 1);
+    }
+    if text.starts_with(['[', '{', '#', '&', '*', '!', '|', '>', '%', '@', '`', '?'])
+        || is_dash(text)
+    {
+        return None;
+    }
+    let bytes = text.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'#' if i > 0 && bytes[i - 1] == b' ' => return None,
+            b':' if i + 1 == bytes.len() || bytes[i + 1] == b' ' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The value written after column `after`, without its comment.
+fn value_span(line: &str, after: usize) -> (usize, usize) {
+    let bytes = line.as_bytes();
+    let start = after + line[after..].len() - line[after..].trim_start().len();
+    let mut quote = None;
+    let mut end = line.len();
+    for i in start..bytes.len() {
+        match (quote, bytes[i]) {
+            (Some(q), b) if b == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'"' | b'\'') if i == start || b"[{, ".contains(&bytes[i - 1]) => {
+                quote = Some(bytes[i])
+            }
+            (None, b'#') if i == start || bytes[i - 1] == b' ' => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    let end = start + line[start..end].trim_end().len();
+    if start == end {
+        (after, after)
+    } else {
+        (start, end)
+    }
+}
+
+/// Whether quotes and flow brackets written on one line are all closed.
+fn closed(text: &str) -> bool {
+    let mut depth = 0i32;
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if depth > 0 || text.starts_with(c) => quote = Some(c),
+            (None, '[' | '{') => depth += 1,
+            (None, ']' | '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0 && quote.is_none()
+}
+
+fn is_collection(value: &Value) -> bool {
+    matches!(value, Value::Sequence(_) | Value::Mapping(_))
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    let text = serde_yaml_ng::to_string(value).ok()?;
+    let text = text.trim_end_matches('\n');
+    (!text.contains('\n')).then(|| text.to_string())
+}
+
+/// `value` as a one-line flow collection or scalar.
+fn flow_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Sequence(items) => {
+            let items: Option<Vec<String>> = items.iter().map(flow_text).collect();
+            Some(format!("[{}]", items?.join(", ")))
+        }
+        Value::Mapping(m) => {
+            let entries: Option<Vec<String>> = m
+                .iter()
+                .map(|(k, v)| Some(format!("{}: {}", flow_text(k)?, flow_text(v)?)))
+                .collect();
+            Some(format!("{{{}}}", entries?.join(", ")))
+        }
+        _ => {
+            let text = scalar_text(value)?;
+            let plain = !text.starts_with(['"', '\'']);
+            if plain && (text.contains([',', '[', ']', '{', '}']) || text.contains(": ")) {
+                Some(format!("'{}'", text.replace('\'', "''")))
+            } else {
+                Some(text)
+            }
+        }
+    }
+}
+
+/// Indent lists under their key, as serde writes them flush with it.
+fn indent_lists(text: &str) -> String {
+    let mut out = String::new();
+    let mut open: Vec<usize> = Vec::new();
+    let mut key_line: Option<usize> = None;
+    for line in text.lines() {
+        let col = indent(line);
+        let body = &line[col..];
+        if !body.is_empty() {
+            while let Some(&at) = open.last() {
+                if col < at || (col == at && !is_dash(body)) {
+                    open.pop();
+                } else {
+                    break;
+                }
+            }
+            if is_dash(body) && key_line == Some(col) && open.last() != Some(&col) {
+                open.push(col);
+            }
+            let key_col = if is_dash(body) { col + 2 } else { col };
+            key_line = body.ends_with(':').then_some(key_col);
+            out.push_str(&" ".repeat(col + 2 * open.len()));
+        }
+        out.push_str(body);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Config {
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        profiles: BTreeMap<String, Profile>,
+    }
+
+    #[derive(Debug, Default, Deserialize, Serialize)]
+    struct Profile {
+        #[serde(default)]
+        dotfiles: Vec<String>,
+        #[serde(default)]
+        packages: Vec<String>,
+    }
+
+    fn edit(text: &str, change: impl FnOnce(&mut Config)) -> Option<String> {
+        let mut config: Config = serde_yaml_ng::from_str(text).unwrap();
+        let old = serde_yaml_ng::to_value(&config).unwrap();
+        change(&mut config);
+        update(text, &old, &config)
+    }
+
+    const TEXT: &str = "\
+# my config
+name: laptop   # this machine
+profiles:
+  # the usual
+  default:
+    dotfiles:
+      - .vimrc   # editor
+      - .zshrc
+    packages: [git, vim]
+
+  work:
+    dotfiles: []
+";
+
+    #[test]
+    fn edits_keep_comments_and_order() {
+        let out = edit(TEXT, |c| {
+            let default = c.profiles.get_mut("default").unwrap();
+            default.dotfiles.push(".tmux.conf".into());
+            default.packages.retain(|p| p != "vim");
+            default.packages.push("ripgrep".into());
+            c.profiles
+                .get_mut("work")
+                .unwrap()
+                .dotfiles
+                .push(".gitconfig".into());
+        })
+        .unwrap();
+        assert_eq!(
+            out,
+            "\
+# my config
+name: laptop   # this machine
+profiles:
+  # the usual
+  default:
+    dotfiles:
+      - .vimrc   # editor
+      - .zshrc
+      - .tmux.conf
+    packages: [git, ripgrep]
+
+  work:
+    dotfiles: [.gitconfig]
+"
+        );
+    }
+
+    #[test]
+    fn new_entries_leave_out_defaults() {
+        let out = edit(TEXT, |c| {
+            c.profiles.insert(
+                "home".into(),
+                Profile {
+                    packages: vec!["htop".into()],
+                    ..Default::default()
+                },
+            );
+            c.profiles.remove("work");
+            c.name = Some("desk: top".into());
+        })
+        .unwrap();
+        assert!(out.starts_with("# my config\nname: 'desk: top'   # this machine\n"));
+        assert!(!out.contains("work"));
+        assert!(
+            out.ends_with("    packages: [git, vim]\n  home:\n    packages:\n      - htop\n"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn removing_every_item_leaves_empty_list() {
+        let out = edit(TEXT, |c| {
+            c.profiles.get_mut("default").unwrap().dotfiles.clear();
+        })
+        .unwrap();
+        assert!(
+            out.contains("  default:\n    dotfiles: []\n    packages: [git, vim]\n"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn unsupported_text_gives_up() {
+        let text = "profiles:\n  default:\n    dotfiles: [a,\n      b]\n";
+        assert_eq!(edit(text, |c| c.name = Some("x".into())), None);
+    }
+}
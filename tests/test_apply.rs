@@ -206,3 +206,452 @@ profiles:
         ".vimrc must NOT be linked (os filter)"
     );
 }
+
+#[test]
+#[serial]
+fn test_apply_picks_os_alternative_for_one_target() {
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    dotfiles
+        .child("linux/gitconfig")
+        .write_str("linux")
+        .unwrap();
+    dotfiles
+        .child("macos/gitconfig")
+        .write_str("macos")
+        .unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(
+            r#"heimdal:
+  version: "1"
+profiles:
+  default:
+    dotfiles:
+      - source: linux/gitconfig
+        target: ~/.gitconfig
+        when: { os: [linux] }
+      - source: macos/gitconfig
+        target: ~/.gitconfig
+        when: { os: [macos] }
+"#,
+        )
+        .unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(&["apply", "--dotfiles-only"])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    let expected = if cfg!(target_os = "macos") {
+        "macos"
+    } else {
+        "linux"
+    };
+    let gitconfig = home.path().join(".gitconfig");
+    assert!(gitconfig.is_symlink());
+    assert_eq!(std::fs::read_to_string(gitconfig).unwrap(), expected);
+}
+
+#[test]
+#[serial]
+fn test_apply_records_manifest() {
+    let home = common::setup_home("default");
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    let manifest = std::fs::read_to_string(home.path().join(".heimdal").join("manifest.json"))
+        .expect("manifest.json must be written by apply");
+    assert!(manifest.contains(".vimrc"));
+    assert!(manifest.contains("\"kind\": \"symlink\""));
+}
+
+#[test]
+#[serial]
+fn test_apply_prunes_orphaned_links() {
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    dotfiles.child(".zshrc").write_str("# zsh").unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str("heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n      - .vimrc\n      - .zshrc\n")
+        .unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert!(home.path().join(".zshrc").is_symlink());
+
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(
+            "heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n      - .vimrc\n",
+        )
+        .unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(contains("Pruned orphaned link"));
+    assert!(!home.path().join(".zshrc").exists());
+    assert!(home.path().join(".vimrc").is_symlink());
+}
+
+#[test]
+#[serial]
+fn test_check_drift_reads_manifest() {
+    let home = common::setup_home("default");
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["state", "check-drift"])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+
+    std::fs::remove_file(home.path().join(".vimrc")).unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["state", "check-drift"])
+        .env("HOME", home.path())
+        .assert()
+        .failure()
+        .stderr(contains("Not linked"));
+}
+
+#[test]
+#[serial]
+fn test_apply_rolls_back_when_post_hook_fails() {
+    let home = common::setup_home("default");
+    std::fs::write(home.path().join(".vimrc"), "original content").unwrap();
+    home.child(".dotfiles")
+        .child("heimdal.yaml")
+        .write_str("heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n      - .vimrc\n    hooks:\n      post_apply: [\"exit 3\"]\n")
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["apply", "--force"])
+        .env("HOME", home.path())
+        .assert()
+        .failure()
+        .stderr(contains("rolling back"));
+
+    let vimrc = home.path().join(".vimrc");
+    assert!(!vimrc.is_symlink(), "force-removed file must be restored");
+    assert_eq!(std::fs::read_to_string(vimrc).unwrap(), "original content");
+    assert!(!home.path().join(".heimdal").join("journal").exists());
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_apply_recovers_interrupted_journal() {
+    let home = common::setup_home("default");
+    let stray = home.path().join(".stray-link");
+    std::os::unix::fs::symlink(home.path().join(".dotfiles/.vimrc"), &stray).unwrap();
+    let journal = home.child(".heimdal").child("journal");
+    journal
+        .child("journal.jsonl")
+        .write_str(&format!(
+            "{{\"op\":\"created_link\",\"path\":\"{}\"}}\n",
+            stray.display()
+        ))
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stderr(contains("interrupted"));
+    assert!(
+        !stray.exists(),
+        "link from the interrupted run must be undone"
+    );
+    assert!(home.path().join(".vimrc").is_symlink());
+}
+
+#[test]
+#[serial]
+fn test_apply_copy_mode_and_pull_back() {
+    let home = common::setup_home("default");
+    home.child(".dotfiles")
+        .child("heimdal.yaml")
+        .write_str("heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n      - source: .vimrc\n        target: ~/.vimrc\n        mode: copy\n")
+        .unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(contains("Copied"));
+    let vimrc = home.path().join(".vimrc");
+    assert!(vimrc.is_file() && !vimrc.is_symlink());
+
+    // A local edit blocks the next apply instead of being overwritten
+    std::fs::write(&vimrc, "edited in place").unwrap();
+    std::fs::write(home.path().join(".dotfiles/.vimrc"), "repo change").unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .failure()
+        .stderr(contains("edited locally"));
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["state", "check-drift", "--pull"])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".dotfiles/.vimrc")).unwrap(),
+        "edited in place"
+    );
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_apply_relative_links() {
+    let home = common::setup_home("default");
+    home.child(".dotfiles")
+        .child("heimdal.yaml")
+        .write_str("heimdal:\n  version: \"1\"\n  relative_links: true\nprofiles:\n  default:\n    dotfiles:\n      - .vimrc\n")
+        .unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_link(home.path().join(".vimrc")).unwrap(),
+        std::path::PathBuf::from(".dotfiles/.vimrc")
+    );
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["state", "check-drift"])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+}
+
+#[test]
+#[serial]
+fn test_apply_stow_walk_honors_ignore() {
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str("heimdal:\n  version: \"1\"\nignore:\n  - \"*.md\"\nprofiles:\n  default: {}\n")
+        .unwrap();
+    dotfiles.child("NOTES.md").write_str("notes").unwrap();
+    dotfiles
+        .child(".heimdalignore")
+        .write_str(".DS_Store\n")
+        .unwrap();
+    dotfiles.child(".DS_Store").write_str("").unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+
+    assert!(home.path().join(".vimrc").is_symlink());
+    assert!(!home.path().join("NOTES.md").exists());
+    assert!(!home.path().join(".DS_Store").exists());
+    assert!(!home.path().join(".heimdalignore").exists());
+}
+
+#[test]
+#[serial]
+fn test_apply_interactive_without_tty_keeps_conflict_behavior() {
+    let home = common::setup_home("default");
+    std::fs::write(home.path().join(".vimrc"), "local").unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["apply", "--interactive"])
+        .env("HOME", home.path())
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(contains("conflict(s) found"));
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".vimrc")).unwrap(),
+        "local"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_apply_enforces_file_and_dir_modes() {
+    use std::os::unix::fs::PermissionsExt;
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    dotfiles.child("ssh/config").write_str("Host *\n").unwrap();
+    dotfiles.child("netrc").write_str("machine x\n").unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(
+            "heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n\
+             \x20     - source: ssh/config\n        target: ~/.ssh/config\n        file_mode: \"0600\"\n        dir_mode: \"0700\"\n\
+             \x20     - source: netrc\n        target: ~/.netrc\n        mode: copy\n        file_mode: 600\n",
+        )
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+
+    let mode = |p: &std::path::Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+    // Symlinked: the repo file behind the link carries the mode
+    assert_eq!(mode(&dotfiles.path().join("ssh/config")), 0o600);
+    assert_eq!(mode(&home.path().join(".ssh")), 0o700);
+    assert_eq!(mode(&home.path().join(".netrc")), 0o600);
+
+    // Loosened by hand: drift warns, the next apply tightens it again
+    std::fs::set_permissions(
+        home.path().join(".netrc"),
+        std::fs::Permissions::from_mode(0o644),
+    )
+    .unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["state", "check-drift"])
+        .env("HOME", home.path())
+        .assert()
+        .stderr(contains("world-readable"));
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert_eq!(mode(&home.path().join(".netrc")), 0o600);
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_validate_warns_on_world_readable_secrets() {
+    use std::os::unix::fs::PermissionsExt;
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    dotfiles.child("netrc").write_str("machine x\n").unwrap();
+    std::fs::set_permissions(
+        dotfiles.path().join("netrc"),
+        std::fs::Permissions::from_mode(0o644),
+    )
+    .unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(
+            "heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n\
+             \x20     - source: netrc\n        target: ~/.netrc\n",
+        )
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("validate")
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stderr(contains("should be private"))
+        .stderr(contains("file_mode"));
+}
+
+#[test]
+#[serial]
+fn test_apply_honors_rich_conditions() {
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    dotfiles.child("a.conf").write_str("a").unwrap();
+    dotfiles.child("b.conf").write_str("b").unwrap();
+    dotfiles.child("t.tmpl").write_str("t").unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(&format!(
+            r#"heimdal:
+  version: "1"
+profiles:
+  default:
+    dotfiles:
+      - source: a.conf
+        target: ~/.a.conf
+        when:
+          env: {{ HEIMDAL_TEST_ROLE: "work-*" }}
+          command: [sh]
+      - source: b.conf
+        target: ~/.b.conf
+        when:
+          any:
+            - arch: [{arch}]
+            - os: [plan9]
+          not: {{ file: [~/.skip-b] }}
+    templates:
+      - src: t.tmpl
+        dest: ~/.t
+        when: {{ not: {{ env: {{ HEIMDAL_TEST_ROLE: "*" }} }} }}
+    hooks:
+      post_apply:
+        - command: touch ~/.hook-ran
+          when: {{ all: [{{ profile: [default] }}, {{ env: {{ HEIMDAL_TEST_ROLE: "work-*" }} }}] }}
+"#,
+            arch = std::env::consts::ARCH
+        ))
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .env("HEIMDAL_TEST_ROLE", "work-laptop")
+        .assert()
+        .success();
+    assert!(home.child(".a.conf").path().is_symlink());
+    assert!(home.child(".b.conf").path().is_symlink());
+    assert!(!home.child(".t").path().exists());
+    assert!(home.child(".hook-ran").path().exists());
+
+    // Without the variable, the mapping and hook are skipped, the template renders
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", home.path())
+        .env_remove("HEIMDAL_TEST_ROLE")
+        .assert()
+        .success();
+    assert!(!home.child(".a.conf").path().exists());
+    assert!(home.child(".t").path().exists());
+}
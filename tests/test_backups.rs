@@ -0,0 +1,138 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+use serial_test::serial;
+
+mod common;
+
+fn heimdal(home: &assert_fs::TempDir, args: &[&str]) -> assert_cmd::assert::Assert {
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(args)
+        .env("HOME", home.path())
+        .assert()
+}
+
+/// Apply with --backup over an existing ~/.vimrc and return the backup id.
+fn backed_up_vimrc(home: &assert_fs::TempDir) -> String {
+    home.child(".vimrc").write_str("my local vimrc").unwrap();
+    heimdal(home, &["apply", "--backup"]).success();
+    let dir = home.path().join(".dotfiles/.heimdal/backups");
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .find(|n| n.starts_with(".vimrc."))
+        .expect("backup of .vimrc")
+}
+
+#[test]
+#[serial]
+fn test_backups_list_shows_original_path() {
+    let home = common::setup_home("default");
+    let id = backed_up_vimrc(&home);
+    let original = home.path().join(".vimrc");
+
+    heimdal(&home, &["backups", "list"])
+        .success()
+        .stdout(contains(id.as_str()))
+        .stdout(contains(original.to_str().unwrap()));
+}
+
+#[test]
+#[serial]
+fn test_backups_diff_against_current() {
+    let home = common::setup_home("default");
+    let id = backed_up_vimrc(&home);
+
+    heimdal(&home, &["backups", "diff", &id])
+        .success()
+        .stdout(contains("-my local vimrc"))
+        .stdout(contains("+\" test vim config"));
+}
+
+#[test]
+#[serial]
+fn test_backups_restore_replaces_our_link() {
+    let home = common::setup_home("default");
+    let id = backed_up_vimrc(&home);
+    let vimrc = home.path().join(".vimrc");
+    assert!(vimrc.is_symlink());
+
+    heimdal(&home, &["backups", "restore", &id]).success();
+
+    assert!(!vimrc.is_symlink());
+    assert_eq!(std::fs::read_to_string(&vimrc).unwrap(), "my local vimrc");
+    heimdal(&home, &["backups", "list"])
+        .success()
+        .stdout(contains("No backups"));
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_backups_restore_replaces_our_relative_link() {
+    let home = common::setup_home("default");
+    let id = backed_up_vimrc(&home);
+    let vimrc = home.path().join(".vimrc");
+    let home_name = home.path().file_name().unwrap().to_string_lossy();
+    std::fs::remove_file(&vimrc).unwrap();
+    std::os::unix::fs::symlink(format!("../{}/.dotfiles/.vimrc", home_name), &vimrc).unwrap();
+
+    heimdal(&home, &["backups", "restore", &id]).success();
+
+    assert_eq!(std::fs::read_to_string(&vimrc).unwrap(), "my local vimrc");
+}
+
+#[test]
+#[serial]
+fn test_backups_restore_refuses_user_file_without_force() {
+    let home = common::setup_home("default");
+    let id = backed_up_vimrc(&home);
+    let elsewhere = home.child("restored");
+    elsewhere.write_str("keep me").unwrap();
+    let to = elsewhere.path().to_str().unwrap();
+
+    heimdal(&home, &["backups", "restore", &id, "--to", to])
+        .failure()
+        .stderr(contains("--force"));
+    heimdal(&home, &["backups", "restore", &id, "--to", to, "--force"]).success();
+    assert_eq!(
+        std::fs::read_to_string(elsewhere.path()).unwrap(),
+        "my local vimrc"
+    );
+}
+
+#[test]
+#[serial]
+fn test_backups_prune_keep() {
+    let home = common::setup_home("default");
+    let id = backed_up_vimrc(&home);
+    let backup = home.path().join(".dotfiles/.heimdal/backups").join(&id);
+
+    heimdal(&home, &["backups", "prune", "--keep", "0", "--dry-run"])
+        .success()
+        .stdout(contains("[preview]"));
+    assert!(backup.exists());
+
+    heimdal(&home, &["backups", "prune", "--keep", "1"])
+        .success()
+        .stdout(contains("Nothing to prune"));
+    heimdal(&home, &["backups", "prune", "--keep", "0"]).success();
+    assert!(!backup.exists());
+}
+
+#[test]
+#[serial]
+fn test_backups_prune_needs_criteria() {
+    let home = common::setup_home("default");
+    heimdal(&home, &["backups", "prune"])
+        .failure()
+        .stderr(contains("--older-than"));
+    heimdal(&home, &["backups", "prune", "--older-than", "soon"])
+        .failure()
+        .stderr(contains("Invalid age"));
+    heimdal(&home, &["backups", "prune", "--older-than", "3é"])
+        .failure()
+        .stderr(contains("Invalid age '3é'"));
+}
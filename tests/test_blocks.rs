@@ -0,0 +1,115 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+use serial_test::serial;
+
+mod common;
+
+const CORP_BASHRC: &str = "# managed by IT\nexport PATH=/corp/bin:$PATH\n";
+
+fn heimdal(home: &assert_fs::TempDir, args: &[&str]) -> assert_cmd::assert::Assert {
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(args)
+        .env("HOME", home.path())
+        .assert()
+}
+
+fn setup(home: &assert_fs::TempDir) {
+    let dotfiles = home.child(".dotfiles");
+    dotfiles
+        .child("snippets/aliases.sh")
+        .write_str("alias ll='ls -l'\n")
+        .unwrap();
+    dotfiles
+        .child("snippets/greeting.sh")
+        .write_str("echo {{ greeting }}\n")
+        .unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(
+            "heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n      - .vimrc\n    blocks:\n\
+             \x20     - id: aliases\n        source: snippets/aliases.sh\n        target: ~/.bashrc\n\
+             \x20     - id: greeting\n        source: snippets/greeting.sh\n        target: ~/.bashrc\n\
+             \x20       template: true\n        vars:\n          greeting: hello\n",
+        )
+        .unwrap();
+    home.child(".bashrc").write_str(CORP_BASHRC).unwrap();
+}
+
+fn bashrc(home: &assert_fs::TempDir) -> String {
+    std::fs::read_to_string(home.path().join(".bashrc")).unwrap()
+}
+
+#[test]
+#[serial]
+fn test_blocks_inserted_next_to_foreign_content() {
+    let home = common::setup_home("default");
+    setup(&home);
+
+    heimdal(&home, &["apply"])
+        .success()
+        .stdout(contains("Inserted block heimdal:aliases"));
+
+    let text = bashrc(&home);
+    assert!(text.starts_with(CORP_BASHRC));
+    assert!(
+        text.contains("# >>> heimdal:aliases >>>\nalias ll='ls -l'\n# <<< heimdal:aliases <<<\n")
+    );
+    assert!(text.contains("echo hello\n"));
+    assert!(!home.path().join(".bashrc").is_symlink());
+
+    heimdal(&home, &["apply"])
+        .success()
+        .stdout(contains("Up to date: block heimdal:aliases"));
+    assert_eq!(bashrc(&home), text);
+    heimdal(&home, &["status"])
+        .success()
+        .stdout(contains("Managed blocks: 2"));
+}
+
+#[test]
+#[serial]
+fn test_blocks_hand_edit_is_drift_and_blocks_apply() {
+    let home = common::setup_home("default");
+    setup(&home);
+    heimdal(&home, &["apply"]).success();
+
+    let edited = bashrc(&home).replace("alias ll='ls -l'", "alias ll='ls -la'");
+    std::fs::write(home.path().join(".bashrc"), edited).unwrap();
+
+    heimdal(&home, &["state", "check-drift"])
+        .failure()
+        .stderr(contains("Modified: block heimdal:aliases"));
+    heimdal(&home, &["apply"])
+        .failure()
+        .stderr(contains("edited by hand"));
+    assert!(bashrc(&home).contains("ls -la"));
+
+    heimdal(&home, &["apply", "--force"]).success();
+    assert!(!bashrc(&home).contains("ls -la"));
+    heimdal(&home, &["state", "check-drift"]).success();
+}
+
+#[test]
+#[serial]
+fn test_blocks_removed_by_unlink_and_when_dropped() {
+    let home = common::setup_home("default");
+    setup(&home);
+    heimdal(&home, &["apply"]).success();
+
+    // Dropping a block from the config takes it out on the next apply
+    let config = home.path().join(".dotfiles/heimdal.yaml");
+    let text = std::fs::read_to_string(&config).unwrap();
+    let (kept, _) = text.split_once("      - id: greeting").unwrap();
+    std::fs::write(&config, kept).unwrap();
+    heimdal(&home, &["apply"])
+        .success()
+        .stdout(contains("Pruned orphaned block heimdal:greeting"));
+    assert!(!bashrc(&home).contains("heimdal:greeting"));
+
+    heimdal(&home, &["unlink"])
+        .success()
+        .stdout(contains("Removed block heimdal:aliases"));
+    assert_eq!(bashrc(&home), CORP_BASHRC);
+}
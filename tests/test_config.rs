@@ -282,6 +282,302 @@ profiles:
     }
 }
 
+#[test]
+fn test_extends_list_linearizes_shared_ancestor_once() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+profiles:
+  base:
+    dotfiles: [.vimrc]
+  work:
+    extends: base
+    dotfiles: [.work]
+  linux:
+    extends: [base]
+    dotfiles: [.xinitrc]
+  dev:
+    extends: [work, linux]
+    dotfiles: [.zshrc]
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let order: Vec<String> = heimdal::config::linearize(&cfg, "dev")
+        .unwrap()
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+    assert_eq!(order, ["base", "work", "linux", "dev"]);
+
+    let resolved = heimdal::config::resolve_profile(&cfg, "dev").unwrap();
+    let sources: Vec<_> = resolved
+        .dotfiles
+        .iter()
+        .map(|d| match d {
+            heimdal::config::DotfileEntry::Simple(s) => s.as_str(),
+            heimdal::config::DotfileEntry::Mapped(m) => m.source.as_str(),
+        })
+        .collect();
+    assert_eq!(sources, [".vimrc", ".work", ".xinitrc", ".zshrc"]);
+}
+
+#[test]
+fn test_include_mixins_with_origins() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+mixins:
+  rust-dev:
+    dotfiles: [.cargo/config.toml]
+    packages:
+      common: [rustup]
+profiles:
+  base:
+    packages:
+      homebrew: [vim]
+  default:
+    extends: base
+    include: [rust-dev]
+    packages:
+      homebrew: [zsh]
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let (resolved, origins) = heimdal::config::resolve_with_origins(&cfg, "default").unwrap();
+    assert_eq!(resolved.packages.homebrew, ["vim", "zsh"]);
+    assert_eq!(origins.packages["homebrew"], ["base", "default"]);
+    assert_eq!(resolved.packages.common, ["rustup"]);
+    assert_eq!(origins.packages["common"], ["mixin rust-dev"]);
+    assert_eq!(origins.dotfiles, ["mixin rust-dev"]);
+    // A mixin cannot be activated on its own
+    assert!(heimdal::config::resolve_profile(&cfg, "rust-dev").is_err());
+}
+
+#[test]
+fn test_extends_inconsistent_order_rejected() {
+    // `y` must come after `x`, but `z` lists `x` last so it would win over `y`
+    let yaml = r#"
+heimdal:
+  version: "1"
+profiles:
+  x: {}
+  y:
+    extends: x
+  z:
+    extends: [y, x]
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let err = heimdal::config::resolve_profile(&cfg, "z").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Cannot order the parents of profile 'z'"));
+    let errors = heimdal::config::validate_config(&cfg);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+}
+
+#[test]
+fn test_hook_merge_strategies_and_refs() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+profiles:
+  base:
+    hooks:
+      pre_apply:
+        - name: setup
+          command: ./setup.sh
+      post_apply: ["echo base"]
+      pre_sync: ["echo base sync"]
+  child:
+    extends: base
+    hooks:
+      merge:
+        pre_apply: prepend
+        post_apply: append
+      pre_apply: ["echo first"]
+      post_apply: ["echo child"]
+      post_sync:
+        - ref: setup
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let (resolved, origins) = heimdal::config::resolve_with_origins(&cfg, "child").unwrap();
+    let commands = |hooks: &[heimdal::config::HookEntry]| -> Vec<String> {
+        hooks
+            .iter()
+            .map(|h| match h {
+                heimdal::config::HookEntry::Simple(c) => c.clone(),
+                heimdal::config::HookEntry::Full { command, .. } => command.clone(),
+                heimdal::config::HookEntry::Ref { name } => panic!("unresolved ref {}", name),
+            })
+            .collect()
+    };
+    assert_eq!(
+        commands(&resolved.hooks.pre_apply),
+        ["echo first", "./setup.sh"]
+    );
+    assert_eq!(origins.hooks.pre_apply, ["child", "base"]);
+    assert_eq!(
+        commands(&resolved.hooks.post_apply),
+        ["echo base", "echo child"]
+    );
+    // Phases without a strategy still replace
+    assert!(resolved.hooks.pre_sync.is_empty());
+    assert_eq!(commands(&resolved.hooks.post_sync), ["./setup.sh"]);
+    assert_eq!(origins.hooks.post_sync, ["base"]);
+}
+
+#[test]
+fn test_hook_ref_must_name_parent_hook() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+profiles:
+  base:
+    hooks:
+      post_apply: ["echo base"]
+  child:
+    extends: base
+    hooks:
+      merge: append
+      post_apply:
+        - name: own
+          command: echo own
+        - ref: own
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let err = heimdal::config::resolve_profile(&cfg, "child").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Hook ref 'own' in profile 'child'"));
+    let errors = heimdal::config::validate_config(&cfg);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+}
+
+#[test]
+fn test_child_entries_override_by_target_dest_and_name() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+packages:
+  common: [git]
+profiles:
+  base:
+    dotfiles:
+      - .gitconfig
+      - .vimrc
+    packages:
+      common: [git, ripgrep]
+    templates:
+      - src: templates/ssh.tmpl
+        dest: ~/.ssh/config
+  work:
+    extends: base
+    dotfiles:
+      - source: work/gitconfig
+        target: ~/.gitconfig
+    packages:
+      common: [ripgrep, jq]
+    templates:
+      - src: templates/ssh-work.tmpl
+        dest: ~/.ssh/config
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let (resolved, origins) = heimdal::config::resolve_with_origins(&cfg, "work").unwrap();
+    let sources: Vec<_> = resolved.dotfiles.iter().map(|d| d.source()).collect();
+    // The override keeps the inherited entry's place
+    assert_eq!(sources, ["work/gitconfig", ".vimrc"]);
+    assert_eq!(origins.dotfiles, ["work", "base"]);
+    assert_eq!(resolved.packages.common, ["git", "ripgrep", "jq"]);
+    assert_eq!(origins.packages["common"], ["base", "work", "work"]);
+    assert_eq!(resolved.templates.len(), 1);
+    assert_eq!(resolved.templates[0].src, "templates/ssh-work.tmpl");
+}
+
+#[test]
+fn test_conditional_alternatives_for_one_target_are_kept() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+profiles:
+  base:
+    dotfiles:
+      - source: linux/gitconfig
+        target: ~/.gitconfig
+        when: { os: [linux] }
+      - source: macos/gitconfig
+        target: ~/.gitconfig
+        when: { os: [macos] }
+    templates:
+      - src: templates/ssh-linux.tmpl
+        dest: ~/.ssh/config
+        when: { os: [linux] }
+      - src: templates/ssh-macos.tmpl
+        dest: ~/.ssh/config
+        when: { os: [macos] }
+  child:
+    extends: base
+  work:
+    extends: base
+    dotfiles:
+      - source: work/gitconfig
+        target: ~/.gitconfig
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    for name in ["base", "child"] {
+        let resolved = heimdal::config::resolve_profile(&cfg, name).unwrap();
+        let sources: Vec<_> = resolved.dotfiles.iter().map(|d| d.source()).collect();
+        assert_eq!(sources, ["linux/gitconfig", "macos/gitconfig"]);
+        let srcs: Vec<_> = resolved.templates.iter().map(|t| t.src.as_str()).collect();
+        assert_eq!(
+            srcs,
+            ["templates/ssh-linux.tmpl", "templates/ssh-macos.tmpl"]
+        );
+    }
+    // An override replaces every inherited alternative
+    let work = heimdal::config::resolve_profile(&cfg, "work").unwrap();
+    let sources: Vec<_> = work.dotfiles.iter().map(|d| d.source()).collect();
+    assert_eq!(sources, ["work/gitconfig"]);
+}
+
+#[test]
+fn test_exclude_drops_inherited_entries() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+packages:
+  homebrew: [mas]
+profiles:
+  base:
+    dotfiles:
+      - .vimrc
+      - source: tmux/tmux.conf
+        target: ~/.tmux.conf
+    packages:
+      common: [docker, vim]
+      apt: [docker]
+    templates:
+      - src: templates/a.tmpl
+        dest: ~/.a
+  server:
+    extends: base
+    exclude:
+      dotfiles: [~/.tmux.conf]
+      packages: [docker, mas]
+      templates: [templates/a.tmpl]
+    packages:
+      apt: [htop]
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let resolved = heimdal::config::resolve_profile(&cfg, "server").unwrap();
+    let sources: Vec<_> = resolved.dotfiles.iter().map(|d| d.source()).collect();
+    assert_eq!(sources, [".vimrc"]);
+    assert_eq!(resolved.packages.common, ["vim"]);
+    assert_eq!(resolved.packages.apt, ["htop"]);
+    assert!(resolved.packages.homebrew.is_empty());
+    assert!(resolved.templates.is_empty());
+    // The parent itself is unaffected
+    let base = heimdal::config::resolve_profile(&cfg, "base").unwrap();
+    assert_eq!(base.dotfiles.len(), 2);
+}
+
 #[test]
 fn test_validate_config_valid() {
     let tmp = TempDir::new().unwrap();
@@ -439,6 +735,203 @@ profiles:
         .failure();
 }
 
+#[test]
+fn test_validate_rejects_unknown_keys_with_location() {
+    let tmp = TempDir::new().unwrap();
+    let main = write_yaml(
+        &tmp,
+        "heimdal.yaml",
+        r#"heimdal:
+  version: "1"
+  stow_compat: true
+include: [work.yaml]
+profiles:
+  default:
+    dotfile: []
+"#,
+    );
+    write_yaml(
+        &tmp,
+        "work.yaml",
+        r#"profiles:
+  work:
+    templates:
+      - src: a.tmpl
+        dest: ~/a
+        whne: { os: [linux] }
+"#,
+    );
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["validate", "--config", main.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "heimdal.yaml:3:3: unknown key 'stow_compat' in heimdal",
+        ))
+        .stderr(predicate::str::contains(
+            "heimdal.yaml:7:5: unknown key 'dotfile' in profiles.default, did you mean 'dotfiles'?",
+        ))
+        .stderr(predicate::str::contains(
+            "work.yaml:6:9: unknown key 'whne' in profiles.work.templates[0], did you mean 'when'?",
+        ));
+}
+
+#[test]
+fn test_validate_checks_files_targets_and_variables() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join(".vimrc"), "").unwrap();
+    fs::write(
+        tmp.path().join("git.tmpl"),
+        "{{ name }} {{ email }} {{ home }} {{ secret:gh_token }}\n",
+    )
+    .unwrap();
+    let main = write_yaml(
+        &tmp,
+        "heimdal.yaml",
+        r#"heimdal:
+  version: "1"
+profiles:
+  base:
+    variables: { email: me@example.com }
+    dotfiles: [.vimrc, .zshrc]
+    templates:
+      - src: git.tmpl
+        dest: ~/.gitconfig
+        vars: { name: Me }
+  work:
+    extends: base
+    match: { hostname: "work-[" }
+    dotfiles:
+      - source: .vimrc
+        target: ~/.gitconfig
+    packages:
+      apt: [git, curl, git]
+    hooks:
+      post_apply:
+        - command: make
+          os: [mac]
+"#,
+    );
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["validate", "--config", main.to_str().unwrap()])
+        .env("HOME", tmp.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Profile 'base':\n  ✗ error: dotfile source '.zshrc' does not exist in the repo\n  \
+             ⚠ warning: template 'git.tmpl' uses secret 'gh_token', which is not in the secrets manifest\n\
+             Profile 'work':",
+        ))
+        .stderr(predicate::str::contains(
+            "error: match: hostname glob 'work-[' is invalid",
+        ))
+        .stderr(predicate::str::contains(
+            "error: post_apply hook 'make': os 'mac' is never matched",
+        ))
+        .stderr(predicate::str::contains(
+            "error: template 'git.tmpl' and dotfile '.vimrc' both deploy to ~/.gitconfig",
+        ))
+        .stderr(predicate::str::contains(
+            "warning: apt lists 'git' more than once",
+        ))
+        .stderr(predicate::str::contains("{{ name }}").not())
+        .stderr(predicate::str::contains("4 validation error(s) found"));
+}
+
+#[test]
+fn test_validate_allows_conditional_alternatives_for_one_target() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("a"), "").unwrap();
+    fs::write(tmp.path().join("b"), "").unwrap();
+    let main = write_yaml(
+        &tmp,
+        "heimdal.yaml",
+        r#"heimdal:
+  version: "1"
+profiles:
+  alternatives:
+    dotfiles:
+      - { source: a, target: ~/.gitconfig, when: { os: [linux] } }
+      - { source: b, target: ~/.gitconfig, when: { os: [macos] } }
+  clash:
+    dotfiles:
+      - { source: a, target: ~/.gitconfig }
+      - { source: b, target: ~/.gitconfig, when: { os: [macos] } }
+"#,
+    );
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["validate", "--config", main.to_str().unwrap()])
+        .env("HOME", tmp.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Profile 'clash':\n  ✗ error: dotfiles lists ~/.gitconfig more than once",
+        ))
+        .stderr(predicate::str::contains("Profile 'alternatives'").not())
+        .stderr(predicate::str::contains("1 validation error(s) found"));
+}
+
+#[test]
+fn test_validate_strict_fails_on_warnings() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("a.tmpl"), "{{ undefined_thing }}\n").unwrap();
+    let main = write_yaml(
+        &tmp,
+        "heimdal.yaml",
+        r#"heimdal:
+  version: "1"
+profiles:
+  default:
+    templates:
+      - src: a.tmpl
+        dest: ~/a
+"#,
+    );
+    let validate = |strict: bool| {
+        let mut cmd = Command::cargo_bin("heimdal").unwrap();
+        cmd.args(["validate", "--config", main.to_str().unwrap()]);
+        if strict {
+            cmd.arg("--strict");
+        }
+        cmd.env("HOME", tmp.path());
+        cmd.assert()
+    };
+
+    validate(false)
+        .success()
+        .stdout(predicate::str::contains("Config is valid (1 profile, 1 warning)"))
+        .stderr(predicate::str::contains(
+            "warning: template 'a.tmpl' uses {{ undefined_thing }}, which no variables: or vars: defines",
+        ));
+    validate(true).failure().stderr(predicate::str::contains(
+        "0 validation error(s) and 1 warning(s) found (--strict)",
+    ));
+}
+
+#[test]
+fn test_schema_command_prints_json_schema() {
+    let output = Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("schema")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(schema["title"], "heimdal.yaml");
+    assert_eq!(schema["additionalProperties"], false);
+    assert!(schema["properties"]["profiles"].is_object());
+    let profile = &schema["definitions"]["Profile"]["properties"];
+    for key in ["extends", "match", "dotfiles", "templates", "variables"] {
+        assert!(profile[key].is_object(), "Profile schema lacks {}", key);
+    }
+}
+
 #[test]
 fn test_validate_bad_yaml_exits_nonzero() {
     let tmp = TempDir::new().unwrap();
@@ -451,3 +944,201 @@ fn test_validate_bad_yaml_exits_nonzero() {
         .assert()
         .failure();
 }
+
+#[test]
+fn test_validate_config_block_ids() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+profiles:
+  default:
+    blocks:
+      - id: "has space"
+        source: a.sh
+        target: ~/.bashrc
+      - id: path
+        source: b.sh
+        target: ~/.bashrc
+      - id: path
+        source: c.sh
+        target: ~/.bashrc
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let errors = heimdal::config::validate_config(&cfg);
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0].contains("may only contain"));
+    assert!(errors[1].contains("appears twice"));
+}
+
+#[test]
+fn test_validate_config_mixins() {
+    let yaml = r#"
+heimdal:
+  version: "1"
+mixins:
+  m1:
+    include: [m2]
+  m2:
+    include: [m1]
+profiles:
+  default:
+    extends: [ghost]
+    include: [m1, nope]
+"#;
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(yaml).unwrap();
+    let errors = heimdal::config::validate_config(&cfg);
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors[0].contains("extends 'ghost'"));
+    assert!(errors[1].contains("includes mixin 'nope'"));
+    assert_eq!(
+        errors[2],
+        "Circular extends detected: mixin m1 → mixin m2 → mixin m1"
+    );
+}
+
+#[test]
+fn test_include_merges_globbed_files() {
+    let tmp = TempDir::new().unwrap();
+    fs::create_dir_all(tmp.path().join("profiles")).unwrap();
+    fs::create_dir_all(tmp.path().join("packages")).unwrap();
+    write_yaml(
+        &tmp,
+        "profiles/work.yaml",
+        "include: [../mixins.yaml]\nprofiles:\n  work:\n    extends: default\n",
+    );
+    write_yaml(
+        &tmp,
+        "profiles/home.yaml",
+        "profiles:\n  home:\n    dotfiles: [.zshrc]\n",
+    );
+    write_yaml(
+        &tmp,
+        "mixins.yaml",
+        "mixins:\n  rust:\n    dotfiles: [.cargo]\n",
+    );
+    write_yaml(
+        &tmp,
+        "packages/cli.yaml",
+        "packages:\n  common: [ripgrep]\n",
+    );
+    let path = write_yaml(
+        &tmp,
+        "heimdal.yaml",
+        r#"
+heimdal:
+  version: "1"
+include:
+  - profiles/*.yaml
+  - packages/*.yaml
+  - profiles/work.yaml
+packages:
+  common: [git]
+profiles:
+  default:
+    dotfiles: [.vimrc]
+"#,
+    );
+    let cfg = heimdal::config::load_config(&path).unwrap();
+    let mut names: Vec<&String> = cfg.profiles.keys().collect();
+    names.sort();
+    assert_eq!(names, ["default", "home", "work"]);
+    assert!(cfg.mixins.contains_key("rust"));
+    assert_eq!(cfg.packages.common, ["git", "ripgrep"]);
+    assert_eq!(
+        cfg.sources.profile_file("work"),
+        Some(tmp.path().join("profiles/work.yaml").as_path())
+    );
+    assert_eq!(cfg.sources.profile_file("default"), None);
+    assert_eq!(cfg.files(&path).len(), 5);
+
+    // Writing back an unchanged config leaves every file as it was
+    let before = fs::read_to_string(tmp.path().join("profiles/home.yaml")).unwrap();
+    heimdal::config::write_config(&path, &cfg).unwrap();
+    assert_eq!(
+        fs::read_to_string(tmp.path().join("profiles/home.yaml")).unwrap(),
+        before
+    );
+    let main = fs::read_to_string(&path).unwrap();
+    assert!(main.contains("profiles/*.yaml"));
+    assert!(!main.contains("ripgrep"));
+}
+
+#[test]
+fn test_include_errors_name_file_and_line() {
+    let tmp = TempDir::new().unwrap();
+    let path = write_yaml(
+        &tmp,
+        "heimdal.yaml",
+        "heimdal:\n  version: \"1\"\ninclude: [extra.yaml]\nprofiles:\n  default: {}\n",
+    );
+
+    let extra = write_yaml(&tmp, "extra.yaml", "profiles:\n  work:\n    dotfiles: 3\n");
+    let err = heimdal::config::load_config(&path).unwrap_err().to_string();
+    assert!(
+        err.contains(&format!(
+            "{}:3:15: profiles.work.dotfiles: invalid type",
+            extra.display()
+        )),
+        "{}",
+        err
+    );
+
+    fs::write(&extra, "heimdal:\n  version: \"1\"\n").unwrap();
+    let err = heimdal::config::load_config(&path).unwrap_err().to_string();
+    assert!(
+        err.contains("extra.yaml:1:1: unknown field `heimdal`"),
+        "{}",
+        err
+    );
+
+    fs::write(&extra, "profiles:\n  work: {}\n  default: {}\n").unwrap();
+    let err = heimdal::config::load_config(&path).unwrap_err().to_string();
+    assert!(
+        err.contains(&format!(
+            "{}:3: profile 'default' is already defined in {}",
+            extra.display(),
+            path.display()
+        )),
+        "{}",
+        err
+    );
+
+    fs::remove_file(&extra).unwrap();
+    let err = heimdal::config::load_config(&path).unwrap_err().to_string();
+    assert!(err.contains("extra.yaml does not exist"), "{}", err);
+}
+
+#[test]
+fn test_conditional_packages_inherit_and_match_host() {
+    let yaml = format!(
+        r#"
+heimdal:
+  version: "1"
+profiles:
+  base:
+    packages:
+      common: [git]
+      conditional:
+        - when: {{ arch: [{arch}] }}
+          common: [htop, jq]
+        - when: {{ os: [plan9] }}
+          common: [acme]
+  work:
+    extends: base
+    exclude:
+      packages: [jq]
+    packages:
+      conditional:
+        - when: {{ profile: [work] }}
+          common: [git, kubectl]
+"#,
+        arch = std::env::consts::ARCH
+    );
+    let cfg: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(&yaml).unwrap();
+    let work = heimdal::config::resolve_profile(&cfg, "work").unwrap();
+    assert_eq!(work.packages.conditional.len(), 3);
+    let here = work.packages.for_host("work");
+    assert_eq!(here.common, ["git", "htop", "kubectl"]);
+    assert!(here.conditional.is_empty());
+    assert_eq!(work.packages.for_host("other").common, ["git", "htop"]);
+}
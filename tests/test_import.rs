@@ -204,3 +204,24 @@ fn test_import_unknown_tool_fails() {
                 .or(predicate::str::contains("unknown")),
         );
 }
+
+#[test]
+fn test_import_honors_heimdalignore() {
+    let dir = stow_dotfiles();
+    dir.child(".heimdalignore").write_str(".zshrc\n").unwrap();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args([
+            "import",
+            "--path",
+            dir.path().to_str().unwrap(),
+            "--from",
+            "stow",
+            "--preview",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".vimrc"))
+        .stdout(predicate::str::contains(".zshrc").not())
+        .stdout(predicate::str::contains(".heimdalignore").not());
+}
@@ -175,6 +175,49 @@ profiles:
         .stderr(predicate::str::contains("nonexistent").or(predicate::str::contains("not found")));
 }
 
+#[test]
+#[serial]
+fn test_init_profile_auto_uses_match_blocks() {
+    let home = TempDir::new().unwrap();
+    let dotfiles = home.child(".dotfiles");
+    dotfiles.create_dir_all().unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(
+            r#"heimdal:
+  version: "1"
+profiles:
+  default:
+    dotfiles: []
+  laptop:
+    match:
+      env: { HEIMDAL_TEST_SITE: travel }
+"#,
+        )
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args([
+            "init",
+            "--repo",
+            "https://example.com/dotfiles.git",
+            "--profile",
+            "auto",
+            "--no-clone",
+        ])
+        .env("HOME", home.path())
+        .env("HEIMDAL_TEST_SITE", "travel")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("with profile 'laptop'"));
+
+    let state_path = home.path().join(".heimdal/state.json");
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&state_path).unwrap()).unwrap();
+    assert_eq!(state["active_profile"], "laptop");
+}
+
 #[test]
 #[serial]
 fn test_init_no_clone_missing_config_fails() {
@@ -0,0 +1,117 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+use serial_test::serial;
+
+mod common;
+
+const APP_SETTINGS: &str = "{\n    // written by the editor\n    \"window.zoomLevel\": 1,\n    \"editor.rulers\": [120],\n}\n";
+
+fn heimdal(home: &assert_fs::TempDir, args: &[&str]) -> assert_cmd::assert::Assert {
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(args)
+        .env("HOME", home.path())
+        .assert()
+}
+
+fn setup(home: &assert_fs::TempDir, arrays: &str) {
+    let dotfiles = home.child(".dotfiles");
+    dotfiles
+        .child("vscode/settings.json")
+        .write_str("{\"editor.fontSize\": 14, \"editor.rulers\": [80]}\n")
+        .unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(&format!(
+            "heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n      - .vimrc\n    merge:\n\
+             \x20     - source: vscode/settings.json\n        target: ~/.config/Code/User/settings.json\n\
+             \x20       arrays: {}\n",
+            arrays
+        ))
+        .unwrap();
+    home.child(".config/Code/User/settings.json")
+        .write_str(APP_SETTINGS)
+        .unwrap();
+}
+
+fn settings(home: &assert_fs::TempDir) -> serde_json::Value {
+    let text =
+        std::fs::read_to_string(home.path().join(".config/Code/User/settings.json")).unwrap();
+    serde_json::from_str(&text).unwrap()
+}
+
+#[test]
+#[serial]
+fn test_merge_keeps_app_keys_and_reports_changes() {
+    let home = common::setup_home("default");
+    setup(&home, "replace");
+
+    heimdal(&home, &["apply"])
+        .success()
+        .stdout(contains("Merged 2 key(s) into"))
+        .stdout(contains("+ editor.fontSize = 14"))
+        .stdout(contains("~ editor.rulers: [120] → [80]"));
+
+    let value = settings(&home);
+    assert_eq!(value["window.zoomLevel"], 1);
+    assert_eq!(value["editor.fontSize"], 14);
+    assert_eq!(value["editor.rulers"], serde_json::json!([80]));
+    assert!(!home
+        .path()
+        .join(".config/Code/User/settings.json")
+        .is_symlink());
+
+    heimdal(&home, &["apply"])
+        .success()
+        .stdout(contains("Up to date:"));
+}
+
+#[test]
+#[serial]
+fn test_merge_unique_arrays_keep_app_entries() {
+    let home = common::setup_home("default");
+    setup(&home, "unique");
+    heimdal(&home, &["apply"]).success();
+    assert_eq!(
+        settings(&home)["editor.rulers"],
+        serde_json::json!([120, 80])
+    );
+}
+
+#[test]
+#[serial]
+fn test_merge_drift_only_flags_owned_keys() {
+    let home = common::setup_home("default");
+    setup(&home, "replace");
+    heimdal(&home, &["apply"]).success();
+
+    // The app changing its own key is not drift
+    let path = home.path().join(".config/Code/User/settings.json");
+    let mut value = settings(&home);
+    value["window.zoomLevel"] = serde_json::json!(3);
+    std::fs::write(&path, serde_json::to_string_pretty(&value).unwrap()).unwrap();
+    heimdal(&home, &["state", "check-drift"]).success();
+
+    value["editor.fontSize"] = serde_json::json!(18);
+    std::fs::write(&path, serde_json::to_string_pretty(&value).unwrap()).unwrap();
+    heimdal(&home, &["state", "check-drift"])
+        .failure()
+        .stderr(contains("key 'editor.fontSize' is 18, heimdal sets 14"));
+}
+
+#[test]
+#[serial]
+fn test_merge_unlink_removes_only_our_keys() {
+    let home = common::setup_home("default");
+    setup(&home, "replace");
+    heimdal(&home, &["apply"]).success();
+
+    heimdal(&home, &["unlink"])
+        .success()
+        .stdout(contains("Removed 2 merged key(s)"));
+    let value = settings(&home);
+    assert_eq!(value["window.zoomLevel"], 1);
+    assert!(value.get("editor.fontSize").is_none());
+    assert!(value.get("editor.rulers").is_none());
+}
@@ -180,3 +180,114 @@ fn test_packages_remove_nonexistent_is_ok() {
         .assert()
         .success();
 }
+
+#[test]
+#[serial]
+fn test_write_back_goes_to_including_file() {
+    let home = setup_home_with_packages();
+    let dotfiles = home.child(".dotfiles");
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str("heimdal:\n  version: \"1\"\ninclude: [profiles/*.yaml]\nprofiles: {}\n")
+        .unwrap();
+    dotfiles
+        .child("profiles/default.yaml")
+        .write_str("profiles:\n  default:\n    packages:\n      apt: [git]\n")
+        .unwrap();
+    dotfiles
+        .child("profiles/other.yaml")
+        .write_str("# untouched\nprofiles:\n  other: {}\n")
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args([
+            "packages",
+            "add",
+            "ripgrep",
+            "--manager",
+            "apt",
+            "--no-install",
+        ])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["profile", "create", "laptop", "--extends", "default"])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+
+    let read = |name: &str| std::fs::read_to_string(dotfiles.path().join(name)).unwrap();
+    assert!(read("profiles/default.yaml").contains("ripgrep"));
+    assert_eq!(
+        read("profiles/other.yaml"),
+        "# untouched\nprofiles:\n  other: {}\n"
+    );
+    let main = read("heimdal.yaml");
+    assert!(main.contains("laptop"), "{}", main);
+    assert!(!main.contains("ripgrep"), "{}", main);
+    assert!(!main.contains("other"), "{}", main);
+}
+
+#[test]
+#[serial]
+fn test_packages_edits_keep_comments_and_layout() {
+    let home = setup_home_with_packages();
+    let config = home.child(".dotfiles/heimdal.yaml");
+    config
+        .write_str(
+            r#"# machine setup
+heimdal:
+  version: "1"
+
+profiles:
+  default:
+    packages:
+      # CLI tools
+      apt:
+        - git   # always
+        - vim
+    dotfiles: []
+"#,
+        )
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args([
+            "packages",
+            "add",
+            "ripgrep",
+            "--manager",
+            "apt",
+            "--no-install",
+        ])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["packages", "remove", "vim", "--no-uninstall"])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+
+    assert_eq!(
+        std::fs::read_to_string(config.path()).unwrap(),
+        r#"# machine setup
+heimdal:
+  version: "1"
+
+profiles:
+  default:
+    packages:
+      # CLI tools
+      apt:
+        - git   # always
+        - ripgrep
+    dotfiles: []
+"#
+    );
+}
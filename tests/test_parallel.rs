@@ -0,0 +1,124 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use serial_test::serial;
+use std::time::Instant;
+
+mod common;
+
+const DIRS: usize = 40;
+const FILES_PER_DIR: usize = 50;
+
+fn heimdal(home: &assert_fs::TempDir, args: &[&str]) -> String {
+    let out = Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(args)
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    String::from_utf8(out.get_output().stdout.clone()).unwrap()
+}
+
+/// A repo of DIRS × FILES_PER_DIR files plus a few templates, each file
+/// listed in the config the way a large monorepo-style setup would.
+fn generate_tree(home: &assert_fs::TempDir) {
+    let dotfiles = home.child(".dotfiles");
+    let mut config =
+        String::from("heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n");
+    for d in 0..DIRS {
+        for f in 0..FILES_PER_DIR {
+            let rel = format!(".tree/d{:02}/f{:03}", d, f);
+            dotfiles.child(&rel).write_str(&rel).unwrap();
+            config.push_str(&format!("      - {}\n", rel));
+        }
+    }
+    config.push_str("    templates:\n");
+    for t in 0..20 {
+        let rel = format!("templates/t{:02}.tmpl", t);
+        dotfiles.child(&rel).write_str("user {{ n }}\n").unwrap();
+        config.push_str(&format!(
+            "      - src: {}\n        dest: ~/.rendered/t{:02}\n        vars:\n          n: \"{}\"\n",
+            rel, t, t
+        ));
+    }
+    dotfiles.child("heimdal.yaml").write_str(&config).unwrap();
+}
+
+/// Not a strict benchmark: timings are printed (see `--nocapture`), while the
+/// assertions check that a parallel apply does the same work, in the same
+/// printed order, as a sequential one.
+#[test]
+#[serial]
+fn test_parallel_apply_large_tree_matches_sequential() {
+    let home = common::setup_home("default");
+    generate_tree(&home);
+
+    let started = Instant::now();
+    let sequential = heimdal(&home, &["apply", "--jobs", "1"]);
+    let sequential_time = started.elapsed();
+    heimdal(&home, &["unlink"]);
+    std::fs::remove_dir_all(home.path().join(".rendered")).unwrap();
+
+    let started = Instant::now();
+    let parallel = heimdal(&home, &["apply", "--jobs", "8"]);
+    let parallel_time = started.elapsed();
+
+    eprintln!(
+        "apply of {} files + 20 templates: --jobs 1 {:?}, --jobs 8 {:?}",
+        DIRS * FILES_PER_DIR,
+        sequential_time,
+        parallel_time
+    );
+    assert_eq!(sequential, parallel);
+
+    let linked: Vec<&str> = parallel
+        .lines()
+        .filter(|l| l.contains("Linked: "))
+        .collect();
+    assert_eq!(linked.len(), DIRS * FILES_PER_DIR);
+    assert!(linked[0].ends_with(".tree/d00/f000"));
+    assert!(linked[linked.len() - 1].ends_with(&format!(
+        ".tree/d{:02}/f{:03}",
+        DIRS - 1,
+        FILES_PER_DIR - 1
+    )));
+    for t in 0..20 {
+        let rendered =
+            std::fs::read_to_string(home.path().join(format!(".rendered/t{:02}", t))).unwrap();
+        assert_eq!(rendered, format!("user {}\n", t));
+    }
+    assert!(home.path().join(".tree/d17/f042").is_symlink());
+}
+
+#[test]
+#[serial]
+fn test_parallel_stow_walk_matches_sequential() {
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    for d in 0..DIRS {
+        for f in 0..5 {
+            dotfiles
+                .child(format!(".app{:02}/f{}", d, f))
+                .write_str("x")
+                .unwrap();
+        }
+    }
+    // Existing dirs make the walk descend and link file by file
+    for d in (0..DIRS).step_by(2) {
+        home.child(format!(".app{:02}/local", d))
+            .write_str("mine")
+            .unwrap();
+    }
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str("heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles: []\n")
+        .unwrap();
+
+    let sequential = heimdal(&home, &["apply", "--jobs", "1", "--dry-run"]);
+    let parallel = heimdal(&home, &["apply", "--jobs", "8", "--dry-run"]);
+    assert_eq!(sequential, parallel);
+
+    heimdal(&home, &["apply", "--jobs", "8"]);
+    assert!(home.path().join(".app01").is_symlink());
+    assert!(home.path().join(".app02/f3").is_symlink());
+    assert!(!home.path().join(".app02/local").is_symlink());
+}
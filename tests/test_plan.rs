@@ -0,0 +1,221 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use serial_test::serial;
+
+mod common;
+
+fn heimdal(home: &assert_fs::TempDir) -> Command {
+    let mut cmd = Command::cargo_bin("heimdal").unwrap();
+    cmd.env("HOME", home.path());
+    cmd
+}
+
+/// A profile with a link, a conflicting file and a template.
+fn setup() -> assert_fs::TempDir {
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    dotfiles
+        .child(".zshrc")
+        .write_str("# repo zshrc\n")
+        .unwrap();
+    dotfiles
+        .child("templates/gitconfig.tmpl")
+        .write_str("name = {{ name }}\n")
+        .unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(
+            "heimdal:\n  version: \"1\"\nprofiles:\n  default:\n    dotfiles:\n      - .vimrc\n      - .zshrc\n    templates:\n      - src: templates/gitconfig.tmpl\n        dest: ~/.gitconfig\n        vars:\n          name: Ada\n",
+        )
+        .unwrap();
+    home.child(".zshrc").write_str("# my own zshrc\n").unwrap();
+    home
+}
+
+#[test]
+#[serial]
+fn test_plan_lists_changes_without_making_them() {
+    let home = setup();
+    heimdal(&home)
+        .args(["plan", "--backup"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("3 change(s)"))
+        .stdout(predicate::str::contains("+ link"))
+        .stdout(predicate::str::contains("~ back up"))
+        .stdout(predicate::str::contains("+ render"));
+    assert!(!home.path().join(".vimrc").exists());
+    assert!(!home.path().join(".zshrc").is_symlink());
+    assert!(!home.path().join(".gitconfig").exists());
+
+    // Without a way to settle the conflict there is no plan
+    heimdal(&home)
+        .arg("plan")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--backup"));
+}
+
+#[test]
+#[serial]
+fn test_plan_json_has_typed_steps() {
+    let home = setup();
+    let out = heimdal(&home)
+        .args(["plan", "--force", "--json"])
+        .assert()
+        .success();
+    let plan: serde_json::Value = serde_json::from_slice(&out.get_output().stdout).unwrap();
+    let actions: Vec<&str> = plan["steps"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(actions, ["create_link", "replace_file", "render_template"]);
+    assert_eq!(plan["profile"], "default");
+    assert_eq!(plan["steps"][1]["before"]["state"], "file");
+    // Rendered output is only referenced by hash
+    assert!(!plan.to_string().contains("name = Ada"));
+}
+
+#[test]
+#[serial]
+fn test_apply_plan_runs_saved_plan() {
+    let home = setup();
+    let plan = home.path().join("plan.json");
+    heimdal(&home)
+        .args(["plan", "--backup", "--out"])
+        .arg(&plan)
+        .assert()
+        .success();
+
+    heimdal(&home)
+        .args(["apply", "--plan"])
+        .arg(&plan)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Applied 3 step(s)"));
+    assert!(home.path().join(".vimrc").is_symlink());
+    assert!(home.path().join(".zshrc").is_symlink());
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".gitconfig")).unwrap(),
+        "name = Ada\n"
+    );
+    let backups = std::fs::read_dir(home.path().join(".dotfiles/.heimdal/backups"))
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with(".zshrc"))
+        .count();
+    assert_eq!(backups, 1);
+
+    // Everything is now deployed, so the next plan is empty
+    heimdal(&home)
+        .arg("plan")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("no changes"));
+}
+
+#[test]
+#[serial]
+fn test_apply_plan_refuses_when_world_changed() {
+    let home = setup();
+    let plan = home.path().join("plan.json");
+    heimdal(&home)
+        .args(["plan", "--force", "--out"])
+        .arg(&plan)
+        .assert()
+        .success();
+
+    // A target changed after planning
+    home.child(".zshrc").write_str("# edited again\n").unwrap();
+    heimdal(&home)
+        .args(["apply", "--plan"])
+        .arg(&plan)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(".zshrc changed"))
+        .stderr(predicate::str::contains("heimdal plan"));
+    assert!(!home.path().join(".vimrc").exists());
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".zshrc")).unwrap(),
+        "# edited again\n"
+    );
+
+    // So did a template's variables
+    heimdal(&home)
+        .args(["plan", "--force", "--out"])
+        .arg(&plan)
+        .assert()
+        .success();
+    let config = home.path().join(".dotfiles/heimdal.yaml");
+    let text = std::fs::read_to_string(&config).unwrap();
+    std::fs::write(&config, text.replace("Ada", "Grace")).unwrap();
+    heimdal(&home)
+        .args(["apply", "--plan"])
+        .arg(&plan)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("heimdal.yaml was edited"));
+    assert!(!home.path().join(".gitconfig").exists());
+}
+
+#[test]
+#[serial]
+fn test_plan_expands_folded_dir_around_ignored_entries() {
+    let home = common::setup_home("default");
+    let dotfiles = home.child(".dotfiles");
+    dotfiles
+        .child(".config/nvim/init.vim")
+        .write_str("set nu\n")
+        .unwrap();
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str("heimdal:\n  version: \"1\"\nprofiles:\n  default: {}\n")
+        .unwrap();
+    heimdal(&home).arg("apply").assert().success();
+    assert!(home.path().join(".config").is_symlink());
+
+    // Ignoring something inside the folded dir means it has to be expanded
+    dotfiles
+        .child(".config/secret.local")
+        .write_str("token\n")
+        .unwrap();
+    dotfiles
+        .child(".heimdalignore")
+        .write_str("*.local\n")
+        .unwrap();
+    let plan = home.path().join("plan.json");
+    heimdal(&home)
+        .args(["plan", "--out"])
+        .arg(&plan)
+        .assert()
+        .success();
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&plan).unwrap()).unwrap();
+    let steps: Vec<(&str, &str)> = saved["steps"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| (s["action"].as_str().unwrap(), s["dest"].as_str().unwrap()))
+        .collect();
+    let config = home.path().join(".config");
+    let nvim = config.join("nvim");
+    assert_eq!(
+        steps,
+        [
+            ("expand", config.to_str().unwrap()),
+            ("create_link", nvim.to_str().unwrap()),
+        ]
+    );
+
+    heimdal(&home)
+        .args(["apply", "--plan"])
+        .arg(&plan)
+        .assert()
+        .success();
+    assert!(!config.is_symlink());
+    assert!(nvim.is_symlink());
+    assert!(!config.join("secret.local").exists());
+}
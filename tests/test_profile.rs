@@ -186,6 +186,68 @@ fn test_profile_switch_same_profile_is_ok() {
         .success();
 }
 
+#[test]
+#[serial]
+fn test_profile_switch_auto_picks_matching_profile() {
+    let home = setup_home_multi_profile();
+    home.child(".dotfiles/heimdal.yaml")
+        .write_str(
+            r#"heimdal:
+  version: "1"
+profiles:
+  default:
+    dotfiles: []
+  work:
+    match:
+      env: { HEIMDAL_TEST_SITE: "office-*" }
+  personal:
+    match:
+      any:
+        - env: { HEIMDAL_TEST_SITE: home }
+        - env: { HEIMDAL_TEST_ANYWHERE: "*" }
+"#,
+        )
+        .unwrap();
+    let heimdal = |args: &[&str], site: &str| {
+        let mut cmd = Command::cargo_bin("heimdal").unwrap();
+        cmd.args(args)
+            .env("HOME", home.path())
+            .env("HEIMDAL_TEST_SITE", site)
+            .env_remove("HEIMDAL_TEST_ANYWHERE");
+        cmd
+    };
+
+    heimdal(&["profile", "list"], "office-3")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("  work  (matches this machine)"))
+        .stdout(predicate::str::contains("  personal\n"));
+
+    heimdal(&["profile", "switch", "auto"], "office-3")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Profile 'work' matches this machine",
+        ));
+    let state_path = home.path().join(".heimdal/state.json");
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&state_path).unwrap()).unwrap();
+    assert_eq!(state["active_profile"], "work");
+
+    heimdal(&["profile", "switch", "auto"], "cafe")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No profile matches this machine"));
+
+    heimdal(&["profile", "switch", "auto"], "office-3")
+        .env("HEIMDAL_TEST_ANYWHERE", "1")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Profiles personal, work all match this machine",
+        ));
+}
+
 // ── profile show ──────────────────────────────────────────────────────────────
 
 #[test]
@@ -272,6 +334,135 @@ fn test_profile_create_with_extends() {
     assert!(content.contains("extends"), "extends not written");
 }
 
+#[test]
+#[serial]
+fn test_profile_create_keeps_rest_of_file() {
+    let home = setup_home_multi_profile();
+    let config = home.child(".dotfiles/heimdal.yaml");
+    let original = "heimdal:\n  version: \"1\"\nprofiles:\n  # everyday\n  default:\n    dotfiles: []\n  work:\n    extends: default   # shared base\n    dotfiles: []\n";
+    config.write_str(original).unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["profile", "create", "child", "--extends", "work"])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+
+    // The new profile is added after the others, without default fields
+    assert_eq!(
+        std::fs::read_to_string(config.path()).unwrap(),
+        format!("{}  child:\n    extends: work\n", original)
+    );
+}
+
+#[test]
+#[serial]
+fn test_profile_create_with_several_parents() {
+    let home = setup_home_multi_profile();
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["profile", "create", "child", "--extends", "work"])
+        .args(["--extends", "personal"])
+        .env("HOME", home.path())
+        .assert()
+        .success();
+
+    let content = std::fs::read_to_string(home.path().join(".dotfiles/heimdal.yaml")).unwrap();
+    let config: heimdal::config::HeimdalConfig = serde_yaml_ng::from_str(&content).unwrap();
+    assert_eq!(config.profiles["child"].extends, ["work", "personal"]);
+    // A single parent is still written as a plain name
+    assert!(content.contains("extends: default"));
+}
+
+#[test]
+#[serial]
+fn test_profile_show_resolved_annotates_origin() {
+    let home = setup_home_multi_profile();
+    home.child(".dotfiles/heimdal.yaml")
+        .write_str(
+            r#"heimdal:
+  version: "1"
+mixins:
+  rust:
+    packages:
+      common: [rustup]
+profiles:
+  default:
+    dotfiles: [.vimrc]
+  personal:
+    dotfiles: [.gitconfig]
+  work:
+    extends: [default, personal]
+    include: [rust]
+    dotfiles: [.work]
+"#,
+        )
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["profile", "show", "work", "--resolved"])
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Resolution order: default → personal → mixin rust → work",
+        ))
+        .stdout(predicate::str::contains(".vimrc  (from default)"))
+        .stdout(predicate::str::contains(".gitconfig  (from personal)"))
+        .stdout(predicate::str::contains("rustup (from mixin rust)"))
+        .stdout(predicate::str::contains("- .work\n"));
+}
+
+#[test]
+#[serial]
+fn test_profile_show_resolved_hooks_with_origin() {
+    let home = setup_home_multi_profile();
+    home.child(".dotfiles/heimdal.yaml")
+        .write_str(
+            r#"heimdal:
+  version: "1"
+profiles:
+  default:
+    hooks:
+      pre_apply:
+        - name: brew-update
+          command: brew update
+  work:
+    extends: default
+    hooks:
+      merge: append
+      pre_apply: ["echo work"]
+      post_apply:
+        - ref: brew-update
+  personal: {}
+"#,
+        )
+        .unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["profile", "show", "work", "--resolved"])
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "pre_apply: [brew-update] brew update  (from default)\n  pre_apply: echo work\n",
+        ))
+        .stdout(predicate::str::contains(
+            "post_apply: [brew-update] brew update  (from default)",
+        ));
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["profile", "show", "work"])
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("post_apply: ref brew-update"));
+}
+
 #[test]
 #[serial]
 fn test_profile_create_duplicate_fails() {
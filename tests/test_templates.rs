@@ -127,3 +127,69 @@ fn test_template_system_vars_available() {
     assert!(sys_vars.contains_key("os"), "missing os");
     assert!(sys_vars.contains_key("home"), "missing home");
 }
+
+#[test]
+#[serial]
+fn test_variables_layer_by_precedence() {
+    let home = setup_home_with_template();
+    let dotfiles = home.child(".dotfiles");
+    dotfiles
+        .child("heimdal.yaml")
+        .write_str(
+            r#"heimdal:
+  version: "1"
+variables:
+  name: "Shared Name"
+  email: "shared@example.com"
+  editor: vim
+profiles:
+  base:
+    variables:
+      email: "base@example.com"
+  default:
+    extends: base
+    variables:
+      editor: nvim
+    templates:
+      - src: .gitconfig.tmpl
+        dest: ~/.gitconfig
+        vars:
+          name: "Template Name"
+"#,
+        )
+        .unwrap();
+    dotfiles
+        .child(".gitconfig.tmpl")
+        .write_str("{{ name }} <{{ email }}> {{ editor }}\n")
+        .unwrap();
+    let heimdal = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("heimdal").unwrap();
+        cmd.args(args).env("HOME", home.path());
+        cmd
+    };
+
+    heimdal(&["template", "preview", ".gitconfig.tmpl"])
+        .assert()
+        .success()
+        .stdout("Template Name <base@example.com> nvim\n");
+
+    home.child(".heimdal/variables.yaml")
+        .write_str("email: me@this-machine\n")
+        .unwrap();
+    heimdal(&["template", "preview", ".gitconfig.tmpl"])
+        .assert()
+        .success()
+        .stdout("Template Name <me@this-machine> nvim\n");
+
+    heimdal(&["template", "variables"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "  email: base@example.com  (from base)  (overridden locally)",
+        ))
+        .stdout(predicate::str::contains("  editor: nvim\n"))
+        .stdout(predicate::str::contains(
+            "  name: Shared Name  (from top-level variables)",
+        ))
+        .stdout(predicate::str::contains("  email: me@this-machine"));
+}
@@ -0,0 +1,97 @@
+use assert_cmd::Command;
+use predicates::str::contains;
+use serial_test::serial;
+
+mod common;
+
+fn apply(home: &assert_fs::TempDir, args: &[&str]) {
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("apply")
+        .args(args)
+        .env("HOME", home.path())
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_unlink_help() {
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["unlink", "--help"])
+        .assert()
+        .success()
+        .stdout(contains("dry-run"))
+        .stdout(contains("restore"));
+}
+
+#[test]
+#[serial]
+fn test_unlink_removes_symlinks() {
+    let home = common::setup_home("default");
+    apply(&home, &[]);
+    assert!(home.path().join(".vimrc").is_symlink());
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("unlink")
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(contains("Unlinked"));
+    assert!(!home.path().join(".vimrc").exists());
+    assert!(home.path().join(".dotfiles").join(".vimrc").exists());
+}
+
+#[test]
+#[serial]
+fn test_unlink_dry_run_keeps_symlinks() {
+    let home = common::setup_home("default");
+    apply(&home, &[]);
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["unlink", "--dry-run"])
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(contains("[preview]"));
+    assert!(home.path().join(".vimrc").is_symlink());
+}
+
+#[test]
+#[serial]
+fn test_unlink_restore_brings_back_backup() {
+    let home = common::setup_home("default");
+    std::fs::write(home.path().join(".vimrc"), "original content").unwrap();
+    apply(&home, &["--backup"]);
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .args(["unlink", "--restore"])
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(contains("Restored"));
+    let vimrc = home.path().join(".vimrc");
+    assert!(!vimrc.is_symlink());
+    assert_eq!(std::fs::read_to_string(vimrc).unwrap(), "original content");
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_unlink_ignores_foreign_symlinks() {
+    let home = common::setup_home("default");
+    let elsewhere = home.path().join("elsewhere");
+    std::fs::write(&elsewhere, "not ours").unwrap();
+    std::os::unix::fs::symlink(&elsewhere, home.path().join(".vimrc")).unwrap();
+
+    Command::cargo_bin("heimdal")
+        .unwrap()
+        .arg("unlink")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert!(home.path().join(".vimrc").is_symlink());
+}